@@ -1,6 +1,11 @@
 use clap::{arg, command, value_parser, Arg, ArgAction, ArgMatches, ColorChoice, Command};
 use std::path::PathBuf;
 
+use crate::deploy::DEFAULT_REMOTE_DIR;
+use crate::dotwalker::WalkOptions;
+use crate::matcher::SectionMatcher;
+use crate::picker;
+
 pub fn get_vec_args<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
     let sections = matches
         .get_many::<String>(name)
@@ -10,13 +15,135 @@ pub fn get_vec_args<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
     return sections;
 }
 
+// sections selected by --section names or globs and --section-regex
+pub fn get_section_matcher(matches: &ArgMatches) -> Option<SectionMatcher> {
+    match SectionMatcher::new(
+        &get_vec_args(matches, "section"),
+        &get_vec_args(matches, "section-regex"),
+    ) {
+        Ok(matcher) => Some(matcher),
+        Err(e) => {
+            eprintln!("invalid section pattern: {}", e);
+            None
+        }
+    }
+}
+
+// like get_section_matcher, but let the user pick from names
+// if no section was given on the command line
+pub fn pick_section_matcher(matches: &ArgMatches, names: &[&str]) -> Option<SectionMatcher> {
+    let matcher = get_section_matcher(matches)?;
+    if !matcher.is_empty() {
+        return Some(matcher);
+    }
+    if names.is_empty() {
+        eprintln!("file has no named sections");
+        return None;
+    }
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    match picker::pick("section", &names) {
+        Some(picked) => Some(SectionMatcher::exact(&picked)),
+        None => {
+            eprintln!("no section given");
+            None
+        }
+    }
+}
+
+// always and never override the detection of colored,
+// which honors NO_COLOR and only colors output to a terminal
+pub fn set_color_choice(matches: &ArgMatches) {
+    match matches.get_one::<String>("color").map(|c| c.as_str()) {
+        Some("always") => colored::control::set_override(true),
+        Some("never") => colored::control::set_override(false),
+        _ => {}
+    }
+}
+
+pub fn get_walk_options(matches: &ArgMatches) -> WalkOptions {
+    WalkOptions {
+        hidden: matches.get_flag("hidden"),
+        no_ignore: matches.get_flag("no-ignore"),
+        only: get_vec_args(matches, "include")
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        exclude: get_vec_args(matches, "exclude")
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        max_depth: matches.get_one::<usize>("max-depth").copied(),
+        follow_symlinks: matches.get_flag("follow-symlinks"),
+    }
+}
+
+// arguments controlling which files of a directory are visited
+fn walk_args(command: Command) -> Command {
+    command
+        .arg(
+            arg!(--hidden "also search hidden directories")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"no-ignore" "do not respect .gitignore and .imosidignore files")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--include <GLOB> "only visit files matching glob")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(--exclude <GLOB> "skip files matching glob")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            arg!(--"max-depth" <N> "do not descend more than N directories")
+                .required(false)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"follow-symlinks" "walk into symlinked directories")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+}
+
 pub fn build_app() -> Command {
     command!()
-        .color(ColorChoice::Always)
+        .color(ColorChoice::Auto)
         .subcommand_required(true)
         .arg_required_else_help(true)
         .about("instant manager of sections in dotfiles")
         .author("paperbenni <paperbenni@gmail.com>")
+        .arg(
+            arg!(-v --verbose "print more details, repeat for even more")
+                .global(true)
+                .action(ArgAction::Count),
+        )
+        .arg(
+            arg!(-q --quiet "only print errors")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--color <WHEN> "when to color output")
+                .required(false)
+                .global(true)
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto"),
+        )
+        .arg(
+            arg!(--"log-file" <FILE> "also append messages to this file")
+                .required(false)
+                .global(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
         .subcommand(
             Command::new("test")
                 .about("testing stuff")
@@ -28,20 +155,49 @@ pub fn build_app() -> Command {
                 .arg(
                     Arg::new("file")
                         .value_parser(value_parser!(PathBuf))
-                        .required(true)
-                        .help("file to compile"),
+                        .required_unless_present("stdin")
+                        .num_args(1..)
+                        .help("files or directories to compile"),
                 )
                 .arg(
                     arg!(-m --metafile "use meta file")
                         .required(false)
                         .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--commit "commit the compiled file if it is in a git repository")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--stdin "read the file from stdin and write the result to stdout")
+                        .required(false)
+                        .conflicts_with_all(["file", "metafile", "commit"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--stdout "print the compiled file instead of writing it")
+                        .required(false)
+                        .conflicts_with_all(["metafile", "commit"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"comment-sign" <SIGN> "comment syntax of stdin, detected if not given")
+                        .required(false)
+                        .requires("stdin")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--strict "refuse files with malformed imosid comments")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
             Command::new("update")
                 .about("update sections from sources")
                 .arg(
-                    arg!(-f --file "file to update")
+                    arg!(-f --file <FILE> "file to update")
                         .required(true)
                         .value_parser(value_parser!(PathBuf)),
                 )
@@ -51,24 +207,92 @@ pub fn build_app() -> Command {
                         .action(ArgAction::SetTrue),
                 )
                 .arg(
-                    arg!(-s --section "only update section, default is all")
+                    arg!(-s --section <SECTION> "only update section or sections matching glob, default is all")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"section-regex" <REGEX> "only update sections matching regex")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"require-clean" "refuse to run if the config repository has uncommitted changes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--offline "only use remote sources fetched before")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--locked "only use the source versions recorded in imosid.lock")
                         .required(false)
-                        .action(ArgAction::Append),
+                        .action(ArgAction::SetTrue),
                 ),
         )
+        .subcommand(walk_args(
+            Command::new("list")
+                .about("list managed files and their sections")
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .help("file or directory to list")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-s --section <SECTION> "only list sections with this name")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-t --target <TARGET> "only list entries whose target contains this")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-m --modified "only list modified entries")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        ))
         .subcommand(
             Command::new("query")
                 .about("print section from file")
                 .arg(
-                    arg!(--file "file to search through")
+                    arg!(--file <FILE> "file to search through")
                         .required(true)
                         .value_parser(value_parser!(PathBuf)),
                 )
                 .arg(
-                    arg!(--section "section to print")
-                        .required(true)
+                    arg!(--section <SECTION> "section or glob of sections to print, picked interactively if missing")
+                        .required(false)
                         .action(ArgAction::Append)
                         .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"section-regex" <REGEX> "print sections matching regex")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--all "print every named section")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--raw "only print section content without marker comments")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--field <FIELD> "print a field of a file managed by a metafile")
+                        .required(false)
+                        .value_parser(["target", "source", "hash", "parent", "profile"]),
                 ),
         )
         .subcommand(
@@ -77,20 +301,185 @@ pub fn build_app() -> Command {
                 .arg(
                     Arg::new("file")
                         .required(true)
-                        .help("file to get info for")
+                        .num_args(1..)
+                        .help("files or managed directories to get info for")
                         .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--diff "show what changed in modified sections")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
+            Command::new("hash")
+                .about("print the hash imosid computes for a file or one of its sections")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to hash")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-s --section <SECTION> "only hash this section or region")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--algorithm <ALGORITHM> "hash algorithm, default is the one already recorded for the file")
+                        .required(false)
+                        .value_parser(["sha256", "blake3"]),
+                ),
+        )
+        .subcommand(walk_args(
             Command::new("apply")
                 .about("apply source to target marked in the file")
                 .arg(
                     Arg::new("file")
-                        .help("file or directory to apply")
+                        .help("files or directories to apply")
                         .required(true)
+                        .num_args(1..)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--profile <PROFILE> "only apply files and sections of this profile")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"add-missing" "add source sections missing from the target")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"prune-sections" "remove target sections deleted from the source")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--link "symlink whole file targets to the source")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--strict "refuse to apply files with malformed imosid comments")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--force "apply files which define a section more than once")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--sudo "write targets only root can write through sudo")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"skip-root" "skip targets only root can write instead of failing")
+                        .required(false)
+                        .conflicts_with("sudo")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--readonly <POLICY> "what to do with read-only targets, chmod writes them anyway")
+                        .required(false)
+                        .value_parser(["skip", "error", "chmod"])
+                        .default_value("error"),
+                )
+                .arg(
+                    arg!(--"preserve-mtime" "keep modification time of existing targets")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"no-cache" "apply files even if they did not change since the last run")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"changed-only" "only apply files which changed since the last run, even if their targets changed")
+                        .required(false)
+                        .conflicts_with("no-cache")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"require-clean" "refuse to run if the config repository has uncommitted changes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--merge "merge source changes into target sections with local edits")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"only-section" <NAME> "only apply sections with this name or glob")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"skip-section" <NAME> "do not apply sections with this name or glob")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"only-glob" <GLOB> "only apply files in the directory matching glob")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--conflict <MODE> "what to do with sections changed in source and target")
+                        .required(false)
+                        .value_parser(["skip", "markers"])
+                        .default_value("skip"),
+                )
+                .arg(
+                    arg!(--notify "send a desktop notification if targets changed")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"no-notify" "do not send a notification even if the config enables it")
+                        .required(false)
+                        .conflicts_with("notify")
+                        .action(ArgAction::SetTrue),
+                ),
+        ))
+        .subcommand(
+            Command::new("merge")
+                .about("interactively reconcile a modified target with its source")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .help("target file to merge")
                         .value_parser(value_parser!(PathBuf)),
                 ),
         )
+        .subcommand(
+            Command::new("sync")
+                .about("copy locally modified sections of a target back into their source")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .help("target file with local edits")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-n --"dry-run" "only show what would be copied to the source")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--commit "commit the source to the config repository")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new("delete")
                 .about("delete section from file")
@@ -106,20 +495,541 @@ pub fn build_app() -> Command {
                         .action(ArgAction::SetTrue),
                 )
                 .arg(
-                    arg!(-s --section "section to delete")
+                    arg!(-s --section <SECTION> "section or glob of sections to delete, picked interactively if missing")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"section-regex" <REGEX> "delete sections matching regex")
+                        .required(false)
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("edit a section of a file in $EDITOR")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file containing the section")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-s --section <SECTION> "section to edit, picked interactively if missing")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("section")
+                .about("manage sections of a file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("wrap existing lines into a new section")
+                        .arg(
+                            Arg::new("file")
+                                .required(true)
+                                .help("file to add section to")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(--name <SECTION> "name of the new section")
+                                .required(true)
+                                .value_parser(value_parser!(String)),
+                        )
+                        .arg(
+                            arg!(--"from-line" <LINE> "first line of the section")
+                                .required(false)
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            arg!(--"to-line" <LINE> "last line of the section")
+                                .required(false)
+                                .value_parser(value_parser!(u32)),
+                        )
+                        .arg(
+                            arg!(--"around-match" <REGEX> "wrap from first to last matching line")
+                                .required(false)
+                                .conflicts_with_all(["from-line", "to-line"])
+                                .value_parser(value_parser!(String)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("create a new config directory")
+                .arg(
+                    Arg::new("directory")
+                        .required(false)
+                        .default_value(".")
+                        .help("directory to create")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--manifest "create an imosid.toml manifest")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--git "initialize a git repository")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--scan "suggest files from ~/.config to adopt")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("adopt")
+                .about("take over an existing file into a config directory")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .help("existing file to manage")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--into <DIRECTORY> "config directory to copy the file into")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--name <SECTION> "name of the section wrapping the file")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-m --metafile "use meta file")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("repair")
+                .about("close unterminated sections, add missing hashes and remove stray markers")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to repair")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-y --"assume-yes" "make every repair without asking")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("rename")
+                .about("rename section in file")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to rename section in")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--from <SECTION> "current section name")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--to <SECTION> "new section name")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--propagate "also rename the section in the target file")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("set target, permissions or source of a file or section")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to change")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-s --section <SECTION> "change this section instead of the whole file")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--target <PATH> "file to apply to")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--permissions <MODE> "permissions of the target, like 644 or 600:user:group")
+                        .required(false)
+                        .conflicts_with("section")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--source <SOURCE> "file or url to update from")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("env")
+                .about("print resolved paths and settings")
+                .arg(
+                    arg!(--format <FORMAT> "output format")
+                        .required(false)
+                        .value_parser(["shell", "json"])
+                        .default_value("shell"),
+                ),
+        )
+        .subcommand(walk_args(
+            Command::new("status")
+                .about("show state of files in a config directory")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to inspect")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(walk_args(
+            Command::new("tui")
+                .about("interactively browse and manage the files of a config directory")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to browse")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(walk_args(
+            Command::new("export")
+                .about("bundle managed files of a config directory into an archive")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to export")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-o --output <FILE> "archive to write")
+                        .required(false)
+                        .default_value("dots.tar.gz")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(
+            Command::new("serve")
+                .about("serve a config directory read-only over http for sources on other machines")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to serve")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-p --port <PORT> "port to listen on")
+                        .required(false)
+                        .default_value("8090")
+                        .value_parser(value_parser!(u16)),
+                )
+                .arg(
+                    arg!(--bind <ADDRESS> "address to listen on")
+                        .required(false)
+                        .default_value("0.0.0.0")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("systemd")
+                .about("apply a config directory periodically with a systemd user timer")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("install")
+                        .about("write and start a user service and timer running imosid apply")
+                        .arg(
+                            Arg::new("directory")
+                                .required(false)
+                                .help("config directory to apply, defaults to the config source")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(--interval <INTERVAL> "time between runs like 30m, 1h or 1d")
+                                .required(false)
+                                .default_value("1h")
+                                .value_parser(value_parser!(String)),
+                        )
+                        .arg(
+                            arg!(--print "print the units instead of installing them")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("apply-args")
+                                .help("arguments for imosid apply, after --")
+                                .last(true)
+                                .num_args(0..)
+                                .value_parser(value_parser!(String)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("uninstall").about("stop and remove the service and timer"),
+                ),
+        )
+        .subcommand(walk_args(
+            Command::new("deploy")
+                .about("apply a config directory on other machines over ssh")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to deploy")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--host <HOST> "ssh destination like user@machine, can be repeated")
                         .required(true)
                         .action(ArgAction::Append)
-                        .value_parser(value_parser!(String))
-                        .action(ArgAction::Append),
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"remote-dir" <DIRECTORY> "directory on the hosts relative to their home, replaced by every deploy")
+                        .required(false)
+                        .default_value(DEFAULT_REMOTE_DIR)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"remote-imosid" <COMMAND> "imosid command on the hosts")
+                        .required(false)
+                        .default_value("imosid")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("apply-args")
+                        .help("arguments for imosid apply on the hosts, after --")
+                        .last(true)
+                        .num_args(0..)
+                        .value_parser(value_parser!(String)),
+                ),
+        ))
+        .subcommand(
+            Command::new("import")
+                .about("unpack an exported archive and apply it")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .subcommand(
+                    Command::new("stow")
+                        .about("convert a GNU stow directory into imosid managed files")
+                        .arg(
+                            Arg::new("directory")
+                                .required(true)
+                                .help("stow directory containing packages")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(-t --target <DIRECTORY> "stow target directory, defaults to the parent of the stow directory")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("chezmoi")
+                        .about("convert a chezmoi source directory into imosid managed files")
+                        .arg(
+                            Arg::new("directory")
+                                .required(true)
+                                .help("chezmoi source directory")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(-t --target <DIRECTORY> "target directory, defaults to the home directory")
+                                .required(false)
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("dotbot")
+                        .about("convert the files linked by a dotbot config into imosid managed files")
+                        .arg(
+                            Arg::new("config")
+                                .required(true)
+                                .help("dotbot config, usually install.conf.yaml")
+                                .value_parser(value_parser!(PathBuf)),
+                        )
+                        .arg(
+                            arg!(--metafile "manage all files through metafiles")
+                                .required(false)
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .arg(
+                    Arg::new("archive")
+                        .required(true)
+                        .help("archive created by imosid export")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--into <DIRECTORY> "directory to unpack into, defaults to the config source")
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--"no-apply" "only unpack the archive")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("list targets whose source is gone from the config directory")
+                .alias("prune")
+                .arg(
+                    arg!(--delete "delete orphaned targets without local changes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
+            Command::new("log")
+                .about("show when files were applied or compiled and what changed")
+                .arg(
+                    Arg::new("target")
+                        .required(false)
+                        .help("only show entries about this file")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(-n --limit <N> "only show the N most recent entries")
+                        .required(false)
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("restore a target to the content of an earlier apply")
+                .arg(
+                    Arg::new("target")
+                        .required(true)
+                        .help("file to roll back")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--to <ID> "id from imosid log or date like 2023-06-01 12:00, defaults to the apply before the last one")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--force "also overwrite sections with local changes")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("remove old cached sources and unused snapshots")
+                .arg(
+                    arg!(--"dry-run" "only list what would be removed")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"max-age" <DAYS> "remove entries older than DAYS, 0 keeps all")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"max-count" <N> "keep at most N entries of each kind, 0 keeps all")
+                        .required(false)
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"max-size" <SIZE> "keep at most SIZE like 100M of each kind, 0 keeps all")
+                        .required(false)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(walk_args(
+            Command::new("fetch")
+                .about("download remote sources used in a directory into the cache")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to fetch sources for")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(walk_args(
+            Command::new("verify")
+                .about("check that every target matches its source")
+                .arg(
+                    Arg::new("directory")
+                        .required(true)
+                        .help("config directory to verify")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(walk_args(
+            Command::new("migrate")
+                .about("upgrade comments and metafiles written by older imosid versions")
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .num_args(1..)
+                        .help("files or directories to migrate")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--check "only list files which need to be migrated")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                ),
+        ))
+        .subcommand(walk_args(
+            Command::new("lint")
+                .about("report malformed imosid comments and broken sections")
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .num_args(1..)
+                        .help("files or directories to lint")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+        .subcommand(walk_args(
             Command::new("check")
                 .about("check directory for modified files")
                 .arg(
-                    arg!(--directory "directory to check")
+                    arg!(--directory <DIR> "directory to check")
                         .required(true)
                         .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--"fail-on" <KIND> "exit with an error if files of this kind are found")
+                        .required(false)
+                        .value_parser(["modified", "unmanaged", "outdated", "any"])
+                        .default_value("any"),
+                )
+                .arg(
+                    arg!(--"no-cache" "parse every file instead of using cached results")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--targets "also report targets which are out of date with their source")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--strict "fail on files with malformed imosid comments, implies --no-cache")
+                        .required(false)
+                        .action(ArgAction::SetTrue),
                 ),
-        )
+        ))
 }
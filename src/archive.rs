@@ -0,0 +1,106 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use colored::Colorize;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use toml::Value;
+
+use crate::built_info;
+use crate::dotwalker::{walk_dotfiles, WalkOptions};
+use crate::init::{IGNORE_NAME, MANIFEST_NAME};
+
+// describes the contents of an exported archive
+pub const EXPORT_MANIFEST_NAME: &str = "imosid-export.toml";
+
+// bundle all managed files of a config directory into a tar.gz archive
+pub fn export_archive(dir: &Path, walkoptions: &WalkOptions, output: &Path) -> io::Result<usize> {
+    let dir = dir.canonicalize()?;
+    let encoder = GzEncoder::new(File::create(output)?, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    let mut files = Vec::new();
+    for dotfile in walk_dotfiles(&dir, walkoptions) {
+        if !dotfile.is_managed() {
+            continue;
+        }
+        let path = PathBuf::from(&dotfile.filename);
+        let relative = match path.strip_prefix(&dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => continue,
+        };
+        builder.append_path_with_name(&path, &relative)?;
+        if dotfile.metafile.is_some() {
+            builder.append_path_with_name(
+                format!("{}.imosid.toml", dotfile.filename),
+                format!("{}.imosid.toml", relative.display()),
+            )?;
+        }
+
+        let mut entry = toml::map::Map::new();
+        entry.insert("path".into(), Value::String(relative.display().to_string()));
+        if let Some(target) = &dotfile.targetfile {
+            entry.insert("target".into(), Value::String(target.clone()));
+        }
+        files.push(Value::Table(entry));
+    }
+
+    // repository settings travel with the files
+    for name in [MANIFEST_NAME, IGNORE_NAME] {
+        let path = dir.join(name);
+        if path.is_file() {
+            builder.append_path_with_name(&path, name)?;
+        }
+    }
+
+    let count = files.len();
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let mut manifest = toml::map::Map::new();
+    manifest.insert(
+        "imosidversion".into(),
+        Value::String(built_info::PKG_VERSION.to_string()),
+    );
+    manifest.insert("created".into(), Value::Integer(created));
+    manifest.insert("file".into(), Value::Array(files));
+    let manifest = toml::to_string(&Value::Table(manifest)).expect("manifest is valid toml");
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(created as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, EXPORT_MANIFEST_NAME, manifest.as_bytes())?;
+
+    builder.into_inner()?.finish()?;
+    Ok(count)
+}
+
+// unpack an exported archive into a config directory
+pub fn import_archive(archive: &Path, into: &Path) -> io::Result<()> {
+    fs::create_dir_all(into)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(archive)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        if path == Path::new(EXPORT_MANIFEST_NAME) {
+            continue;
+        }
+        if into.join(&path).exists() {
+            println!(
+                "{} {}",
+                path.display().to_string().yellow(),
+                "already exists, overwriting".yellow()
+            );
+        }
+        // unpack_in refuses paths escaping the directory
+        if !entry.unpack_in(into)? {
+            eprintln!("skipping unsafe path {}", path.display().to_string().red());
+        }
+    }
+    Ok(())
+}
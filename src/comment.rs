@@ -1,4 +1,18 @@
 use regex::Regex;
+use semver::Version;
+#[cfg(test)]
+use tracing::warn;
+
+use crate::condition::Condition;
+use crate::diagnostic::Diagnostic;
+use crate::encryption::Encryption;
+use crate::permissions::Permissions;
+use crate::remote::SourceSpec;
+use crate::section::Placement;
+
+// version of the comment syntax written by this imosid
+// files without an all syntax comment are version 1, which also allowed start and stop
+pub const COMMENT_SYNTAX_VERSION: u32 = 2;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 // give targetinfo sourceinfo, hashinfo and targetinfo required parameter fields
@@ -9,6 +23,18 @@ pub enum CommentType {
     TargetInfo,
     HashInfo,
     PermissionInfo,
+    ConditionInfo,
+    ExpandInfo,
+    TemplateInfo,
+    ProfileInfo,
+    PlacementInfo,
+    AnchorInfo,
+    ProtectedInfo,
+    PreHookInfo,
+    PostHookInfo,
+    EncryptedInfo,
+    SyntaxInfo,
+    VersionInfo,
 }
 
 impl CommentType {
@@ -20,6 +46,18 @@ impl CommentType {
             "source" => CommentType::SourceInfo,
             "permissions" => CommentType::PermissionInfo,
             "target" => CommentType::TargetInfo,
+            "condition" => CommentType::ConditionInfo,
+            "expand" => CommentType::ExpandInfo,
+            "template" => CommentType::TemplateInfo,
+            "profile" => CommentType::ProfileInfo,
+            "placement" => CommentType::PlacementInfo,
+            "anchor" => CommentType::AnchorInfo,
+            "protected" => CommentType::ProtectedInfo,
+            "prehook" => CommentType::PreHookInfo,
+            "posthook" => CommentType::PostHookInfo,
+            "encrypted" => CommentType::EncryptedInfo,
+            "syntax" => CommentType::SyntaxInfo,
+            "imosidversion" => CommentType::VersionInfo,
             &_ => {
                 return Option::None;
             }
@@ -36,6 +74,18 @@ impl Into<String> for CommentType {
             CommentType::TargetInfo => "target",
             CommentType::HashInfo => "hash",
             CommentType::PermissionInfo => "permissions",
+            CommentType::ConditionInfo => "condition",
+            CommentType::ExpandInfo => "expand",
+            CommentType::TemplateInfo => "template",
+            CommentType::ProfileInfo => "profile",
+            CommentType::PlacementInfo => "placement",
+            CommentType::AnchorInfo => "anchor",
+            CommentType::ProtectedInfo => "protected",
+            CommentType::PreHookInfo => "prehook",
+            CommentType::PostHookInfo => "posthook",
+            CommentType::EncryptedInfo => "encrypted",
+            CommentType::SyntaxInfo => "syntax",
+            CommentType::VersionInfo => "imosidversion",
         })
     }
 }
@@ -46,6 +96,7 @@ pub struct Specialcomment {
     pub section: String, // section name extracted from prefix
     pub comment_type: CommentType,
     pub argument: Option<String>, // optional argument, used for hashes etc
+    pub indent: String,           // whitespace before the comment sign
 }
 
 impl Specialcomment {
@@ -68,30 +119,52 @@ impl Specialcomment {
         )
     }
 
+    /// lenient parse_line, malformed comments are reported and treated as content
+    #[cfg(test)]
     pub fn from_line(line: &str, commentsymbol: &str, linenumber: u32) -> Option<Specialcomment> {
-        if !line.starts_with(commentsymbol) {
-            return Option::None;
+        match Specialcomment::parse_line(line, commentsymbol, linenumber) {
+            Ok(comment) => comment,
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
         }
+    }
 
-        // construct regex that matches valid comments
-        let mut iscomment = String::from("^ *");
-        iscomment.push_str(&commentsymbol);
-        iscomment.push_str(" *\\.\\.\\. *(.*)");
+    /// parse an imosid comment, Ok(None) if line is not one
+    /// Err describes what is wrong with lines which look like one but are malformed
+    pub fn parse_line(
+        line: &str,
+        commentsymbol: &str,
+        linenumber: u32,
+    ) -> Result<Option<Specialcomment>, Diagnostic> {
+        if !line.trim_start().starts_with(commentsymbol) {
+            return Ok(None);
+        }
+
+        // construct regex that matches valid comments, they may be indented
+        let mut iscomment = String::from("^([ \t]*)");
+        iscomment.push_str(&regex::escape(commentsymbol));
+        iscomment.push_str("[ \t]*\\.\\.\\.[ \t]*(.*)");
         let commentregex = Regex::new(&iscomment).unwrap();
 
         let keywords = commentregex.captures(&line);
 
         if let Some(captures) = &keywords {
-            let keywords = captures
-                .get(1)
-                .unwrap()
-                .as_str()
-                .split(" ")
-                .collect::<Vec<&str>>();
+            let indent = String::from(&captures[1]);
+            let column = indent.chars().count() + 1;
+            let malformed =
+                |message: &str| Diagnostic::warning(linenumber, column, 0, String::from(message));
+            // words may be separated by any amount of spaces and tabs
+            let keywords = captures[2].split_whitespace().collect::<Vec<&str>>();
 
+            // lines ending in ...ignore are content left out of the section hash
+            if keywords == ["ignore"] {
+                return Ok(None);
+            }
             // needs at least a section and a keyword
             if keywords.len() < 2 {
-                return Option::None;
+                return Err(malformed("missing section name or keyword"));
             }
 
             let sectionname = keywords[0];
@@ -104,67 +177,149 @@ impl Specialcomment {
             };
 
             let tmptype: CommentType;
-            tmptype = CommentType::from_keyword(keyword)?;
+            tmptype = match CommentType::from_keyword(keyword) {
+                Some(tmptype) => tmptype,
+                // ignore markers are section content
+                None if keyword == "ignorebegin" || keyword == "ignoreend" => return Ok(None),
+                None => {
+                    // point at the keyword, it follows the section name
+                    let rest = &line[captures.get(2).unwrap().start()..];
+                    let aftername = rest.find(sectionname).unwrap() + sectionname.len();
+                    let offset = line.len() - rest.len()
+                        + aftername
+                        + rest[aftername..].find(keyword).unwrap();
+                    return Err(Diagnostic::warning(
+                        linenumber,
+                        line[..offset].chars().count() + 1,
+                        keyword.chars().count(),
+                        format!("unknown keyword {}", keyword),
+                    ));
+                }
+            };
+            // hook commands, per os targets and anchors contain spaces, they take the rest of the line
+            // up to a trailing comment, other words after the argument are commentary
+            let cargument = match tmptype {
+                CommentType::PreHookInfo
+                | CommentType::PostHookInfo
+                | CommentType::TargetInfo
+                | CommentType::AnchorInfo
+                    if keywords.len() > 2 =>
+                {
+                    // skip section name and keyword, keeping the spacing of the rest
+                    let mut rest = captures[2].trim();
+                    for _ in 0..2 {
+                        rest = rest
+                            .split_once(char::is_whitespace)
+                            .map_or("", |(_, rest)| rest)
+                            .trim_start();
+                    }
+                    let rest = match rest.split_once(&format!(" {} ", commentsymbol)) {
+                        Some((argument, _)) => argument.trim_end(),
+                        None => rest,
+                    };
+                    Some(String::from(rest))
+                }
+                _ => cargument,
+            };
             match tmptype {
                 CommentType::HashInfo => {
                     if cargument == None {
-                        println!("missing hash value on line {}", linenumber);
-                        return Option::None;
+                        return Err(malformed("missing hash value"));
                     }
                 }
-                CommentType::SourceInfo => {
-                    if cargument.is_some() {
-                        println!("updating from source not implemented yet");
-                        unimplemented!();
-                        //TODO do something
-                        //fetch from file/url/git
-                    } else {
-                        println!("missing source file argument on line {}", linenumber);
-                        return Option::None;
+                CommentType::SourceInfo => match &cargument {
+                    Some(arg) if SourceSpec::parse(arg).is_some() => {}
+                    Some(_) => {
+                        return Err(malformed("invalid source or pin"));
                     }
-                }
+                    None => {
+                        return Err(malformed("missing source file argument"));
+                    }
+                },
                 CommentType::PermissionInfo => {
                     // permissioms can only be set for the entire file
                     if sectionname != "all" {
-                        return Option::None;
+                        return Err(malformed("permissions can only be set for all"));
                     }
                     match &cargument {
-                        None => {
-                            return Option::None;
+                        Some(arg) if Permissions::parse(arg).is_some() => {}
+                        _ => {
+                            return Err(malformed("invalid permissions"));
                         }
-                        //todo: more validation. maybe own permission type?
-                        Some(arg) => match arg.parse::<u32>() {
-                            Err(_) => {
-                                return Option::None;
-                            }
-                            Ok(_) => {}
-                        },
                     }
                 }
-                CommentType::TargetInfo => {
-                    if sectionname == "all" {
-                        if cargument == None {
-                            println!("missing target value on line {}", linenumber);
-                            return Option::None;
+                // targets can apply to the whole file or to a single section
+                CommentType::TargetInfo if cargument.is_none() => {
+                    return Err(malformed("missing target value"));
+                }
+                CommentType::ProfileInfo if cargument.is_none() => {
+                    return Err(malformed("missing profile name"));
+                }
+                CommentType::PlacementInfo => match &cargument {
+                    Some(arg) if Placement::parse(arg).is_some() => {}
+                    _ => {
+                        return Err(malformed("invalid placement"));
+                    }
+                },
+                CommentType::AnchorInfo => match &cargument {
+                    Some(arg) if Regex::new(arg).is_ok() => {}
+                    _ => {
+                        return Err(malformed("invalid anchor"));
+                    }
+                },
+                // hooks run when the file target changes
+                CommentType::PreHookInfo | CommentType::PostHookInfo if sectionname != "all" => {
+                    return Err(malformed("hooks can only be set for all"));
+                }
+                CommentType::PreHookInfo | CommentType::PostHookInfo if cargument.is_none() => {
+                    return Err(malformed("missing hook command"));
+                }
+                CommentType::EncryptedInfo => match &cargument {
+                    Some(arg) if Encryption::parse(arg).is_some() => {}
+                    _ => {
+                        return Err(malformed("invalid encryption"));
+                    }
+                },
+                CommentType::ConditionInfo => match &cargument {
+                    Some(arg) if Condition::parse(arg).is_some() => {}
+                    _ => {
+                        return Err(malformed("invalid condition"));
+                    }
+                },
+                CommentType::SyntaxInfo => {
+                    if sectionname != "all" {
+                        return Err(malformed("syntax version can only be set for all"));
+                    }
+                    match &cargument {
+                        Some(arg) if arg.parse::<u32>().is_ok() => {}
+                        _ => {
+                            return Err(malformed("invalid syntax version"));
+                        }
+                    }
+                }
+                CommentType::VersionInfo => {
+                    // the imosid version which last compiled the file
+                    if sectionname != "all" {
+                        return Err(malformed("imosid version can only be set for all"));
+                    }
+                    match &cargument {
+                        Some(arg) if Version::parse(arg).is_ok() => {}
+                        _ => {
+                            return Err(malformed("invalid imosid version"));
                         }
-                    } else {
-                        println!(
-                            "warning: target can only apply to the whole file {}",
-                            linenumber
-                        );
-                        return Option::None;
                     }
                 }
                 _ => {}
             }
 
-            return Some(Specialcomment {
+            return Ok(Some(Specialcomment {
                 line: linenumber,
                 section: String::from(sectionname),
                 comment_type: tmptype,
                 argument: cargument,
-            });
+                indent,
+            }));
         };
-        return Option::None;
+        Ok(None)
     }
 }
@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::comment::{CommentType, Specialcomment};
+use crate::diagnostic::Diagnostic;
 
 pub struct CommentMap {
     map: HashMap<String, Vec<Specialcomment>>,
@@ -24,30 +25,64 @@ impl CommentMap {
         self.potentially_invalid = true;
     }
 
-    pub fn remove_incomplete(&mut self) {
+    // remove sections with missing or repeated comments, returning what is wrong with them
+    pub fn remove_incomplete(&mut self) -> Vec<Diagnostic> {
         let mut incomplete_sections = vec![];
+        let mut problems = vec![];
         for (section, comments) in self.map.iter() {
             if section == "all" {
                 continue;
             }
-            let mut incomplete = false;
+            let mut problem = None;
             let mut comment_types: HashSet<CommentType> = HashSet::new();
             for comment in comments {
                 // do not allow for multiple definitions of the same attribute
                 if comment_types.contains(&comment.comment_type) {
-                    incomplete = true;
+                    let message = if comment.comment_type == CommentType::SectionBegin {
+                        let first = comments
+                            .iter()
+                            .find(|first| first.comment_type == CommentType::SectionBegin)
+                            .unwrap();
+                        format!(
+                            "section {} is defined more than once, first on line {}",
+                            section, first.line
+                        )
+                    } else {
+                        let keyword: String = comment.comment_type.clone().into();
+                        format!("section {} has more than one {}", section, keyword)
+                    };
+                    problem = Some(Diagnostic::error(
+                        comment.line,
+                        comment.indent.chars().count() + 1,
+                        0,
+                        message,
+                    ));
                     break;
                 }
                 comment_types.insert(comment.comment_type.clone());
             }
 
-            if !incomplete {
-                incomplete = !comment_types.contains(&CommentType::SectionBegin)
-                    || !comment_types.contains(&CommentType::HashInfo)
-                    || !comment_types.contains(&CommentType::SectionEnd);
+            if problem.is_none() {
+                let missing = [
+                    CommentType::SectionBegin,
+                    CommentType::HashInfo,
+                    CommentType::SectionEnd,
+                ]
+                .into_iter()
+                .find(|comment_type| !comment_types.contains(comment_type));
+                if let Some(missing) = missing {
+                    let keyword: String = missing.into();
+                    problem = Some(Diagnostic::error(
+                        comments[0].line,
+                        comments[0].indent.chars().count() + 1,
+                        0,
+                        format!("section {} has no {} comment", section, keyword),
+                    ));
+                }
             }
-            if incomplete {
+            if let Some(problem) = problem {
                 incomplete_sections.push(section.clone());
+                problems.push(problem);
             }
         }
 
@@ -55,6 +90,8 @@ impl CommentMap {
             self.remove_section(&section);
         }
         self.potentially_invalid = false;
+        problems.sort_by_key(|problem| problem.line);
+        problems
     }
 
     pub fn remove_section(&mut self, section: &str) {
@@ -0,0 +1,36 @@
+use crate::facts::Facts;
+
+enum Predicate {
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+// condition a section has to fulfill to be applied
+// predicates are separated by commas and all have to hold
+// example: hostname=laptop,os!=macos,env.WORK=1
+pub struct Condition {
+    predicates: Vec<Predicate>,
+}
+
+impl Condition {
+    pub fn parse(input: &str) -> Option<Condition> {
+        let mut predicates = Vec::new();
+        for part in input.split(',') {
+            if let Some((key, value)) = part.split_once("!=") {
+                predicates.push(Predicate::NotEquals(key.to_string(), value.to_string()));
+            } else if let Some((key, value)) = part.split_once('=') {
+                predicates.push(Predicate::Equals(key.to_string(), value.to_string()));
+            } else {
+                return None;
+            }
+        }
+        Some(Condition { predicates })
+    }
+
+    pub fn evaluate(&self, facts: &Facts) -> bool {
+        self.predicates.iter().all(|predicate| match predicate {
+            Predicate::Equals(key, value) => facts.get(key).as_ref() == Some(value),
+            Predicate::NotEquals(key, value) => facts.get(key).as_ref() != Some(value),
+        })
+    }
+}
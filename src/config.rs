@@ -0,0 +1,206 @@
+use crate::built_info;
+use crate::facts::Facts;
+use crate::files::expand_tilde;
+use crate::gc::Retention;
+use crate::notify::Reload;
+use std::env;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use toml::Value;
+
+// user configuration and the directories imosid works with
+pub struct Config {
+    pub path: PathBuf,
+    pub loaded: bool,
+    pub source: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub auto_commit: bool, // commit files imosid writes back to the config repository
+    pub notify: bool,      // send a desktop notification after apply changed targets
+    pub reload: Vec<Reload>,
+    pub retention: Retention, // what imosid gc keeps
+}
+
+impl Config {
+    // read config file, missing files result in the default configuration
+    pub fn load() -> Config {
+        let path = match env::var("IMOSID_CONFIG") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => config_dir().join("config.toml"),
+        };
+
+        let mut config = Config {
+            path,
+            loaded: false,
+            source: None,
+            profile: None,
+            auto_commit: false,
+            notify: false,
+            reload: Vec::new(),
+            retention: Retention::default(),
+        };
+
+        if let Ok(content) = read_to_string(&config.path) {
+            match content.parse::<Value>() {
+                Ok(value) => {
+                    config.loaded = true;
+                    if let Some(Value::String(source)) = value.get("source") {
+                        config.source = Some(PathBuf::from(expand_tilde(source)));
+                    }
+                    if let Some(Value::String(profile)) = value.get("profile") {
+                        config.profile = Some(profile.to_string());
+                    }
+                    if let Some(Value::Boolean(auto_commit)) = value.get("auto_commit") {
+                        config.auto_commit = *auto_commit;
+                    }
+                    if let Some(Value::Boolean(notify)) = value.get("notify") {
+                        config.notify = *notify;
+                    }
+                    if let Some(Value::Table(reloads)) = value.get("reload") {
+                        for (name, reload) in reloads {
+                            match Reload::from_value(name, reload) {
+                                Some(reload) => config.reload.push(reload),
+                                None => eprintln!("invalid reload command {}", name),
+                            }
+                        }
+                    }
+                    if let Some(gc) = value.get("gc") {
+                        match Retention::from_value(gc) {
+                            Some(retention) => config.retention = retention,
+                            None => eprintln!("invalid gc settings"),
+                        }
+                    }
+                }
+                Err(_) => {
+                    eprintln!("could not parse config file {}", config.path.display());
+                }
+            }
+        }
+
+        if let Ok(source) = env::var("IMOSID_SOURCE") {
+            config.source = Some(PathBuf::from(source));
+        }
+        if let Ok(profile) = env::var("IMOSID_PROFILE") {
+            config.profile = Some(profile);
+        }
+
+        config
+    }
+
+    // all resolved settings as key value pairs
+    fn get_settings(&self) -> Vec<(&'static str, String)> {
+        let facts = Facts::gather();
+        vec![
+            ("IMOSID_VERSION", String::from(built_info::PKG_VERSION)),
+            ("IMOSID_CONFIG", self.path.display().to_string()),
+            ("IMOSID_CONFIG_LOADED", self.loaded.to_string()),
+            (
+                "IMOSID_SOURCE_DIR",
+                self.source
+                    .as_ref()
+                    .map(|source| source.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            ("IMOSID_CACHE_DIR", cache_dir().display().to_string()),
+            ("IMOSID_STATE_DIR", state_dir().display().to_string()),
+            ("IMOSID_BACKUP_DIR", backup_dir().display().to_string()),
+            ("IMOSID_HOSTNAME", facts.hostname),
+            ("IMOSID_OS", facts.os),
+            ("IMOSID_ARCH", facts.arch),
+            ("IMOSID_PROFILE", self.profile.clone().unwrap_or_default()),
+            ("IMOSID_AUTO_COMMIT", self.auto_commit.to_string()),
+            ("IMOSID_NOTIFY", self.notify.to_string()),
+        ]
+    }
+
+    // output that can be evaluated by a posix shell
+    pub fn to_shell(&self) -> String {
+        let mut retstr = String::new();
+        for (key, value) in self.get_settings() {
+            retstr.push_str(&format!("{}='{}'\n", key, value.replace('\'', "'\\''")));
+        }
+        retstr
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .get_settings()
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": \"{}\"", key, escape_json(value)))
+            .collect::<Vec<String>>();
+        format!("{{\n{}\n}}\n", entries.join(",\n"))
+    }
+}
+
+fn escape_json(input: &str) -> String {
+    let mut retstr = String::new();
+    for c in input.chars() {
+        match c {
+            '"' => retstr.push_str("\\\""),
+            '\\' => retstr.push_str("\\\\"),
+            '\n' => retstr.push_str("\\n"),
+            '\t' => retstr.push_str("\\t"),
+            c if (c as u32) < 0x20 => retstr.push_str(&format!("\\u{:04x}", c as u32)),
+            c => retstr.push(c),
+        }
+    }
+    retstr
+}
+
+#[cfg(not(test))]
+fn home_dir() -> PathBuf {
+    home::home_dir().expect("could not find home directory")
+}
+
+// tests keep their state, cache and config out of the real home directory
+// every test thread can point them at its own directory
+#[cfg(test)]
+thread_local! {
+    static TEST_HOME: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub fn set_test_home(home: &std::path::Path) {
+    TEST_HOME.with(|testhome| *testhome.borrow_mut() = Some(home.to_path_buf()));
+}
+
+#[cfg(test)]
+fn xdg_base(_variable: &str, fallback: &str) -> PathBuf {
+    TEST_HOME
+        .with(|home| home.borrow().clone())
+        .unwrap_or_else(|| env::temp_dir().join("imosid-test"))
+        .join(fallback)
+}
+
+// xdg base directory with fallback relative to home
+#[cfg(not(test))]
+fn xdg_base(variable: &str, fallback: &str) -> PathBuf {
+    match env::var(variable) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(fallback),
+    }
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> PathBuf {
+    xdg_base(variable, fallback).join("imosid")
+}
+
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+pub fn backup_dir() -> PathBuf {
+    state_dir().join("backups")
+}
+
+// user units of systemd
+pub fn systemd_user_dir() -> PathBuf {
+    xdg_base("XDG_CONFIG_HOME", ".config").join("systemd/user")
+}
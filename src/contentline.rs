@@ -1,5 +0,0 @@
-pub struct ContentLine {
-    pub linenumber: u32,
-    pub content: String,
-}
-
@@ -0,0 +1,156 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use colored::Colorize;
+
+use crate::archive::export_archive;
+use crate::dotwalker::WalkOptions;
+
+// where deploy unpacks the config on a host, relative to the home directory
+pub const DEFAULT_REMOTE_DIR: &str = ".cache/imosid/deploy";
+
+// written into every deployed directory, directories without it are never replaced
+pub const DEPLOY_MARKER: &str = ".imosid-deploy";
+
+// how a config directory is applied on other machines
+pub struct Deployment {
+    pub remote_dir: String,      // replaced on every deploy
+    pub imosid: String,          // imosid command on the hosts
+    pub apply_args: Vec<String>, // passed on to imosid apply
+}
+
+impl Default for Deployment {
+    fn default() -> Self {
+        Deployment {
+            remote_dir: String::from(DEFAULT_REMOTE_DIR),
+            imosid: String::from("imosid"),
+            apply_args: Vec::new(),
+        }
+    }
+}
+
+/// quote word for a posix shell
+pub fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c))
+    {
+        return String::from(word);
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+/// remote dirs have to stay below the home directory of the host
+pub fn check_remote_dir(dir: &str) -> Result<(), String> {
+    if dir.starts_with('/') || dir.starts_with('~') {
+        return Err(format!(
+            "remote dir {} has to be relative to the home directory",
+            dir
+        ));
+    }
+    let dir = dir.strip_suffix('/').unwrap_or(dir);
+    if dir
+        .split('/')
+        .any(|component| matches!(component, "" | "." | ".."))
+    {
+        return Err(format!(
+            "remote dir {} cannot contain empty, . or .. components",
+            dir
+        ));
+    }
+    Ok(())
+}
+
+impl Deployment {
+    /// shell script run on the host with the exported archive on stdin
+    /// it unpacks the archive next to the remote dir, swaps it in and applies it
+    /// an existing remote dir is only replaced if an earlier deploy created it
+    pub fn remote_script(&self) -> String {
+        let dir = shell_quote(&self.remote_dir);
+        let imosid = shell_quote(&self.imosid);
+        let mut apply = format!("{} apply \"$dir\"", imosid);
+        for arg in &self.apply_args {
+            apply.push(' ');
+            apply.push_str(&shell_quote(arg));
+        }
+        [
+            String::from("set -e"),
+            format!("dir={}", dir),
+            format!(
+                "if [ -e \"$dir\" ] && [ ! -e \"$dir/{}\" ]; then",
+                DEPLOY_MARKER
+            ),
+            String::from("echo \"$dir was not created by imosid deploy, not replacing it\" >&2"),
+            String::from("exit 1"),
+            String::from("fi"),
+            String::from("mkdir -p \"$(dirname \"$dir\")\""),
+            String::from("new=$(mktemp -d \"$dir.XXXXXX\")"),
+            String::from("archive=$(mktemp)"),
+            String::from("trap 'rm -rf \"$new\" \"$archive\"' EXIT"),
+            String::from("cat > \"$archive\""),
+            format!(
+                "{} import --no-apply --into \"$new\" \"$archive\" > /dev/null",
+                imosid
+            ),
+            format!("touch \"$new/{}\"", DEPLOY_MARKER),
+            String::from("rm -rf \"$dir\""),
+            String::from("mv \"$new\" \"$dir\""),
+            apply,
+        ]
+        .join("\n")
+    }
+
+    // stream archive to host over ssh and run the remote script there
+    fn deploy_archive(&self, archive: &Path, host: &str) -> io::Result<bool> {
+        let status = Command::new("ssh")
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("--")
+            .arg(host)
+            .arg(format!("sh -c {}", shell_quote(&self.remote_script())))
+            .stdin(Stdio::from(File::open(archive)?))
+            .status()?;
+        Ok(status.success())
+    }
+
+    /// export the managed files of dir once and apply them on every host
+    /// returns the hosts the deployment failed on
+    pub fn deploy(
+        &self,
+        dir: &Path,
+        walkoptions: &WalkOptions,
+        hosts: &[String],
+    ) -> io::Result<Vec<String>> {
+        check_remote_dir(&self.remote_dir)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let archive =
+            std::env::temp_dir().join(format!("imosid-deploy-{}.tar.gz", std::process::id()));
+        let count = match export_archive(dir, walkoptions, &archive) {
+            Ok(count) => count,
+            Err(e) => {
+                let _ = fs::remove_file(&archive);
+                return Err(e);
+            }
+        };
+        let mut failed = Vec::new();
+        for host in hosts {
+            println!("deploying {} files to {}", count, host.bold());
+            match self.deploy_archive(&archive, host) {
+                Ok(true) => {}
+                Ok(false) => {
+                    eprintln!("{} {}", "deploy failed on".red(), host.red().bold());
+                    failed.push(host.clone());
+                }
+                Err(e) => {
+                    eprintln!("could not run ssh for {}: {}", host.red(), e);
+                    failed.push(host.clone());
+                }
+            }
+        }
+        let _ = fs::remove_file(&archive);
+        Ok(failed)
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+
+use colored::{ColoredString, Colorize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Warning, // the lines are kept as content
+    Error,   // a section could not be used
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// a problem with the imosid comments of a file
+// line and column start at 1, a length of 0 spans the rest of the line
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: u32,
+    pub column: usize,
+    pub length: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Diagnostic {
+    pub fn warning(line: u32, column: usize, length: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line,
+            column,
+            length,
+            message,
+        }
+    }
+
+    pub fn error(line: u32, column: usize, length: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            line,
+            column,
+            length,
+            message,
+        }
+    }
+
+    fn paint(&self, text: &str) -> ColoredString {
+        match self.severity {
+            Severity::Warning => text.yellow().bold(),
+            Severity::Error => text.red().bold(),
+        }
+    }
+
+    /// annotate the line of content the diagnostic is about, like rustc does
+    ///
+    /// error: section foo is defined more than once
+    ///  --> .bashrc:12:1
+    ///    |
+    /// 12 | #... foo begin
+    ///    | ^^^^^^^^^^^^^^
+    pub fn render(&self, filename: &str, content: &str) -> String {
+        let linenumber = self.line.to_string();
+        let gutter = " ".repeat(linenumber.len());
+        let bar = "|".blue().bold();
+        let mut output = format!(
+            "{}: {}\n{}{} {}:{}:{}\n",
+            self.paint(&self.severity.to_string()),
+            self.message.bold(),
+            gutter,
+            "-->".blue().bold(),
+            filename,
+            self.line,
+            self.column
+        );
+        let line = match content.lines().nth((self.line as usize).saturating_sub(1)) {
+            Some(line) => line,
+            None => return output,
+        };
+        let before = self.column.saturating_sub(1);
+        let length = match self.length {
+            0 => line
+                .trim_end()
+                .chars()
+                .count()
+                .saturating_sub(before)
+                .max(1),
+            length => length,
+        };
+        // tabs would shift the markers, keep them in the prefix
+        let prefix: String = line
+            .chars()
+            .take(before)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        output.push_str(&format!(
+            "{} {}\n{} {} {}\n{} {} {}{}\n",
+            gutter,
+            bar,
+            linenumber.blue().bold(),
+            bar,
+            line,
+            gutter,
+            bar,
+            prefix,
+            self.paint(&"^".repeat(length))
+        ));
+        output
+    }
+}
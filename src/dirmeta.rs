@@ -0,0 +1,277 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use toml::map::Map;
+use toml::Value;
+use tracing::{error, info, warn};
+use walkdir::WalkDir;
+
+use crate::files::{expand_tilde, write_atomic, ApplyOptions, ApplyResult};
+use crate::hashable::HashAlgorithm;
+use crate::matcher::SectionMatcher;
+
+pub const DIR_METAFILE_NAME: &str = "dir.imosid.toml";
+
+// the fields of a dir.imosid.toml file as they are stored on disk
+#[derive(Serialize, Deserialize)]
+struct DirMetaFileData {
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+// a dir.imosid.toml managing every file below the directory it is in
+// the whole tree is hashed and applied at once
+pub struct DirMetaFile {
+    pub dir: PathBuf,
+    pub hash: String,
+    pub target: Option<String>,
+    pub exclude: Vec<String>, // globs of paths relative to dir which are left alone
+    pub profile: Option<String>,
+    pub modified: bool,
+    excluded: SectionMatcher,
+    extra: Map<String, Value>,
+}
+
+impl DirMetaFile {
+    /// read the dir.imosid.toml of dir
+    pub fn new(dir: &Path) -> Result<DirMetaFile, String> {
+        let content = fs::read_to_string(dir.join(DIR_METAFILE_NAME)).map_err(|e| e.to_string())?;
+        let data: DirMetaFileData = toml::from_str(&content).map_err(|e| e.to_string())?;
+        let globs: Vec<&str> = data.exclude.iter().map(|glob| glob.as_str()).collect();
+        let excluded = SectionMatcher::new(&globs, &[])
+            .map_err(|e| format!("invalid exclude pattern: {}", e))?;
+        let mut dirmetafile = DirMetaFile {
+            dir: dir.to_path_buf(),
+            hash: data.hash,
+            target: data.target,
+            exclude: data.exclude,
+            profile: data.profile,
+            modified: false,
+            excluded,
+            extra: data.extra,
+        };
+        dirmetafile.modified =
+            dirmetafile.tree_hash().map_err(|e| e.to_string())? != dirmetafile.hash;
+        Ok(dirmetafile)
+    }
+
+    /// start managing dir, an existing dir.imosid.toml is kept
+    pub fn from(dir: &Path) -> Result<DirMetaFile, String> {
+        if dir.join(DIR_METAFILE_NAME).is_file() {
+            return DirMetaFile::new(dir);
+        }
+        Ok(DirMetaFile {
+            dir: dir.to_path_buf(),
+            hash: String::new(),
+            target: None,
+            exclude: Vec::new(),
+            profile: None,
+            modified: true,
+            excluded: SectionMatcher::default(),
+            extra: Map::new(),
+        })
+    }
+
+    /// managed files below the directory, relative to it and sorted
+    pub fn managed_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&self.dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = match entry.path().strip_prefix(&self.dir) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => continue,
+            };
+            if relative == Path::new(DIR_METAFILE_NAME)
+                || (!self.excluded.is_empty() && self.excluded.matches(&relative.to_string_lossy()))
+            {
+                continue;
+            }
+            files.push(relative);
+        }
+        files
+    }
+
+    // hash of the paths and contents of all managed files
+    fn tree_hash(&self) -> io::Result<String> {
+        let algorithm = HashAlgorithm::from_hash(&self.hash);
+        let mut tree = Vec::new();
+        for file in self.managed_files() {
+            tree.extend_from_slice(file.to_string_lossy().as_bytes());
+            tree.push(0);
+            tree.extend_from_slice(
+                algorithm
+                    .digest(&fs::read(self.dir.join(&file))?)
+                    .as_bytes(),
+            );
+            tree.push(b'\n');
+        }
+        Ok(algorithm.digest(&tree))
+    }
+
+    /// mark the current tree as unmodified, return true if the hash changed
+    pub fn compile(&mut self) -> io::Result<bool> {
+        let hash = self.tree_hash()?;
+        self.modified = false;
+        if hash == self.hash {
+            return Ok(false);
+        }
+        self.hash = hash;
+        Ok(true)
+    }
+
+    pub fn output(&self) -> String {
+        let data = DirMetaFileData {
+            hash: self.hash.clone(),
+            target: self.target.clone(),
+            exclude: self.exclude.clone(),
+            profile: self.profile.clone(),
+            extra: self.extra.clone(),
+        };
+        toml::to_string(&data).expect("metafile fields are always valid toml")
+    }
+
+    pub fn write_to_file(&self) -> bool {
+        let path = self.dir.join(DIR_METAFILE_NAME);
+        if write_atomic(&path, self.output().as_bytes()).is_err() {
+            error!("could not write {}", path.display().to_string().red());
+            return false;
+        }
+        true
+    }
+
+    /// copy the tree to the target directory, removing files which
+    /// were removed from the tree since it was last applied
+    /// a target modified since then is left alone
+    pub fn apply(&self, options: &ApplyOptions, changes: &mut Vec<String>) -> ApplyResult {
+        let dirname = self.dir.display().to_string();
+        if !options.facts.matches_profile(&self.profile) {
+            return ApplyResult::Unchanged;
+        }
+        let target = match &self.target {
+            Some(target) => PathBuf::from(expand_tilde(target)),
+            None => {
+                error!("{} has no target directory", dirname.red());
+                return ApplyResult::Error;
+            }
+        };
+        if self.modified {
+            warn!("{}", format!("{} modified, skipping", dirname).yellow());
+            return ApplyResult::Unchanged;
+        }
+
+        // the target keeps a dir.imosid.toml of the tree applied to it
+        let targetmeta = if target.join(DIR_METAFILE_NAME).is_file() {
+            match DirMetaFile::new(&target) {
+                Ok(targetmeta) => Some(targetmeta),
+                Err(e) => {
+                    error!("invalid metafile in {}: {}", target.display(), e);
+                    return ApplyResult::Error;
+                }
+            }
+        } else {
+            None
+        };
+        match &targetmeta {
+            Some(targetmeta) if targetmeta.modified => {
+                warn!(
+                    "{}",
+                    format!("target {} modified, skipping", target.display()).yellow()
+                );
+                return ApplyResult::Unchanged;
+            }
+            Some(targetmeta) if targetmeta.hash == self.hash => return ApplyResult::Unchanged,
+            None if fs::read_dir(&target).is_ok_and(|mut dir| dir.next().is_some()) => {
+                warn!(
+                    "{}",
+                    format!("target {} is not managed, skipping", target.display()).yellow()
+                );
+                return ApplyResult::Unchanged;
+            }
+            _ => {}
+        }
+
+        if let Err(e) = self.sync(&target, targetmeta.as_ref()) {
+            error!(
+                "could not apply {} to {}: {}",
+                dirname.red(),
+                target.display(),
+                e
+            );
+            return ApplyResult::Error;
+        }
+        let applied = DirMetaFile {
+            dir: target.clone(),
+            hash: self.hash.clone(),
+            target: None,
+            exclude: self.exclude.clone(),
+            profile: None,
+            modified: false,
+            excluded: self.excluded.clone(),
+            extra: Map::new(),
+        };
+        if !applied.write_to_file() {
+            return ApplyResult::Error;
+        }
+        info!(
+            "applied {} to {}",
+            dirname.green(),
+            target.display().to_string().bold()
+        );
+        changes.push(target.display().to_string());
+        ApplyResult::Changed
+    }
+
+    fn sync(&self, target: &Path, targetmeta: Option<&DirMetaFile>) -> io::Result<()> {
+        let files = self.managed_files();
+        fs::create_dir_all(target)?;
+        for file in &files {
+            let source = self.dir.join(file);
+            let destination = target.join(file);
+            if fs::read(&destination).ok() == Some(fs::read(&source)?) {
+                continue;
+            }
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&source, &destination)?;
+        }
+        // the target is unmodified, files it has on top were removed from the tree
+        if let Some(targetmeta) = targetmeta {
+            for file in targetmeta.managed_files() {
+                if !files.contains(&file) {
+                    fs::remove_file(target.join(&file))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pretty_info(&self) -> String {
+        let mut ret = format!("directory hash: {}\n", self.hash);
+        if let Some(target) = &self.target {
+            ret.push_str(&format!("target : {}\n", target.bold()));
+        }
+        if self.modified {
+            ret.push_str(&"modified".red().bold());
+        } else {
+            ret.push_str(&"unmodified".green().bold());
+        }
+        ret
+    }
+}
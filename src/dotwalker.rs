@@ -1,33 +1,159 @@
+use std::io::{self, ErrorKind};
+use std::path::Path;
 pub(crate) use std::path::PathBuf;
 
 use colored::Colorize;
-use walkdir::WalkDir;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing::{debug, error, warn};
 
-use crate::files::{ApplyResult, DotFile};
+use crate::archive::EXPORT_MANIFEST_NAME;
+use crate::config::Config;
+use crate::deploy::DEPLOY_MARKER;
+use crate::dirmeta::{DirMetaFile, DIR_METAFILE_NAME};
+use crate::files::{ApplyOptions, ApplyResult, DotFile};
+use crate::hashcache::HashCache;
+use crate::hooks::{apply_with_hooks, Hooks};
+use crate::init::{IGNORE_NAME, MANIFEST_NAME};
+use crate::notify;
+use crate::state::StateDb;
 
-pub fn walk_config_dir(path: &PathBuf) -> impl Iterator<Item = walkdir::DirEntry> {
-    // TODO: how does ripgrep handle this?
-    let walker = WalkDir::new(path)
-        .into_iter()
+// controls which files of a config directory are visited
+#[derive(Default)]
+pub struct WalkOptions {
+    pub hidden: bool,             // also walk hidden directories
+    pub no_ignore: bool,          // do not respect .gitignore and .imosidignore files
+    pub only: Vec<String>,        // only visit files matching these globs
+    pub exclude: Vec<String>,     // never visit files matching these globs
+    pub max_depth: Option<usize>, // do not descend deeper than this below the directory
+    pub follow_symlinks: bool,    // walk into symlinked directories
+}
+
+fn config_walker(path: &PathBuf, walkoptions: &WalkOptions) -> ignore::Walk {
+    let mut builder = WalkBuilder::new(path);
+    let hidden = walkoptions.hidden;
+    // hidden files are what dotfile repos consist of, only skip hidden directories
+    builder
+        .hidden(false)
+        .require_git(false)
+        .git_ignore(!walkoptions.no_ignore)
+        .git_global(!walkoptions.no_ignore)
+        .git_exclude(!walkoptions.no_ignore)
+        .ignore(!walkoptions.no_ignore)
+        .parents(!walkoptions.no_ignore)
+        .max_depth(walkoptions.max_depth)
+        .follow_links(walkoptions.follow_symlinks)
+        .filter_entry(move |entry| {
+            let name = entry.file_name().to_str().unwrap_or("");
+            let isdir = entry.file_type().is_some_and(|filetype| filetype.is_dir());
+            if !isdir || entry.depth() == 0 {
+                return true;
+            }
+            name != ".git" && (hidden || !name.starts_with('.'))
+        });
+    if !walkoptions.no_ignore {
+        builder.add_custom_ignore_filename(IGNORE_NAME);
+    }
+    if !walkoptions.only.is_empty() || !walkoptions.exclude.is_empty() {
+        let mut overrides = OverrideBuilder::new(path);
+        // overrides whitelist plain globs and ignore globs starting with !
+        let globs = walkoptions
+            .only
+            .iter()
+            .cloned()
+            .chain(walkoptions.exclude.iter().map(|glob| format!("!{}", glob)));
+        for glob in globs {
+            if let Err(e) = overrides.add(&glob) {
+                error!("invalid glob {}: {}", glob.red(), e);
+            }
+        }
+        match overrides.build() {
+            Ok(overrides) => {
+                builder.overrides(overrides);
+            }
+            Err(e) => error!("could not use globs: {}", e),
+        }
+    }
+
+    builder.build()
+}
+
+// files of directories with a dir.imosid.toml are applied with the directory
+fn in_managed_dir(path: &Path, root: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .any(|dir| dir.join(DIR_METAFILE_NAME).is_file())
+}
+
+pub fn walk_config_dir(
+    path: &PathBuf,
+    walkoptions: &WalkOptions,
+) -> impl Iterator<Item = ignore::DirEntry> {
+    let root = path.clone();
+    let walker = config_walker(path, walkoptions)
         .filter_map(|e| e.ok())
-        .filter(|e| {
+        .filter(move |e| {
             let path = e.path();
             let entrystring = path.to_str().unwrap();
+            let entryname = path.file_name().and_then(|name| name.to_str());
             !entrystring.ends_with(".imosid.toml")
-                && !entrystring.contains("/.git/")
+                && entryname != Some(MANIFEST_NAME)
+                && entryname != Some(IGNORE_NAME)
+                && entryname != Some(EXPORT_MANIFEST_NAME)
+                && entryname != Some(DEPLOY_MARKER)
                 && path.to_path_buf().is_file()
+                && !in_managed_dir(path, &root)
         });
     return walker;
 }
 
-pub fn walk_dotfiles(path: &PathBuf) -> Vec<DotFile> {
+/// directories below path managed by a dir.imosid.toml
+pub fn walk_managed_dirs(path: &PathBuf, walkoptions: &WalkOptions) -> Vec<DirMetaFile> {
+    let mut dirs = Vec::new();
+    for entry in config_walker(path, walkoptions).filter_map(|e| e.ok()) {
+        if entry.file_name() != DIR_METAFILE_NAME {
+            continue;
+        }
+        let dir = match entry.path().parent() {
+            Some(dir) => dir,
+            None => continue,
+        };
+        match DirMetaFile::new(dir) {
+            Ok(dirmetafile) => dirs.push(dirmetafile),
+            Err(e) => error!("invalid metafile {}: {}", entry.path().display(), e),
+        }
+    }
+    dirs
+}
+
+// binary files are expected in config dirs and only get a warning
+pub fn report_open_error(path: &Path, error: &io::Error) {
+    if error.kind() == ErrorKind::InvalidData {
+        warn!(
+            "{} skipping {}: {}",
+            "warning:".yellow().bold(),
+            path.to_str().unwrap().yellow(),
+            error
+        );
+    } else {
+        error!(
+            "could not open file {}: {}",
+            path.to_str().unwrap().red(),
+            error
+        );
+    }
+}
+
+pub fn walk_dotfiles(path: &PathBuf, walkoptions: &WalkOptions) -> Vec<DotFile> {
     let mut dotfiles = Vec::new();
-    for entry in walk_config_dir(path) {
+    for entry in walk_config_dir(path, walkoptions) {
         let entrypath = entry.path().to_path_buf();
         let dotfile = match DotFile::from_pathbuf(&entrypath) {
             Ok(file) => file,
-            Err(_) => {
-                eprintln!("could not open file {}", entrypath.to_str().unwrap().red());
+            Err(e) => {
+                report_open_error(&entrypath, &e);
                 continue;
             }
         };
@@ -36,27 +162,146 @@ pub fn walk_dotfiles(path: &PathBuf) -> Vec<DotFile> {
     dotfiles
 }
 
-pub fn apply_config_dir(path: &PathBuf) -> bool {
+// how applying the files of a directory went
+#[derive(Default)]
+struct ApplySummary {
+    changed: Vec<String>,
+    unchanged: usize,
+    skipped: usize, // cached or not a text file
+    failed: Vec<String>,
+}
+
+impl ApplySummary {
+    fn print(&self) {
+        println!("{}", "summary".bold());
+        println!(
+            "  {} {}",
+            format!("{:<10}", "changed").green(),
+            self.changed.len()
+        );
+        println!(
+            "  {} {}",
+            format!("{:<10}", "unchanged").bold(),
+            self.unchanged
+        );
+        println!(
+            "  {} {}",
+            format!("{:<10}", "skipped").dimmed(),
+            self.skipped
+        );
+        println!(
+            "  {} {}",
+            format!("{:<10}", "failed").red(),
+            self.failed.len()
+        );
+        for file in &self.changed {
+            println!("  {} {}", "changed".green(), file);
+        }
+        for file in &self.failed {
+            println!("  {} {}", "failed".red(), file);
+        }
+    }
+}
+
+fn progress_bar(length: usize) -> ProgressBar {
+    let bar = ProgressBar::new(length as u64);
+    if let Ok(style) = ProgressStyle::with_template("{bar:30} {pos}/{len} {wide_msg}") {
+        bar.set_style(style);
+    }
+    bar
+}
+
+pub fn apply_config_dir(path: &PathBuf, walkoptions: &WalkOptions, options: &ApplyOptions) -> bool {
     if !path.is_dir() {
         return false;
     }
 
-    let mut donesomething = false;
-    for entry in walk_config_dir(path) {
-        let tmpsource = match DotFile::from_pathbuf(&entry.path().to_path_buf()) {
+    let mut cache = HashCache::load(options.cache);
+    let mut state = StateDb::load();
+    let mut hooks = Hooks::load(Some(path));
+    let mut changes = Vec::new();
+    let mut applied = Vec::new(); // changed targets of files applied without errors
+    let mut summary = ApplySummary::default();
+    let entries: Vec<_> = walk_config_dir(path, walkoptions).collect();
+    // output of apply goes above the bar, it is hidden if stderr is no terminal
+    let bar = progress_bar(entries.len());
+    for entry in entries {
+        let name = entry
+            .path()
+            .strip_prefix(path)
+            .unwrap_or(entry.path())
+            .display()
+            .to_string();
+        bar.set_message(name.clone());
+        bar.inc(1);
+        if cache.is_applied(entry.path(), options)
+            || (options.changed_only && cache.is_source_applied(entry.path(), options))
+        {
+            bar.suspend(|| debug!("{} already applied, skipping", name));
+            summary.skipped += 1;
+            continue;
+        }
+        let opened = if options.strict {
+            DotFile::from_pathbuf_strict(&entry.path().to_path_buf())
+        } else {
+            DotFile::from_pathbuf(&entry.path().to_path_buf())
+        };
+        let tmpsource = match opened {
             Ok(file) => file,
-            Err(_) => {
-                eprintln!(
-                    "could not open file {}",
-                    &entry.path().to_str().unwrap().red()
-                );
+            Err(e) => {
+                bar.suspend(|| report_open_error(entry.path(), &e));
+                if e.kind() == ErrorKind::InvalidData {
+                    summary.skipped += 1;
+                } else {
+                    summary.failed.push(name);
+                }
                 continue;
             }
         };
-        if let ApplyResult::Changed = tmpsource.apply() {
-            donesomething = true;
+        let changecount = changes.len();
+        let result =
+            bar.suspend(|| apply_with_hooks(&tmpsource, options, &mut hooks, &mut changes));
+        match result {
+            ApplyResult::Changed => {
+                applied.extend_from_slice(&changes[changecount..]);
+                summary.changed.push(name);
+                cache.set_applied(&tmpsource, options);
+                state.record(&tmpsource, &changes[changecount..], &options.facts);
+            }
+            ApplyResult::Unchanged => {
+                summary.unchanged += 1;
+                cache.set_applied(&tmpsource, options);
+            }
+            ApplyResult::Error => summary.failed.push(name),
         }
     }
+    bar.finish_and_clear();
+
+    for dir in walk_managed_dirs(path, walkoptions) {
+        let name = dir
+            .dir
+            .strip_prefix(path)
+            .unwrap_or(&dir.dir)
+            .display()
+            .to_string();
+        let changecount = changes.len();
+        match dir.apply(options, &mut changes) {
+            ApplyResult::Changed => {
+                applied.extend_from_slice(&changes[changecount..]);
+                summary.changed.push(name);
+            }
+            ApplyResult::Unchanged => summary.unchanged += 1,
+            ApplyResult::Error => summary.failed.push(name),
+        }
+    }
+    cache.save();
+    let donesomething = !summary.changed.is_empty();
+    if donesomething {
+        state.save();
+    }
+    hooks.run_post_apply(&changes);
+    summary.print();
+    notify::after_apply(&Config::load(), &applied, options.notify);
 
     donesomething
 }
@@ -0,0 +1,72 @@
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::config::config_dir;
+
+// tools encrypted sections can be stored with
+// sections keep the ascii armored ciphertext, it is only decrypted when applying
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encryption {
+    Age,
+    Gpg,
+}
+
+impl Encryption {
+    pub fn parse(input: &str) -> Option<Encryption> {
+        match input {
+            "age" => Some(Encryption::Age),
+            "gpg" => Some(Encryption::Gpg),
+            _ => None,
+        }
+    }
+
+    // age needs an identity file, gpg uses its agent
+    fn command(&self) -> Command {
+        match self {
+            Encryption::Age => {
+                let mut command = Command::new("age");
+                command.arg("--decrypt").arg("--identity").arg(age_identity());
+                command
+            }
+            Encryption::Gpg => {
+                let mut command = Command::new("gpg");
+                command.arg("--quiet").arg("--batch").arg("--decrypt");
+                command
+            }
+        }
+    }
+
+    /// decrypt armored ciphertext, None if the tool is missing or fails
+    pub fn decrypt(&self, ciphertext: &str) -> Option<String> {
+        let mut child = self
+            .command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        child
+            .stdin
+            .take()?
+            .write_all(ciphertext.as_bytes())
+            .ok()?;
+        let output = child.wait_with_output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let mut plaintext = String::from_utf8(output.stdout).ok()?;
+        // section content always ends with a newline
+        if !plaintext.is_empty() && !plaintext.ends_with('\n') {
+            plaintext.push('\n');
+        }
+        Some(plaintext)
+    }
+}
+
+fn age_identity() -> PathBuf {
+    match env::var("IMOSID_AGE_IDENTITY") {
+        Ok(identity) => PathBuf::from(identity),
+        Err(_) => config_dir().join("age.key"),
+    }
+}
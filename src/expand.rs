@@ -0,0 +1,101 @@
+use directories::BaseDirs;
+use regex::{Captures, Regex};
+use std::env;
+use std::fs::read_to_string;
+
+// look up a variable, falling back to the xdg defaults
+fn get_variable(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    let fallback = match name {
+        "XDG_CONFIG_HOME" => ".config",
+        "XDG_DATA_HOME" => ".local/share",
+        "XDG_CACHE_HOME" => ".cache",
+        "XDG_STATE_HOME" => ".local/state",
+        _ => return None,
+    };
+    let home = home::home_dir()?;
+    Some(home.join(fallback).display().to_string())
+}
+
+// substitute ${VARIABLE} with its value
+// unknown variables are left untouched
+pub fn expand_variables(input: &str) -> String {
+    let variableregex = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    variableregex
+        .replace_all(input, |captures: &Captures| {
+            get_variable(&captures[1]).unwrap_or_else(|| String::from(&captures[0]))
+        })
+        .to_string()
+}
+
+// substitute $VARIABLE and ${VARIABLE} in paths
+pub fn expand_path_variables(input: &str) -> String {
+    let variableregex =
+        Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))").unwrap();
+    variableregex
+        .replace_all(input, |captures: &Captures| {
+            let name = captures.get(1).or(captures.get(2)).unwrap().as_str();
+            get_variable(name).unwrap_or_else(|| String::from(&captures[0]))
+        })
+        .to_string()
+}
+
+// home directory of another user from /etc/passwd
+pub fn user_home(user: &str) -> Option<String> {
+    let content = read_to_string("/etc/passwd").ok()?;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() > 5 && fields[0] == user {
+            return Some(String::from(fields[5]));
+        }
+    }
+    None
+}
+
+// resolve shorthands like xdg-config:foo/bar.conf to the xdg base directories
+pub fn expand_xdg_shorthand(input: &str) -> Option<String> {
+    let (prefix, rest) = input.split_once(':')?;
+    let basedirs = BaseDirs::new()?;
+    let basedir = match prefix {
+        "xdg-config" => basedirs.config_dir().to_path_buf(),
+        "xdg-data" => basedirs.data_dir().to_path_buf(),
+        "xdg-cache" => basedirs.cache_dir().to_path_buf(),
+        "xdg-state" => basedirs.state_dir()?.to_path_buf(),
+        _ => return None,
+    };
+    Some(basedir.join(rest).display().to_string())
+}
+
+// targets which differ between operating systems, split into os and path
+// example: linux=~/.config/foo macos=~/Library/Application Support/foo
+fn os_targets(input: &str) -> Option<Vec<(&str, &str)>> {
+    let osregex = Regex::new(r"(?:^|\s)([a-z]+)=").unwrap();
+    let captures: Vec<Captures> = osregex.captures_iter(input).collect();
+    if captures.first()?.get(0)?.start() != 0 {
+        return None;
+    }
+    let mut targets = Vec::new();
+    for (index, capture) in captures.iter().enumerate() {
+        let start = capture.get(0)?.end();
+        let end = match captures.get(index + 1) {
+            Some(next) => next.get(0)?.start(),
+            None => input.len(),
+        };
+        targets.push((capture.get(1)?.as_str(), input[start..end].trim()));
+    }
+    Some(targets)
+}
+
+/// the target for the running operating system
+/// plain targets apply everywhere, None if no target is given for this os
+pub fn resolve_os_target(input: &str) -> Option<&str> {
+    match os_targets(input) {
+        Some(targets) => targets
+            .into_iter()
+            .find(|(os, _)| *os == env::consts::OS)
+            .map(|(_, target)| target),
+        None => Some(input),
+    }
+}
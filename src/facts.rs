@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::read_to_string;
+
+// facts about the machine imosid is running on
+// used to decide which sections apply
+pub struct Facts {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub profile: Option<String>,
+}
+
+impl Facts {
+    pub fn gather() -> Facts {
+        Facts {
+            hostname: get_hostname(),
+            os: String::from(env::consts::OS),
+            arch: String::from(env::consts::ARCH),
+            profile: None,
+        }
+    }
+
+    // true if a comma separated list of profiles contains the active profile
+    pub fn matches_profile(&self, profiles: &Option<String>) -> bool {
+        match profiles {
+            None => true,
+            Some(profiles) => match &self.profile {
+                Some(active) => profiles.split(',').any(|profile| profile == active),
+                None => false,
+            },
+        }
+    }
+
+    // look up a fact by name, environment variables are available as env.NAME
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "hostname" => Some(self.hostname.clone()),
+            "os" => Some(self.os.clone()),
+            "arch" => Some(self.arch.clone()),
+            "profile" => self.profile.clone(),
+            _ => {
+                let varname = key.strip_prefix("env.")?;
+                env::var(varname).ok()
+            }
+        }
+    }
+}
+
+fn get_hostname() -> String {
+    // windows sets COMPUTERNAME instead
+    for variable in ["HOSTNAME", "COMPUTERNAME"] {
+        if let Ok(hostname) = env::var(variable) {
+            if !hostname.is_empty() {
+                return hostname;
+            }
+        }
+    }
+    for path in ["/proc/sys/kernel/hostname", "/etc/hostname"] {
+        if let Ok(hostname) = read_to_string(path) {
+            let hostname = hostname.trim();
+            if !hostname.is_empty() {
+                return String::from(hostname);
+            }
+        }
+    }
+    String::from("localhost")
+}
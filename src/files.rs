@@ -1,21 +1,144 @@
-use crate::comment::{CommentType, Specialcomment};
+use crate::built_info;
+use crate::comment::{CommentType, Specialcomment, COMMENT_SYNTAX_VERSION};
 use crate::commentmap::CommentMap;
-use crate::contentline::ContentLine;
-use crate::hashable::Hashable;
-use crate::metafile::MetaFile;
-use crate::section::{NamedSectionData, Section, SectionData};
+use crate::diagnostic::Diagnostic;
+use crate::expand::{expand_path_variables, expand_xdg_shorthand, resolve_os_target, user_home};
+use crate::facts::Facts;
+use crate::hashable::{HashAlgorithm, Hashable};
+use crate::languages::Languages;
+use crate::lockfile::LockFile;
+use crate::matcher::SectionMatcher;
+use crate::metafile::{link_target, MetaFile};
+use crate::permissions::{is_readonly, Permissions};
+use crate::privilege::{create_parent_dirs, needs_root, set_permissions, write_file, RootPolicy};
+use crate::section::{hashed_content, NamedSectionData, Placement, Section, SectionData};
+use crate::snapshot::{diff_snapshot, load_snapshot, store_snapshot};
 use colored::Colorize;
 use regex::Regex;
-use std::collections::HashMap;
+use semver::Version;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
 
 use std::io::prelude::*;
 use std::io::{self, ErrorKind};
-use std::ops::Deref;
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+#[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
-use std::path::{Path, PathBuf};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
 use std::string::String;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+pub const BOM: char = '\u{feff}';
+// ~ followed by the separator of the platform
+const HOME_PREFIX: &str = if cfg!(windows) { "~\\" } else { "~/" };
+// names tried before giving up on creating a temporary file
+const TMPFILE_ATTEMPTS: usize = 16;
+
+pub const BINARY_FILE_ERROR: &str = "binary or non utf-8 file, manage it with a metafile";
+const MALFORMED_FILE_ERROR: &str = "malformed imosid comments";
+
+pub fn imosid_version() -> Version {
+    Version::parse(built_info::PKG_VERSION).unwrap()
+}
+
+/// warn if a file was compiled by a newer imosid, it may use features this version ignores
+/// return true if it was
+pub fn check_imosid_version(filename: &str, version: &Version) -> bool {
+    let current = imosid_version();
+    if *version <= current {
+        return false;
+    }
+    warn!(
+        "{} {} was written by imosid {}, this is {}, newer features may be ignored",
+        "warning:".yellow().bold(),
+        filename,
+        version,
+        current
+    );
+    true
+}
+
+// text files are valid utf-8 and contain no null bytes
+pub fn is_text(bytes: &[u8]) -> bool {
+    !bytes.contains(&0) && std::str::from_utf8(bytes).is_ok()
+}
+
+// check if a file can be managed with comments
+pub fn is_text_file(path: &Path) -> io::Result<bool> {
+    Ok(is_text(&fs::read(path)?))
+}
+
+// line ending used when writing a file
+// content is kept with \n internally so hashes do not depend on it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    // detect the dominant line ending of content
+    pub fn detect(content: &str) -> LineEnding {
+        let crlf = content.matches("\r\n").count();
+        let lf = content.matches('\n').count() - crlf;
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    // convert content with \n line endings
+    pub fn apply(&self, content: &str) -> String {
+        match self {
+            LineEnding::Lf => String::from(content),
+            LineEnding::CrLf => content.replace('\n', "\r\n"),
+        }
+    }
+}
+
+// how imosid keeps track of the content of a file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ManagementState {
+    Unmanaged,       // no named sections and no metafile
+    CommentManaged,  // named sections between imosid comments
+    MetafileManaged, // whole content tracked by a .imosid.toml file
+}
+
+impl ManagementState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManagementState::Unmanaged => "unmanaged",
+            ManagementState::CommentManaged => "comments",
+            ManagementState::MetafileManaged => "metafile",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<ManagementState> {
+        match input {
+            "unmanaged" => Some(ManagementState::Unmanaged),
+            "comments" => Some(ManagementState::CommentManaged),
+            "metafile" => Some(ManagementState::MetafileManaged),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ManagementState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManagementState::Unmanaged => write!(f, "unmanaged"),
+            _ => write!(f, "managed by {}", self.as_str()),
+        }
+    }
+}
 
 pub enum ApplyResult {
     Changed,
@@ -23,17 +146,109 @@ pub enum ApplyResult {
     Error,
 }
 
+// what to do when a section changed in both source and target
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictStyle {
+    Skip,
+    Markers,
+}
+
+// what apply does with targets without write permission
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadonlyPolicy {
+    Error, // fail the file
+    Skip,  // leave the target alone and report it
+    Chmod, // write the target anyway, it stays read-only
+}
+
+// settings controlling how source files are applied to their targets
+pub struct ApplyOptions {
+    pub facts: Facts,
+    pub add_missing: bool,    // append source sections the target does not have
+    pub prune_sections: bool, // remove unmodified target sections the source no longer has
+    pub link: bool,           // symlink whole file targets to the source instead of copying
+    pub preserve_mtime: bool, // keep the modification time of rewritten targets
+    pub cache: bool,          // skip files unchanged since they were last applied
+    pub changed_only: bool,   // also skip them if only their targets changed since
+    pub merge: bool,          // merge source changes into locally modified target sections
+    pub conflict: ConflictStyle,
+    pub only_sections: SectionMatcher, // only apply these sections, all if empty
+    pub skip_sections: SectionMatcher, // never apply these sections
+    pub notify: bool,                  // send a desktop notification about changed targets
+    pub strict: bool,                  // refuse to apply files with malformed imosid comments
+    pub force: bool,                   // apply files with duplicate section names
+    pub root: RootPolicy,              // what to do with targets only root can write
+    pub readonly: ReadonlyPolicy,      // what to do with read-only targets
+}
+
+impl ApplyOptions {
+    pub fn new(profile: Option<String>) -> ApplyOptions {
+        let mut facts = Facts::gather();
+        facts.profile = profile;
+        ApplyOptions {
+            facts,
+            add_missing: false,
+            prune_sections: false,
+            link: false,
+            preserve_mtime: false,
+            cache: true,
+            changed_only: false,
+            merge: false,
+            conflict: ConflictStyle::Skip,
+            only_sections: SectionMatcher::default(),
+            skip_sections: SectionMatcher::default(),
+            notify: false,
+            strict: false,
+            force: false,
+            root: RootPolicy::Error,
+            readonly: ReadonlyPolicy::Error,
+        }
+    }
+
+    // true if section applies to this machine and is not filtered out
+    pub fn selects(&self, section: &Section) -> bool {
+        if !section.applies(&self.facts) {
+            return false;
+        }
+        match section {
+            Section::Named(_, named_data) => self.selects_name(&named_data.name),
+            Section::Anonymous(_) => true,
+        }
+    }
+
+    // true if a section or region called name is not filtered out
+    pub fn selects_name(&self, name: &str) -> bool {
+        self.only_sections.matches(name)
+            && (self.skip_sections.is_empty() || !self.skip_sections.matches(name))
+    }
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        ApplyOptions::new(None)
+    }
+}
+
 pub struct DotFile {
     //TODO maybe implement finalize?
     specialcomments: Vec<Specialcomment>,
     pub sections: Vec<Section>,
-    pub file: File,
     pub filename: String,
     pub targetfile: Option<String>,
     pub metafile: Option<MetaFile>,
     pub commentsign: String,
     pub modified: bool,
-    pub permissions: Option<u32>,
+    pub permissions: Option<Permissions>,
+    pub profile: Option<String>,
+    pub lineending: LineEnding,
+    pub bom: bool,                       // file starts with a utf-8 byte order mark
+    pub trailingnewline: bool,           // file ends with a newline
+    pub prehook: Option<String>,         // command to run before the target changes
+    pub posthook: Option<String>,        // command to run after the target changed
+    pub diagnostics: Vec<Diagnostic>,    // malformed imosid comments, treated as content
+    pub duplicate_sections: Vec<String>, // names of sections defined more than once
+    pub syntaxversion: u32,              // comment syntax version the file was written with
+    pub imosidversion: Option<Version>,  // imosid version which last compiled the file, if recorded
 }
 
 impl DotFile {
@@ -43,98 +258,218 @@ impl DotFile {
     }
 
     pub fn from_pathbuf(path: &PathBuf) -> Result<DotFile, std::io::Error> {
+        Self::read_pathbuf(path, false)
+    }
+
+    /// like from_pathbuf, but malformed imosid comments are errors as in from_str_strict
+    /// they are reported and the error is of kind InvalidInput
+    pub fn from_pathbuf_strict(path: &PathBuf) -> Result<DotFile, std::io::Error> {
+        Self::read_pathbuf(path, true)
+    }
+
+    fn read_pathbuf(path: &PathBuf, strict: bool) -> Result<DotFile, std::io::Error> {
         let sourcepath = path
             .canonicalize()
             .expect("could not canonicalize path")
             .display()
             .to_string();
 
-        let sourcefile = match OpenOptions::new().read(true).write(true).open(path) {
-            Err(e) => {
-                if e.kind() == ErrorKind::PermissionDenied {
-                    // open file as readonly if writing is not permitted
-                    // TODO: skip readonly files entirely
-                    match OpenOptions::new().read(true).write(false).open(path) {
-                        Ok(file) => file,
-                        Err(error) => return Err(error),
+        let bytes = fs::read(path)?;
+        let metapath = format!("{}.imosid.toml", sourcepath);
+
+        // check for metafile, metafiles manage the raw bytes of a file
+        if Path::new(&metapath).is_file() {
+            let metafile = match MetaFile::new(PathBuf::from(&metapath), &bytes) {
+                Ok(mut metafile) => {
+                    metafile.finalize();
+                    metafile
+                }
+                Err(e) => {
+                    return Err(io::Error::other(format!(
+                        "invalid metafile {}: {}",
+                        metapath, e
+                    )));
+                }
+            };
+            return Ok(DotFile {
+                specialcomments: Vec::new(),
+                sections: Vec::new(),
+                filename: sourcepath,
+                targetfile: metafile.targetfile.clone(),
+                modified: metafile.modified,
+                permissions: metafile.permissions.clone(),
+                profile: metafile.profile.clone(),
+                imosidversion: Some(metafile.imosidversion().clone()),
+                metafile: Some(metafile),
+                commentsign: String::from(""),
+                lineending: LineEnding::Lf,
+                bom: false,
+                trailingnewline: true,
+                prehook: None,
+                posthook: None,
+                diagnostics: Vec::new(),
+                duplicate_sections: Vec::new(),
+                syntaxversion: COMMENT_SYNTAX_VERSION,
+            });
+        }
+
+        // binary files can not be parsed for comments
+        if !is_text(&bytes) {
+            return Err(io::Error::new(ErrorKind::InvalidData, BINARY_FILE_ERROR));
+        }
+        let content = String::from_utf8(bytes)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, BINARY_FILE_ERROR))?;
+        let dotfile = if strict {
+            match DotFile::from_str_strict(&content, &sourcepath) {
+                Ok(dotfile) => dotfile,
+                Err(diagnostics) => {
+                    for diagnostic in &diagnostics {
+                        error!("{}", diagnostic.render(&sourcepath, &content));
                     }
-                } else {
-                    return Err(e);
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        MALFORMED_FILE_ERROR,
+                    ));
                 }
             }
-            Ok(file) => file,
+        } else {
+            DotFile::from_str(&content, &sourcepath)
         };
+        if let Some(version) = &dotfile.imosidversion {
+            check_imosid_version(&sourcepath, version);
+        }
+        // newer syntax may mean things this version would get wrong
+        if dotfile.syntaxversion > COMMENT_SYNTAX_VERSION {
+            return Err(io::Error::other(format!(
+                "{} uses comment syntax version {}, this imosid supports up to {}",
+                sourcepath, dotfile.syntaxversion, COMMENT_SYNTAX_VERSION
+            )));
+        }
+        Ok(dotfile)
+    }
+
+    /// upgrade the comments of the file to the current syntax version
+    /// markers are written in the current syntax when the file is written again
+    /// return false if the file is already up to date
+    pub fn migrate(&mut self) -> bool {
+        if let Some(metafile) = &mut self.metafile {
+            return metafile.migrate();
+        }
+        if self.syntaxversion >= COMMENT_SYNTAX_VERSION
+            || !self.is_managed()
+            || self.written_by_newer()
+        {
+            return false;
+        }
+        self.specialcomments
+            .retain(|comment| comment.comment_type != CommentType::SyntaxInfo);
+        self.specialcomments.push(Specialcomment {
+            line: 0,
+            section: String::from("all"),
+            comment_type: CommentType::SyntaxInfo,
+            argument: Some(COMMENT_SYNTAX_VERSION.to_string()),
+            indent: String::new(),
+        });
+        self.syntaxversion = COMMENT_SYNTAX_VERSION;
+        true
+    }
+
+    /// parse content which does not have to exist on disk
+    /// filename is used to guess the comment syntax and becomes the filename of the DotFile
+    pub fn from_str(content: &str, filename: &str) -> DotFile {
+        let dotfile = Self::parse(content, filename, None);
+        dotfile.report_diagnostics();
+        dotfile
+    }
+
+    /// parse content using commentsign instead of guessing the comment syntax
+    pub fn from_str_with_comment_sign(content: &str, filename: &str, commentsign: &str) -> DotFile {
+        let dotfile = Self::parse(content, filename, Some(commentsign));
+        dotfile.report_diagnostics();
+        dotfile
+    }
 
-        let metafile;
+    /// like from_str, but malformed imosid comments and incomplete sections are errors
+    /// instead of being treated as content
+    pub fn from_str_strict(content: &str, filename: &str) -> Result<DotFile, Vec<Diagnostic>> {
+        let dotfile = Self::parse(content, filename, None);
+        if dotfile.diagnostics.is_empty() {
+            Ok(dotfile)
+        } else {
+            Err(dotfile.diagnostics)
+        }
+    }
+
+    /// everything wrong with the imosid comments of content, without reporting it
+    pub fn diagnose(content: &str, filename: &str) -> Vec<Diagnostic> {
+        Self::parse(content, filename, None).diagnostics
+    }
 
+    fn report_diagnostics(&self) {
+        for diagnostic in &self.diagnostics {
+            warn!(
+                "{} {}: {}, treating it as content",
+                "warning:".yellow().bold(),
+                self.filename,
+                diagnostic
+            );
+        }
+    }
+
+    fn parse(content: &str, filename: &str, knowncommentsign: Option<&str>) -> DotFile {
         let mut comments = Vec::new();
         let mut line_counter = 0;
 
         let mut sections: Vec<Section> = Vec::new();
-        let mut lines: Vec<ContentLine> = Vec::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
 
         let mut comment_map: CommentMap = CommentMap::new();
-        let mut section_map: HashMap<String, Vec<Specialcomment>> = HashMap::new();
 
         let mut target_file: Option<String> = Option::None;
         let mut permissions = Option::None;
-        let mut commentsign = String::new();
-        let mut hascommentsign = false;
-
-        // check for metafile
-        if Path::new(&format!("{}.imosid.toml", sourcepath)).is_file() {
-            let mut content = String::new();
-            io::BufReader::new(&sourcefile).read_to_string(&mut content)?;
-
-            metafile = if let Some(mut metafile) = MetaFile::new(
-                PathBuf::from(&format!("{}.imosid.toml", sourcepath)),
-                &content,
-            ) {
-                metafile.finalize();
-                metafile
-            } else {
-                return Err(std::io::Error::new(ErrorKind::Other, "invalid metafile"));
-            };
-            return Ok(DotFile {
-                specialcomments: comments,
-                sections,
-                file: sourcefile,
-                filename: sourcepath,
-                targetfile: metafile.targetfile.clone(),
-                modified: metafile.modified,
-                permissions: metafile.permissions.clone(),
-                metafile: Some(metafile),
-                commentsign: String::from(""),
-            });
-        }
+        let mut profile: Option<String> = Option::None;
+
+        let lineending = LineEnding::detect(content);
+        let bom = content.starts_with(BOM);
+        let content = content.strip_prefix(BOM).unwrap_or(content);
+        let trailingnewline = content.is_empty() || content.ends_with('\n');
+        let commentsign = match knowncommentsign {
+            Some(commentsign) => String::from(commentsign),
+            None => get_comment_sign(filename, content),
+        };
 
-        let filelines = io::BufReader::new(&sourcefile).lines();
         // parse lines for special comments
-        for i in filelines {
+        // content lines are not copied here, they are read again when filling sections
+        for line in content.lines() {
             line_counter += 1;
-            let line = i?;
-            // TODO: Do this better
-            if !hascommentsign {
-                commentsign = String::from(get_comment_sign(&sourcepath, &line));
-                hascommentsign = true;
+            // formats without comments have no special comments
+            if commentsign.is_empty() {
+                continue;
             }
-
-            let newcomment = Specialcomment::from_line(&line, &commentsign, line_counter);
-            match newcomment {
-                Some(comment) => {
+            match Specialcomment::parse_line(line, &commentsign, line_counter) {
+                Ok(Some(comment)) => {
                     // comments with section all apply to the entire file
-                    //TODO: move checking into comment from_line
                     comment_map.push_comment(comment.clone());
-                    comments.push(comment.clone());
+                    comments.push(comment);
                 }
-                None => lines.push(ContentLine {
-                    linenumber: line_counter,
-                    content: line,
-                }),
+                Ok(None) => {}
+                Err(diagnostic) => diagnostics.push(diagnostic),
             }
         }
 
-        comment_map.remove_incomplete();
+        // sections defined more than once can not be told apart, none of them are used
+        let mut duplicate_sections: Vec<String> = Vec::new();
+        let mut defined: HashSet<&str> = HashSet::new();
+        for comment in &comments {
+            if comment.comment_type == CommentType::SectionBegin
+                && !defined.insert(&comment.section)
+                && !duplicate_sections.contains(&comment.section)
+            {
+                duplicate_sections.push(comment.section.clone());
+            }
+        }
+
+        diagnostics.extend(comment_map.remove_incomplete());
 
         if let Some(comment) = comment_map.get_comment("all", CommentType::TargetInfo) {
             if let Some(arg) = &comment.argument {
@@ -143,13 +478,27 @@ impl DotFile {
         }
         if let Some(comment) = comment_map.get_comment("all", CommentType::PermissionInfo) {
             if let Some(arg) = &comment.argument {
-                permissions = match arg.split_at(3).1.parse::<u32>() {
-                    Err(_) => Option::None,
-                    Ok(permnumber) => Option::Some(permnumber),
-                }
+                permissions = Permissions::parse(arg);
             }
         }
 
+        if let Some(comment) = comment_map.get_comment("all", CommentType::ProfileInfo) {
+            profile = comment.argument.clone();
+        }
+        let prehook = comment_map
+            .get_comment("all", CommentType::PreHookInfo)
+            .and_then(|comment| comment.argument.clone());
+        let posthook = comment_map
+            .get_comment("all", CommentType::PostHookInfo)
+            .and_then(|comment| comment.argument.clone());
+        let syntaxversion = comment_map
+            .get_comment("all", CommentType::SyntaxInfo)
+            .and_then(|comment| comment.argument.as_ref()?.parse().ok())
+            .unwrap_or(1);
+        let imosidversion = comment_map
+            .get_comment("all", CommentType::VersionInfo)
+            .and_then(|comment| Version::parse(comment.argument.as_ref()?).ok());
+
         for sectionname in comment_map.get_sections() {
             Section::from_comment_map(sectionname, &comment_map).map(|section| {
                 sections.push(section);
@@ -159,32 +508,23 @@ impl DotFile {
         // sort sections by lines (retaining the original order of the file)
         sections.sort_by(|a, b| a.get_data().startline.cmp(&b.get_data().startline));
 
-        // detect overlapping sections
-        let vecsize = sections.len();
-        let mut broken_indices = Vec::new();
-        let mut skipnext = false;
-        for i in 0..vecsize {
-            if skipnext {
-                skipnext = false;
-                continue;
-            }
-            let currentsection = &sections[i];
-            if i < vecsize - 1 {
-                let nextsection = &sections[i + 1];
-                if nextsection.get_data().startline < currentsection.get_data().endline {
-                    broken_indices.push(i + 1);
-                    broken_indices.push(i);
-                    skipnext = true;
-                }
-            }
-        }
+        let mut sections = demote_overlapping(sections, &mut diagnostics);
+
+        // markers of sections which were dropped stay in the file as content
+        let keptsections: HashSet<String> = sections
+            .iter()
+            .filter_map(|section| match section {
+                Section::Named(_, named_data) => Some(named_data.name.clone()),
+                Section::Anonymous(_) => None,
+            })
+            .collect();
+        let commentlines: Vec<u32> = comments
+            .iter()
+            .filter(|comment| comment.section == "all" || keptsections.contains(&comment.section))
+            .map(|comment| comment.line)
+            .collect();
 
-        for i in broken_indices {
-            println!("section {} overlapping", i);
-            sections.remove(i);
-        }
-
-        let modified = false;
+        let mut modified = false;
         // introduce anonymous sections
         if sections.len() > 0 {
             let mut currentline = 1;
@@ -200,43 +540,69 @@ impl DotFile {
                 }
                 currentline = i.get_data().endline + 1;
             }
+            // content after the last section
+            if currentline <= line_counter {
+                anonymous_sections.push(Section::new_anonymous(currentline, line_counter));
+            }
 
             sections.extend(anonymous_sections);
             sections.sort_by(|a, b| a.get_data().startline.cmp(&b.get_data().startline));
         } else {
             // make the entire file one anonymous section
-            let newsection = Section::new_anonymous(1, lines.len() as u32);
+            let newsection = Section::new_anonymous(1, line_counter);
             sections.push(newsection);
         }
 
-        // fill sections with content
-        for i in &mut sections {
-            // TODO: speed this up, binary search or something
-            for c in &lines {
-                if c.linenumber > i.get_data().endline {
-                    break;
-                } else if c.linenumber < i.get_data().startline {
-                    continue;
+        // fill sections with content in a single pass over the file
+        // sections are sorted and do not overlap, so the current section only moves forward
+        let mut commentlines = commentlines.into_iter().peekable();
+        let mut current = 0;
+        for (linenumber, line) in (1..).zip(content.lines()) {
+            if commentlines.peek() == Some(&linenumber) {
+                commentlines.next();
+                continue;
+            }
+            while current < sections.len() && sections[current].get_data().endline < linenumber {
+                current += 1;
+            }
+            match sections.get_mut(current) {
+                Some(section) if section.get_data().startline <= linenumber => {
+                    section.push_line(line)
                 }
-                i.push_line(&c.content);
+                _ => {}
             }
+        }
+        for i in &mut sections {
             i.finalize();
-            //TODO: deal with "modified" variable
+            if let Section::Named(_, named_data) = i {
+                if named_data.hash != named_data.targethash {
+                    modified = true;
+                }
+            }
         }
 
-        let retfile = DotFile {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+        DotFile {
             specialcomments: comments,
             sections,
-            file: sourcefile,
-            filename: sourcepath,
+            filename: String::from(filename),
             targetfile: target_file,
             commentsign,
+            lineending,
+            bom,
+            trailingnewline,
+            prehook,
+            posthook,
             metafile: None,
             modified,
             permissions,
-        };
-
-        return Ok(retfile);
+            profile,
+            diagnostics,
+            duplicate_sections,
+            syntaxversion,
+            imosidversion,
+        }
     }
 
     fn get_named_sections(&self) -> Vec<(&SectionData, &NamedSectionData)> {
@@ -249,6 +615,21 @@ impl DotFile {
         return retvec;
     }
 
+    // names of the named sections, or of the regions of a metafile
+    pub fn section_names(&self) -> Vec<&str> {
+        if let Some(metafile) = &self.metafile {
+            return metafile
+                .regions
+                .iter()
+                .map(|region| region.name.as_str())
+                .collect();
+        }
+        self.get_named_sections()
+            .iter()
+            .map(|(_, named_data)| named_data.name.as_str())
+            .collect()
+    }
+
     pub fn count_named_sections(&self) -> u32 {
         let mut counter = 0;
         for i in &self.sections {
@@ -259,18 +640,37 @@ impl DotFile {
         counter
     }
 
-    pub fn is_managed(&self) -> bool {
+    pub fn management_state(&self) -> ManagementState {
         if self.metafile.is_some() {
-            return true;
-        }
-        if !self.is_anonymous() {
-            return true;
+            ManagementState::MetafileManaged
+        } else if self.count_named_sections() > 0 {
+            ManagementState::CommentManaged
+        } else {
+            ManagementState::Unmanaged
         }
-        return false;
+    }
+
+    pub fn is_managed(&self) -> bool {
+        self.management_state() != ManagementState::Unmanaged
+    }
+
+    /// changes of modified sections since they were compiled or applied
+    /// the diff is None if there is no snapshot of the section to compare with
+    pub fn section_diffs(&self) -> Vec<(String, Option<String>)> {
+        self.get_named_sections()
+            .into_iter()
+            .filter(|(_, named_data)| named_data.hash != named_data.targethash)
+            .map(|(data, named_data)| {
+                (
+                    named_data.name.clone(),
+                    diff_snapshot(&named_data.targethash, &data.content),
+                )
+            })
+            .collect()
     }
 
     pub fn pretty_info(&self) -> String {
-        let mut retstring = String::new();
+        let mut retstring = format!("state: {}\n", self.management_state().to_string().bold());
         match &self.metafile {
             Some(metafile) => {
                 retstring.push_str(&metafile.pretty_info());
@@ -285,7 +685,7 @@ impl DotFile {
                 }
             }
         };
-        if let Some(permissions) = self.permissions {
+        if let Some(permissions) = &self.permissions {
             retstring.push_str(&format!(
                 "target permissions: {}\n",
                 permissions.to_string().bold()
@@ -299,48 +699,114 @@ impl DotFile {
         return retstring;
     }
 
-    pub fn update(&mut self) {
+    // refresh sections from their sources
+    // only sections in filter are refreshed unless it is empty
+    // offline only uses remote sources which were fetched before
+    // remote sources are resolved through lockfile
+    // return true if anything changed
+    pub fn update(
+        &mut self,
+        filter: &SectionMatcher,
+        offline: bool,
+        lockfile: &mut LockFile,
+    ) -> bool {
         //iterate over sections in self.sections
 
         let mut modified = false;
         let mut applymap: HashMap<&String, DotFile> = HashMap::new();
         let mut source_sections = Vec::new();
+        if self
+            .metafile
+            .as_ref()
+            .is_some_and(|m| !m.regions.is_empty())
+        {
+            return self.update_regions(filter, offline, lockfile);
+        }
         if self.metafile.is_some() {
+            if !filter.is_empty() {
+                error!("cannot update individual sections of file managed by metafile");
+                return false;
+            }
             let metafile = &self.metafile.as_ref().unwrap();
             if metafile.modified {
-                return;
+                return false;
             }
             if !metafile.sourcefile.is_some() {
-                return;
+                return false;
             }
             //TODO look up what as_ref does
             match DotFile::new(&metafile.sourcefile.as_ref().unwrap()) {
                 Ok(file) => {
-                    modified = self.applyfile(&file);
+                    modified = self.applyfile(&file, &file.sections, &ApplyOptions::default());
                 }
                 Err(e) => {
-                    println!("failed to apply metafile sourfe, error: {}", e);
+                    error!("failed to apply metafile source, error: {}", e);
                 }
             }
-            return;
+            return modified;
+        }
+
+        for pattern in filter.unmatched(&self.section_names()) {
+            error!("could not find section {}", pattern.red());
         }
 
         for section in &self.sections {
             if let Section::Named(_, named_data) = section {
-                if let Some(source) = &named_data.source {
-                    if !applymap.contains_key(source) {
-                        match DotFile::new(source) {
-                            Ok(sfile) => {
-                                applymap.insert(source, sfile);
-                            }
-                            Err(_) => {
-                                println!("error: could not open source file {}", source);
-                                continue;
-                            }
+                if !filter.matches(&named_data.name) {
+                    continue;
+                }
+                let source = match &named_data.source {
+                    Some(source) => source,
+                    None => {
+                        // only worth mentioning if the section was asked for
+                        if !filter.is_empty() {
+                            warn!(
+                                "{}",
+                                format!("section {} has no source", &named_data.name).yellow()
+                            );
                         }
+                        continue;
                     }
-                    if let Some(sfile) = applymap.get(source) {
-                        source_sections.push(sfile.clone().get_section(source).unwrap());
+                };
+                if !applymap.contains_key(source) {
+                    // remote sources are fetched and checked against their pin first
+                    let sourcepath = match lockfile.resolve(source, offline) {
+                        Some(sourcepath) => sourcepath,
+                        None => {
+                            error!(
+                                "{}",
+                                format!(
+                                    "not updating section {} from {}",
+                                    &named_data.name, source
+                                )
+                                .red()
+                                .bold()
+                            );
+                            continue;
+                        }
+                    };
+                    match DotFile::new(sourcepath.to_str().unwrap()) {
+                        Ok(sfile) => {
+                            applymap.insert(source, sfile);
+                        }
+                        Err(_) => {
+                            error!("could not open source file {}", source);
+                            continue;
+                        }
+                    }
+                }
+                if let Some(sfile) = applymap.get(source) {
+                    // sections are written to targets the same way apply does
+                    match sfile
+                        .get_section(&named_data.name)
+                        .and_then(|section| section.render())
+                    {
+                        Some(sourcesection) => source_sections.push(sourcesection),
+                        None => warn!(
+                            "{}",
+                            format!("source file {} has no section {}", source, &named_data.name)
+                                .yellow()
+                        ),
                     }
                 }
             }
@@ -348,12 +814,14 @@ impl DotFile {
 
         for applysection in source_sections.iter() {
             if let Section::Named(data, named_data) = applysection.clone() {
-                self.applysection(data, named_data);
+                modified =
+                    self.applysection(data, named_data, &ApplyOptions::default()) || modified;
             }
         }
+        modified
     }
 
-    fn get_section(&self, name: &str) -> Option<Section> {
+    pub fn get_section(&self, name: &str) -> Option<Section> {
         for i in &self.sections {
             if let Section::Named(_, named_data) = i {
                 if named_data.name == name {
@@ -371,13 +839,301 @@ impl DotFile {
             _ => false,
         }) {
             self.sections.remove(index);
-            println!("deleting section {}", sectionname);
             return true;
         } else {
             return false;
         }
     }
 
+    // refresh regions of a metafile from their sources
+    // a region source is a file whose whole content is the region
+    fn update_regions(
+        &mut self,
+        filter: &SectionMatcher,
+        offline: bool,
+        lockfile: &mut LockFile,
+    ) -> bool {
+        for pattern in filter.unmatched(&self.section_names()) {
+            error!("could not find region {}", pattern.red());
+        }
+        let metafile = self.metafile.as_mut().unwrap();
+        let mut modified = false;
+        for region in metafile.regions.clone() {
+            if !filter.matches(&region.name) {
+                continue;
+            }
+            let source = match &region.source {
+                Some(source) => source,
+                None => {
+                    if !filter.is_empty() {
+                        warn!(
+                            "{}",
+                            format!("region {} has no source", &region.name).yellow()
+                        );
+                    }
+                    continue;
+                }
+            };
+            if region.modified {
+                warn!(
+                    "{}",
+                    format!("region {} modified, skipping", &region.name).yellow()
+                );
+                continue;
+            }
+            let content = match lockfile
+                .resolve(source, offline)
+                .and_then(|path| fs::read_to_string(path).ok())
+            {
+                Some(content) => content,
+                None => {
+                    error!(
+                        "{}",
+                        format!("not updating region {} from {}", &region.name, source).red()
+                    );
+                    continue;
+                }
+            };
+            if metafile.region_content(&region.name).as_deref() == Some(content.as_str()) {
+                continue;
+            }
+            if metafile.replace_region(&region.name, &content) {
+                modified = true;
+            }
+        }
+        modified
+    }
+
+    // wrap lines from..=to into a new compiled section and write the file
+    pub fn wrap_lines(&mut self, name: &str, from: u32, to: u32) -> bool {
+        if self.metafile.is_some() {
+            error!("cannot add sections to file managed by metafile");
+            return false;
+        }
+        if self.has_section(name) {
+            error!("section {} already exists", name.red());
+            return false;
+        }
+        for (data, named_data) in self.get_named_sections() {
+            if from <= data.endline && to >= data.startline {
+                error!(
+                    "lines {}-{} overlap section {}",
+                    from,
+                    to,
+                    named_data.name.red()
+                );
+                return false;
+            }
+        }
+
+        let content = match fs::read_to_string(&self.filename) {
+            Ok(content) => content,
+            Err(_) => {
+                error!("could not read file {}", &self.filename.red());
+                return false;
+            }
+        };
+        let content = content.strip_prefix(BOM).unwrap_or(&content);
+        let lines: Vec<&str> = content.lines().collect();
+        if from < 1 || from > to || to as usize > lines.len() {
+            error!("invalid line range {}-{}", from, to);
+            return false;
+        }
+
+        let mut section = Section::new(from, to, name.to_string(), None, String::new());
+        for line in &lines[(from - 1) as usize..to as usize] {
+            if !matches!(
+                Specialcomment::parse_line(line, &self.commentsign, 0),
+                Ok(None)
+            ) {
+                error!("cannot wrap imosid comment {}", line.red());
+                return false;
+            }
+            section.push_line(line);
+        }
+        section.finalize();
+        section.compile();
+
+        let mut newcontent = String::new();
+        for line in &lines[..(from - 1) as usize] {
+            newcontent.push_str(&format!("{}\n", line));
+        }
+        newcontent.push_str(&section.output(&self.commentsign));
+        for line in &lines[to as usize..] {
+            newcontent.push_str(&format!("{}\n", line));
+        }
+        let newcontent = self.finish_output(newcontent);
+        if write_atomic(Path::new(&self.filename), newcontent.as_bytes()).is_err() {
+            error!("could not write to file {}", &self.filename.red());
+            return false;
+        }
+
+        match DotFile::new(&self.filename) {
+            Ok(file) => {
+                *self = file;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // first and last line of the file matching pattern
+    pub fn find_match_range(&self, pattern: &Regex) -> Option<(u32, u32)> {
+        let content = fs::read_to_string(&self.filename).ok()?;
+        let mut range: Option<(u32, u32)> = None;
+        for (index, line) in content.lines().enumerate() {
+            if pattern.is_match(line) {
+                let linenumber = index as u32 + 1;
+                range = match range {
+                    None => Some((linenumber, linenumber)),
+                    Some((start, _)) => Some((start, linenumber)),
+                };
+            }
+        }
+        range
+    }
+
+    // rename section, the content hash stays the same
+    // return false if the section does not exist or the new name is taken
+    pub fn renamesection(&mut self, from: &str, to: &str) -> bool {
+        if self.has_section(to) {
+            return false;
+        }
+        for section in &mut self.sections {
+            if let Section::Named(_, named_data) = section {
+                if named_data.name == from {
+                    named_data.name = String::from(to);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // replace the content of a section, it is modified unless compiled
+    // return false if the section does not exist
+    pub fn set_section_content(&mut self, name: &str, content: &str) -> bool {
+        for section in &mut self.sections {
+            if let Section::Named(data, named_data) = section {
+                if named_data.name == name {
+                    data.content = String::from(content);
+                    section.finalize();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// put back the content sections had when they were applied with hashes
+    /// sections with local changes are only overwritten if force is set
+    /// return false if no section was restored
+    pub fn rollback(&mut self, hashes: &[(String, String)], force: bool) -> bool {
+        if self.metafile.is_some() {
+            warn!("files managed by metafiles cannot be rolled back");
+            return false;
+        }
+        let mut restored = false;
+        for (name, hash) in hashes {
+            let content = match load_snapshot(hash) {
+                Some(content) => content,
+                None => {
+                    warn!("no snapshot of section {}, skipping", name.yellow());
+                    continue;
+                }
+            };
+            let section = self.sections.iter_mut().find(|section| {
+                matches!(section, Section::Named(_, named_data) if &named_data.name == name)
+            });
+            let section = match section {
+                Some(section) => section,
+                None => {
+                    warn!(
+                        "section {} is not in {}, skipping",
+                        name.yellow(),
+                        self.filename
+                    );
+                    continue;
+                }
+            };
+            if let Section::Named(data, named_data) = section {
+                if &named_data.targethash == hash && data.content == content {
+                    continue;
+                }
+                if named_data.hash != named_data.targethash && !force {
+                    warn!("section {} modified, skipping", name.yellow());
+                    continue;
+                }
+                data.content = content;
+                named_data.targethash = hash.clone();
+            }
+            section.finalize();
+            restored = true;
+        }
+        restored
+    }
+
+    // set target and source of a section, arguments which are None are left alone
+    // return false if the section does not exist
+    pub fn set_section_info(
+        &mut self,
+        name: &str,
+        target: Option<&str>,
+        source: Option<&str>,
+    ) -> bool {
+        for section in &mut self.sections {
+            if let Section::Named(_, named_data) = section {
+                if named_data.name == name {
+                    if let Some(target) = target {
+                        named_data.target = Some(String::from(target));
+                    }
+                    if let Some(source) = source {
+                        named_data.source = Some(String::from(source));
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // set properties of the whole file, arguments which are None are left alone
+    // only metafiles have a source for the whole file, return false if it cannot be set
+    pub fn set_file_info(
+        &mut self,
+        target: Option<&str>,
+        permissions: Option<Permissions>,
+        source: Option<&str>,
+    ) -> bool {
+        if source.is_some() && self.metafile.is_none() {
+            return false;
+        }
+        if let Some(target) = target {
+            self.targetfile = Some(String::from(target));
+        }
+        if permissions.is_some() {
+            self.permissions = permissions;
+        }
+        if let Some(metafile) = &mut self.metafile {
+            metafile.targetfile = self.targetfile.clone();
+            metafile.permissions = self.permissions.clone();
+            if let Some(source) = source {
+                metafile.sourcefile = Some(String::from(source));
+            }
+        }
+        true
+    }
+
+    // target a section gets applied to
+    pub fn get_section_target(&self, name: &str) -> Option<String> {
+        for (_, named_data) in self.get_named_sections() {
+            if named_data.name == name {
+                return named_data.target.clone().or(self.targetfile.clone());
+            }
+        }
+        None
+    }
+
     //TODO: changedstatus
     pub fn compile(&mut self) -> bool {
         let mut didsomething = false;
@@ -386,168 +1142,614 @@ impl DotFile {
                 for i in 0..self.sections.len() {
                     didsomething = self.sections[i].compile().into() || didsomething;
                 }
+                if didsomething {
+                    self.record_version();
+                }
+                // the compiled content is what later modifications are compared to
+                self.snapshot_sections();
+            }
+            Some(metafile) => {
+                didsomething = metafile.compile().into();
+            }
+        }
+        didsomething
+    }
+
+    /// true if the file was last compiled by a newer imosid than this one
+    pub fn written_by_newer(&self) -> bool {
+        self.imosidversion
+            .as_ref()
+            .is_some_and(|version| *version > imosid_version())
+    }
+
+    // files which record the compiling imosid version get the current one
+    // versions of newer imosids are kept, so they still know the file is theirs
+    fn record_version(&mut self) {
+        let current = imosid_version();
+        if self
+            .imosidversion
+            .as_ref()
+            .is_none_or(|version| *version >= current)
+        {
+            return;
+        }
+        for comment in &mut self.specialcomments {
+            if comment.comment_type == CommentType::VersionInfo {
+                comment.argument = Some(current.to_string());
+            }
+        }
+        self.imosidversion = Some(current);
+    }
+
+    // return false if the file could not be written
+    pub fn write_to_file(&mut self) -> bool {
+        self.write_with_policy(RootPolicy::Error)
+    }
+
+    // like write_to_file, through sudo if root is Sudo and only root can write the file
+    pub fn write_with_policy(&mut self, root: RootPolicy) -> bool {
+        let targetname = &expand_tilde(&self.filename);
+        let content = match &self.metafile {
+            None => self.to_string().into_bytes(),
+            Some(metafile) => metafile.content.clone(),
+        };
+        if let Err(e) = write_file(Path::new(targetname), &content, root) {
+            error!("could not write to file {}: {}", &self.filename.red(), e);
+            return false;
+        }
+        if let Some(metafile) = &mut self.metafile {
+            metafile.write_with_policy(root);
+        }
+
+        if let Some(permissions) = &self.permissions {
+            if let Err(e) = set_permissions(Path::new(targetname), permissions, root) {
+                error!(
+                    "could not set permissions {} on {}: {}",
+                    permissions,
+                    targetname.red(),
+                    e
+                );
+            }
+        }
+        true
+    }
+
+    // write an existing target, mode and owner are kept by write_to_file
+    // unless the file specifies permissions
+    // return false if it could not be written
+    fn write_target(&mut self, options: &ApplyOptions) -> bool {
+        let targetname = expand_tilde(&self.filename);
+        let mtime = fs::metadata(&targetname)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if !self.write_with_policy(options.root) {
+            return false;
+        }
+        self.snapshot_sections();
+        if !options.preserve_mtime {
+            return true;
+        }
+        if let Some(mtime) = mtime {
+            let restored = OpenOptions::new()
+                .write(true)
+                .open(&targetname)
+                .and_then(|file| file.set_modified(mtime));
+            if restored.is_err() {
+                error!(
+                    "could not restore modification time of {}",
+                    targetname.red()
+                );
+            }
+        }
+        true
+    }
+
+    // remember the content of unmodified sections as the base for later merges
+    fn snapshot_sections(&self) {
+        for (data, named_data) in self.get_named_sections() {
+            if named_data.hash == named_data.targethash {
+                store_snapshot(&named_data.hash, &data.content);
+            }
+        }
+    }
+
+    // create the target file if not existing
+    // sections are the rendered sections of source
+    // TODO: result
+    pub fn create_file(
+        source: &DotFile,
+        sections: &[Section],
+        options: &ApplyOptions,
+    ) -> ApplyResult {
+        let targetpath = String::from(source.targetfile.clone().unwrap());
+        let realtargetpath = expand_tilde(&targetpath);
+        // create new file
+        match &source.metafile {
+            None => {
+                // sections with their own target do not belong in the file target
+                let sections = sections
+                    .iter()
+                    .filter(|section| match section {
+                        Section::Named(_, named_data) => named_data.target.is_none(),
+                        Section::Anonymous(_) => true,
+                    })
+                    .filter(|section| options.selects(section))
+                    .cloned()
+                    .collect();
+                let mut targetfile: DotFile = DotFile {
+                    specialcomments: source.specialcomments.clone(),
+                    sections,
+                    filename: realtargetpath.clone(),
+                    targetfile: Option::Some(targetpath),
+                    commentsign: source.commentsign.clone(),
+                    lineending: source.lineending,
+                    bom: source.bom,
+                    trailingnewline: source.trailingnewline,
+                    prehook: source.prehook.clone(),
+                    posthook: source.posthook.clone(),
+                    metafile: None,
+                    modified: source.modified,
+                    permissions: source.permissions.clone(),
+                    profile: source.profile.clone(),
+                    diagnostics: Vec::new(),
+                    duplicate_sections: Vec::new(),
+                    syntaxversion: source.syntaxversion,
+                    imosidversion: source.imosidversion.clone(),
+                };
+                if !targetfile.write_with_policy(options.root) {
+                    return ApplyResult::Error;
+                }
+                targetfile.snapshot_sections();
+                ApplyResult::Changed
+            }
+            Some(metafile) => {
+                if metafile.modified {
+                    warn!(
+                        "{}",
+                        format!("{} modified, skipping", &source.filename).yellow()
+                    );
+                    return ApplyResult::Unchanged;
+                }
+                if let Err(e) =
+                    write_file(Path::new(&realtargetpath), &metafile.content, options.root)
+                {
+                    error!("could not write file {}: {}", &targetpath.red(), e);
+                    return ApplyResult::Error;
+                }
+                let mut newmetafile =
+                    MetaFile::from_with_policy(PathBuf::from(&realtargetpath), options.root);
+                newmetafile.sourcefile = Some(source.filename.clone());
+                newmetafile.permissions = metafile.permissions.clone();
+                newmetafile.regions = metafile.regions.clone();
+                newmetafile.write_with_policy(options.root);
+                newmetafile.write_permissions(options.root);
+                ApplyResult::Changed
+            }
+        }
+    }
+
+    // apply to all targets, adding the ones which changed to changes
+    pub fn apply(&self, options: &ApplyOptions, changes: &mut Vec<String>) -> ApplyResult {
+        let mut donesomething = false;
+        if options.strict && self.written_by_newer() {
+            error!(
+                "{} {}",
+                &self.filename.red(),
+                "was written by a newer imosid, not applying".red()
+            );
+            return ApplyResult::Error;
+        }
+        if options.strict && !self.diagnostics.is_empty() {
+            error!(
+                "{} {}",
+                &self.filename.red(),
+                "has malformed imosid comments, not applying".red()
+            );
+            return ApplyResult::Error;
+        }
+        // applying would leave the duplicates out of the target, they are treated as content
+        if !options.force && !self.duplicate_sections.is_empty() {
+            error!(
+                "{} {} {}, use --force to apply anyway",
+                &self.filename.red(),
+                "defines sections more than once:".red(),
+                self.duplicate_sections.join(", ").bold()
+            );
+            return ApplyResult::Error;
+        }
+        if !options.facts.matches_profile(&self.profile) {
+            info!(
+                "{} {}",
+                &self.filename.dimmed(),
+                "not part of active profile, skipping".dimmed()
+            );
+            return ApplyResult::Unchanged;
+        }
+        // files without any of the selected sections are left alone
+        if !options.only_sections.is_empty()
+            && !self
+                .section_names()
+                .iter()
+                .any(|name| options.only_sections.matches(name))
+        {
+            return ApplyResult::Unchanged;
+        }
+        // targets for other operating systems are left alone
+        let filetarget = self
+            .targetfile
+            .as_ref()
+            .filter(|target| resolve_os_target(target).is_some());
+        if options.link {
+            if let Some(target) = filetarget {
+                if self.can_link() && !needs_root(Path::new(&expand_tilde(target))) {
+                    let result = self.link_target(target, options);
+                    if let ApplyResult::Changed = result {
+                        changes.push(expand_tilde(target));
+                    }
+                    return result;
+                }
+                warn!(
+                    "{} {}",
+                    &self.filename.yellow(),
+                    "cannot be linked, copying instead".yellow()
+                );
+            }
+        }
+        // sections which cannot be rendered would be left out of the targets
+        // the rest are rendered only once, decrypting may ask for a passphrase
+        let sections = match self.render_sections(options) {
+            Some(sections) => sections,
+            None => {
+                error!(
+                    "{} {}",
+                    &self.filename.red(),
+                    "has sections which could not be rendered, not applying".red()
+                );
+                return ApplyResult::Error;
+            }
+        };
+        let section_targets = get_section_targets(&sections, options);
+        for (target, sections) in &section_targets {
+            match self.apply_section_target(target, sections, options) {
+                ApplyResult::Changed => {
+                    donesomething = true;
+                    changes.push(expand_tilde(target));
+                }
+                ApplyResult::Unchanged => {}
+                ApplyResult::Error => return ApplyResult::Error,
+            }
+        }
+        if let Some(target) = filetarget {
+            match self.apply_file_target(target, &sections, options) {
+                ApplyResult::Changed => {
+                    donesomething = true;
+                    changes.push(expand_tilde(target));
+                }
+                ApplyResult::Unchanged => {}
+                ApplyResult::Error => return ApplyResult::Error,
+            }
+        } else if self.targetfile.is_some() {
+            debug!(
+                "{} has no target on {}, skipping",
+                &self.filename,
+                env::consts::OS
+            );
+        } else if section_targets.is_empty() {
+            info!("{} has no target file", &self.filename.red());
+            return ApplyResult::Error;
+        }
+        if donesomething {
+            return ApplyResult::Changed;
+        } else {
+            return ApplyResult::Unchanged;
+        }
+    }
+
+    // only files which are managed as a whole can be symlinked
+    fn can_link(&self) -> bool {
+        if self.metafile.is_some() {
+            return true;
+        }
+        if self.count_named_sections() != 1 {
+            return false;
+        }
+        self.sections.iter().all(|section| match section {
+            Section::Named(_, named_data) => {
+                named_data.target.is_none()
+                    && named_data.condition.is_none()
+                    && named_data.profile.is_none()
+                    && !named_data.expand
+                    && !named_data.template
+            }
+            Section::Anonymous(data) => data.content.trim().is_empty(),
+        })
+    }
+
+    // replace the target with a symlink to this file
+    fn link_target(&self, target: &str, options: &ApplyOptions) -> ApplyResult {
+        let realtarget = expand_tilde(target);
+        let targetpath = Path::new(&realtarget);
+        let sourcepath = Path::new(&self.filename);
+
+        if let Ok(linkdestination) = fs::read_link(targetpath) {
+            if linkdestination == sourcepath {
+                return ApplyResult::Unchanged;
+            }
+        }
+        if let Some(result) = check_target(target, options) {
+            return result;
+        }
+
+        if targetpath.is_symlink() {
+            if fs::remove_file(targetpath).is_err() {
+                error!("could not remove link {}", target.red());
+                return ApplyResult::Error;
+            }
+        } else if targetpath.exists() {
+            // never replace unmanaged targets or targets with local changes
+            match DotFile::new(&realtarget) {
+                Ok(targetfile) if targetfile.is_managed() && !targetfile.modified => {}
+                _ => {
+                    warn!(
+                        "{}",
+                        format!("target {} modified, skipping", target).yellow()
+                    );
+                    return ApplyResult::Unchanged;
+                }
+            }
+            if fs::remove_file(targetpath).is_err() {
+                error!("could not remove file {}", target.red());
+                return ApplyResult::Error;
+            }
+        } else if let Some(parent) = targetpath.parent() {
+            if fs::create_dir_all(parent).is_err() {
+                error!("could not create directory for {}", target.red());
+                return ApplyResult::Error;
+            }
+        }
+
+        if symlink_file(sourcepath, targetpath).is_err() {
+            error!("could not link {}", target.red());
+            return ApplyResult::Error;
+        }
+
+        // record the link so check can verify it later
+        if self.metafile.is_some() {
+            let mut metafile = MetaFile::from(PathBuf::from(&self.filename));
+            if !metafile.link {
+                metafile.link = true;
+                metafile.write_to_file();
+            }
+        }
+
+        info!("linked {} to {}", &target.bold(), &self.filename.green());
+        ApplyResult::Changed
+    }
+
+    // the sections as they are written to targets, None if a selected one cannot be rendered
+    // sections which are not selected are left as they are
+    fn render_sections(&self, options: &ApplyOptions) -> Option<Vec<Section>> {
+        self.sections
+            .iter()
+            .map(|section| {
+                if options.selects(section) {
+                    section.render()
+                } else {
+                    Some(section.clone())
+                }
+            })
+            .collect()
+    }
+
+    // expanded paths of all targets applying this file writes to
+    pub fn get_target_paths(&self, facts: &Facts) -> Vec<String> {
+        let mut targets = Vec::new();
+        if let Some(target) = &self.targetfile {
+            if resolve_os_target(target).is_some() {
+                targets.push(expand_tilde(target));
+            }
+        }
+        for section in &self.sections {
+            if !section.applies(facts) {
+                continue;
+            }
+            if let Section::Named(_, named_data) = section {
+                let target = named_data
+                    .target
+                    .as_ref()
+                    .filter(|target| resolve_os_target(target).is_some());
+                if let Some(target) = target {
+                    let target = expand_tilde(target);
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+            }
+        }
+        targets
+    }
+
+    // apply the sections without their own target to the target of the file
+    // creating the target if it does not exist yet
+    fn apply_file_target(
+        &self,
+        target: &str,
+        sections: &[Section],
+        options: &ApplyOptions,
+    ) -> ApplyResult {
+        if !Path::new(&expand_tilde(target)).is_file() {
+            if let Some(result) = check_target(target, options) {
+                return result;
+            }
+        }
+        if create_file(target, options.root) {
+            let result = DotFile::create_file(self, sections, options);
+            if matches!(result, ApplyResult::Changed) {
+                info!(
+                    "applied {} to create {} ",
+                    &self.filename.green(),
+                    &target.bold()
+                );
+            }
+            return result;
+        }
+        let mut targetfile = match DotFile::new(&expand_tilde(target)) {
+            Ok(file) => file,
+            Err(_) => {
+                error!("failed to parse {}", &target.red());
+                return ApplyResult::Error;
+            }
+        };
+        if !targetfile.applyfile(self, sections, options) {
+            return ApplyResult::Unchanged;
+        }
+        if let Some(result) = check_target(target, options) {
+            return result;
+        }
+        info!("applied {} to {} ", &self.filename.green(), &target.bold());
+        if !targetfile.write_target(options) {
+            return ApplyResult::Error;
+        }
+        ApplyResult::Changed
+    }
+
+    // apply sections with a section target to that target
+    // creating the target from the sections if it does not exist yet
+    fn apply_section_target(
+        &self,
+        target: &str,
+        sections: &Vec<Section>,
+        options: &ApplyOptions,
+    ) -> ApplyResult {
+        let realtarget = expand_tilde(target);
+        if !Path::new(&realtarget).is_file() {
+            if let Some(result) = check_target(target, options) {
+                return result;
             }
-            Some(metafile) => {
-                didsomething = metafile.compile().into();
+        }
+        if create_file(target, options.root) {
+            let mut content = String::new();
+            for section in sections {
+                content.push_str(&section.output(&self.commentsign));
             }
+            let content = self.finish_output(content);
+            if write_file(Path::new(&realtarget), content.as_bytes(), options.root).is_err() {
+                error!("could not write to file {}", &target.red());
+                return ApplyResult::Error;
+            }
+            for section in sections {
+                if let Section::Named(data, named_data) = section {
+                    store_snapshot(&named_data.hash, &data.content);
+                }
+            }
+            info!(
+                "applied {} sections from {} to create {}",
+                sections.len(),
+                &self.filename.green(),
+                &target.bold()
+            );
+            return ApplyResult::Changed;
         }
-        didsomething
-    }
 
-    pub fn write_to_file(&mut self) {
-        let targetname = &expand_tilde(&self.filename);
-        let newfile = File::create(targetname);
-        match newfile {
+        let mut targetfile = match DotFile::new(&realtarget) {
+            Ok(file) => file,
             Err(_) => {
-                println!("error: could not write to file {}", &self.filename);
-                panic!("write_to_file");
+                error!("failed to parse {}", &target.red());
+                return ApplyResult::Error;
             }
-            Ok(mut file) => match &mut self.metafile {
-                None => {
-                    file.write_all(self.to_string().as_bytes()).unwrap();
+        };
+        let mut applycounter = 0;
+        for section in sections {
+            if let Section::Named(data, named_data) = section {
+                if targetfile.has_section_hash(&named_data.name, &named_data.hash) {
+                    continue;
                 }
-                Some(metafile) => {
-                    file.write_all(metafile.content.as_bytes()).unwrap();
-                    metafile.write_to_file();
+                if targetfile.applysection(data.clone(), named_data.clone(), options) {
+                    applycounter += 1;
                 }
-            },
+            }
         }
-
-        if let Some(permissions) = self.permissions {
-            let mut perms = fs::metadata(targetname).unwrap().permissions();
-            let permint = u32::from_str_radix(&format!("{}", permissions + 1000000), 8).unwrap();
-            perms.set_mode(permint);
-            println!("setting permissions");
-            fs::set_permissions(targetname, perms).expect("failed to set permissions");
+        if applycounter == 0 {
+            return ApplyResult::Unchanged;
         }
+        if let Some(result) = check_target(target, options) {
+            return result;
+        }
+        info!(
+            "applied {} sections from {} to {}",
+            applycounter,
+            &self.filename.green(),
+            &target.bold()
+        );
+        if !targetfile.write_target(options) {
+            return ApplyResult::Error;
+        }
+        ApplyResult::Changed
     }
 
-    // create the target file if not existing
-    // TODO: result
-    pub fn create_file(source: &DotFile) -> bool {
-        let targetpath = String::from(source.targetfile.clone().unwrap());
-        let realtargetpath = expand_tilde(&targetpath);
-        // create new file
-        match &source.metafile {
-            None => {
-                let mut targetfile: DotFile = DotFile {
-                    specialcomments: source.specialcomments.clone(),
-                    sections: source.sections.clone(),
-                    filename: realtargetpath.clone(),
-                    targetfile: Option::Some(targetpath),
-                    commentsign: source.commentsign.clone(),
-                    file: source.file.try_clone().unwrap(),
-                    metafile: None,
-                    modified: source.modified,
-                    permissions: source.permissions,
-                };
-                targetfile.write_to_file();
-                return true;
+    // true if the file contains section name with content hash
+    // hashes with different algorithms are compared by rehashing the content
+    pub fn has_section_hash(&self, name: &str, hash: &str) -> bool {
+        for (data, named_data) in self.get_named_sections() {
+            if named_data.name != name {
+                continue;
             }
-            Some(metafile) => {
-                if metafile.modified {
-                    println!(
-                        "{}",
-                        format!("{} modified, skipping", &source.filename).yellow()
-                    );
-                    return false;
-                }
-                OpenOptions::new()
-                    .write(true)
-                    .open(&realtargetpath)
-                    .expect(&format!("cannot open file {}", &targetpath))
-                    .write_all(metafile.content.as_bytes())
-                    .expect(&format!("could not write file {}", &targetpath));
-                let mut newmetafile = MetaFile::from(PathBuf::from(&realtargetpath));
-                newmetafile.sourcefile = Some(source.filename.clone());
-                newmetafile.permissions = metafile.permissions;
-                newmetafile.write_to_file();
-                newmetafile.write_permissions();
+            if named_data.hash == hash {
                 return true;
             }
-        }
-    }
-
-    pub fn is_anonymous(&self) -> bool {
-        self.count_named_sections() == 0
-    }
-
-    pub fn apply(&self) -> ApplyResult {
-        let mut donesomething = false;
-        if let Some(target) = &self.targetfile {
-            if create_file(&target) {
-                if DotFile::create_file(self) {
-                    println!(
-                        "applied {} to create {} ",
-                        &self.filename.green(),
-                        &target.bold()
-                    );
-                    donesomething = true;
-                }
-            } else {
-                let mut targetfile = match DotFile::new(&expand_tilde(&target)) {
-                    Ok(file) => file,
-                    Err(_) => {
-                        eprintln!("failed to parse {}", &target.red());
-                        return ApplyResult::Error;
-                    }
-                };
-                if targetfile.applyfile(&self) {
-                    println!("applied {} to {} ", &self.filename.green(), &target.bold());
-                    targetfile.write_to_file();
-                    donesomething = true;
-                }
+            let algorithm = HashAlgorithm::from_hash(hash);
+            if algorithm != HashAlgorithm::from_hash(&named_data.hash) {
+                let content = hashed_content(&named_data.name, &data.content);
+                return algorithm.digest(content.as_bytes()) == hash;
             }
-        } else {
-            println!("{} has no target file", &self.filename.red());
-            return ApplyResult::Error;
-        }
-        if donesomething {
-            return ApplyResult::Changed;
-        } else {
-            return ApplyResult::Unchanged;
         }
+        false
     }
 
     fn can_apply(&self, other: &DotFile) -> bool {
-        if self.metafile.is_some() {
-            if other.metafile.is_some() {
-                return true;
-            } else {
-                eprintln!(
+        match (self.management_state(), other.management_state()) {
+            (ManagementState::MetafileManaged, ManagementState::MetafileManaged) => true,
+            (ManagementState::MetafileManaged, _) => {
+                warn!(
                     "{} {}",
                     "cannot apply comment file to metafile ".yellow(),
                     self.filename.yellow().bold()
                 );
-                return false;
+                false
             }
-        } else {
-            if self.is_anonymous() {
-                eprintln!(
+            // sections with an anchor can be inserted into files of other tools
+            (ManagementState::Unmanaged, ManagementState::CommentManaged)
+                if other
+                    .get_named_sections()
+                    .iter()
+                    .any(|(_, named_data)| named_data.anchor.is_some()) =>
+            {
+                true
+            }
+            (ManagementState::Unmanaged, _) => {
+                warn!(
                     "{} {}",
                     "cannot apply to unmanaged file ".yellow(),
                     self.filename.yellow().bold()
                 );
-                return false;
+                false
             }
-            if other.metafile.is_some() {
-                eprintln!(
+            (_, ManagementState::MetafileManaged) => {
+                error!(
                     "cannot apply metafile to normal imosid file {}",
                     self.filename.bold()
                 );
-                return false;
-            } else {
-                if other.is_anonymous() {
-                    eprintln!(
-                        "{} {}",
-                        other.filename.red(),
-                        "is unmanaged, cannot be applied"
-                    );
-                    return false;
-                } else {
-                    return true;
-                }
+                false
+            }
+            (_, ManagementState::Unmanaged) => {
+                error!(
+                    "{} {}",
+                    other.filename.red(),
+                    "is unmanaged, cannot be applied"
+                );
+                false
             }
+            (ManagementState::CommentManaged, ManagementState::CommentManaged) => true,
         }
     }
 
@@ -560,6 +1762,12 @@ impl DotFile {
         return false;
     }
 
+    fn has_protected_sections(&self) -> bool {
+        self.get_named_sections()
+            .iter()
+            .any(|(_, named_data)| named_data.protected)
+    }
+
     fn has_same_sections(&self, other: &DotFile) -> bool {
         if self.sections.len() != other.sections.len() {
             return false;
@@ -573,9 +1781,14 @@ impl DotFile {
     }
 
     // return true if file will be modified
-    // applies other file to self
+    // applies other file to self, sections are the rendered sections of inputfile
     // TODO: return result
-    pub fn applyfile(&mut self, inputfile: &DotFile) -> bool {
+    pub fn applyfile(
+        &mut self,
+        inputfile: &DotFile,
+        sections: &[Section],
+        options: &ApplyOptions,
+    ) -> bool {
         if !self.can_apply(inputfile) {
             return false;
         }
@@ -585,13 +1798,20 @@ impl DotFile {
                 let mut modified = false;
 
                 // true if input file contains all sections that self has
-                let allsections = self.has_same_sections(&inputfile);
+                // and all of them apply to this machine
+                let allsections = self.is_managed()
+                    && self.has_same_sections(inputfile)
+                    && !self.has_protected_sections()
+                    && inputfile
+                        .sections
+                        .iter()
+                        .all(|section| options.selects(section));
 
                 if !self.modified && allsections {
                     // copy entire file contents if all sections are unmodified
-                    self.sections = inputfile.sections.clone();
+                    self.sections = sections.to_vec();
                     self.specialcomments = inputfile.specialcomments.clone();
-                    println!(
+                    info!(
                         "applied all sections from {} to {}",
                         inputfile.filename.bold(),
                         self.filename.bold()
@@ -599,21 +1819,32 @@ impl DotFile {
                     modified = true;
                 } else {
                     let mut applycounter = 0;
-                    for (data, named_data) in inputfile.get_named_sections() {
-                        if self.applysection(data.clone(), named_data.clone()) {
+                    for section in sections {
+                        let (data, named_data) = match section {
+                            Section::Named(data, named_data) => (data, named_data),
+                            Section::Anonymous(_) => continue,
+                        };
+                        // sections with their own target are applied separately
+                        if named_data.target.is_some() || !options.selects(section) {
+                            continue;
+                        }
+                        if self.applysection(data.clone(), named_data.clone(), options) {
                             applycounter += 1;
                             modified = true;
                         }
                     }
+                    if options.prune_sections && self.prune_sections(inputfile) {
+                        modified = true;
+                    }
                     if modified {
-                        println!(
+                        info!(
                             "applied {} sections from {} to {}",
                             applycounter,
                             inputfile.filename.bold(),
                             self.filename.bold()
                         );
                     } else {
-                        println!(
+                        info!(
                             "applied no sections from {} to {}{}",
                             inputfile.filename.bold().dimmed(),
                             self.filename.bold().dimmed(),
@@ -630,20 +1861,45 @@ impl DotFile {
 
             // apply entire content if file is managed by metafile
             Some(metafile) => {
+                // files with regions only get the regions of the source
+                if let Some(applymetafile) = inputfile
+                    .metafile
+                    .as_ref()
+                    .filter(|applymetafile| !applymetafile.regions.is_empty())
+                {
+                    if metafile.regions.is_empty() {
+                        warn!(
+                            "{}",
+                            format!("target {} has no regions, skipping", &self.filename.bold())
+                                .yellow()
+                        );
+                        return false;
+                    }
+                    let applycounter = metafile.apply_regions(applymetafile, options);
+                    if applycounter > 0 {
+                        info!(
+                            "applied {} regions from {} to {}",
+                            applycounter,
+                            inputfile.filename.bold(),
+                            self.filename.bold()
+                        );
+                    }
+                    return applycounter > 0;
+                }
                 if !metafile.modified {
                     if let Some(applymetafile) = &inputfile.metafile {
                         if applymetafile.modified {
-                            println!("source file {} modified", &applymetafile.parentfile);
+                            warn!("source file {} modified", &applymetafile.parentfile);
                             return false;
                         }
                         if metafile.hash == applymetafile.hash {
-                            println!("file {} already up to date", self.filename.bold());
+                            info!("file {} already up to date", self.filename.bold());
                             return false;
                         }
                         metafile.content = applymetafile.content.clone();
                         metafile.hash = applymetafile.hash.clone();
 
-                        println!(
+                        info!(
                             "applied {} to {}",
                             inputfile.filename.bold(),
                             self.filename.bold()
@@ -651,7 +1907,7 @@ impl DotFile {
                         return true;
                     }
                 } else {
-                    println!(
+                    warn!(
                         "{}",
                         format!("target {} modified, skipping", &self.filename.bold()).yellow()
                     );
@@ -661,9 +1917,17 @@ impl DotFile {
         }
     }
 
-    fn applysection(&mut self, sectiondata: SectionData, named_data: NamedSectionData) -> bool {
+    // replace section with the same name
+    // missing sections are inserted if they have a placement or add_missing is set
+    // locally modified sections are skipped unless they can be merged
+    fn applysection(
+        &mut self,
+        sectiondata: SectionData,
+        named_data: NamedSectionData,
+        options: &ApplyOptions,
+    ) -> bool {
         if let Some(_) = &self.metafile {
-            eprintln!(
+            error!(
                 "{}",
                 "cannot apply individual section to file managed by metafile"
                     .red()
@@ -672,52 +1936,245 @@ impl DotFile {
             return false;
         }
         if named_data.hash != named_data.targethash {
-            eprintln!("cannot apply modified section");
+            error!("cannot apply modified section");
             return false;
         }
 
         for section_index in 0..self.sections.len() {
             let tmpsection = self.sections.get(section_index).unwrap();
-            if let Section::Named(_, src_named_data) = tmpsection {
+            if let Section::Named(target_data, src_named_data) = tmpsection {
                 if src_named_data.name.eq(&named_data.name) {
+                    if src_named_data.protected {
+                        warn!(
+                            "{}",
+                            format!("section {} is protected, skipping", &named_data.name).yellow()
+                        );
+                        return false;
+                    }
+                    if src_named_data.hash == named_data.hash {
+                        return false;
+                    }
+                    if src_named_data.hash != src_named_data.targethash {
+                        // source did not change since the local edits were made
+                        if src_named_data.targethash == named_data.hash {
+                            return false;
+                        }
+                        if !options.merge && options.conflict == ConflictStyle::Skip {
+                            warn!(
+                                "{}",
+                                format!(
+                                    "section {} modified in target, skipping",
+                                    &named_data.name
+                                )
+                                .yellow()
+                            );
+                            return false;
+                        }
+                        let merged = merge_section(
+                            target_data,
+                            &src_named_data.targethash,
+                            sectiondata,
+                            named_data,
+                            options.conflict,
+                        );
+                        return match merged {
+                            Some(merged) => {
+                                self.sections[section_index] = merged;
+                                true
+                            }
+                            None => false,
+                        };
+                    }
                     self.sections[section_index] = Section::Named(sectiondata, named_data);
                     return true;
                 }
             }
         }
-        return false;
+
+        // sections with an anchor go below the first unmanaged line matching it
+        if let Some(anchor) = named_data
+            .anchor
+            .as_ref()
+            .and_then(|anchor| Regex::new(anchor).ok())
+        {
+            if let Some(index) = self.split_at_anchor(&anchor) {
+                self.sections
+                    .insert(index, Section::Named(sectiondata, named_data));
+                self.renumber_sections();
+                return true;
+            }
+        }
+
+        // insert missing sections which specify where they belong
+        let placement = match named_data
+            .placement
+            .as_ref()
+            .and_then(|p| Placement::parse(p))
+        {
+            Some(placement) => placement,
+            None if options.add_missing => Placement::Bottom,
+            None => return false,
+        };
+        self.insert_section(Section::Named(sectiondata, named_data), &placement);
+        true
+    }
+
+    // remove unmodified sections which do not exist in the source anymore
+    // return true if any section was removed
+    fn prune_sections(&mut self, source: &DotFile) -> bool {
+        let mut prunenames = Vec::new();
+        for (_, named_data) in self.get_named_sections() {
+            if named_data.hash == named_data.targethash
+                && !named_data.protected
+                && !source.has_section(&named_data.name)
+            {
+                prunenames.push(named_data.name.clone());
+            }
+        }
+        for name in &prunenames {
+            self.deletesection(name);
+        }
+        if prunenames.is_empty() {
+            return false;
+        }
+        self.renumber_sections();
+        true
+    }
+
+    // insert new section at placement and recalculate line numbers
+    fn insert_section(&mut self, section: Section, placement: &Placement) {
+        let index = match placement {
+            Placement::Top => {
+                // keep the hashbang on the first line
+                if self.get_hashbang().is_some() {
+                    1
+                } else {
+                    0
+                }
+            }
+            Placement::Bottom => self.sections.len(),
+            Placement::After(name) => match self.sections.iter().position(|x| match x {
+                Section::Named(_, named_data) => named_data.name.eq(name),
+                _ => false,
+            }) {
+                Some(index) => index + 1,
+                None => self.sections.len(),
+            },
+        };
+        self.sections.insert(index, section);
+        self.renumber_sections();
+    }
+
+    // split the anonymous section containing the first line matching anchor
+    // after that line, return the index a section below the line goes to
+    fn split_at_anchor(&mut self, anchor: &Regex) -> Option<usize> {
+        let (index, offset) = self
+            .sections
+            .iter()
+            .enumerate()
+            .find_map(|(index, section)| match section {
+                Section::Anonymous(data) => {
+                    let mut offset = 0;
+                    for line in data.content.split_inclusive('\n') {
+                        offset += line.len();
+                        if anchor.is_match(line.trim_end_matches(['\r', '\n'])) {
+                            return Some((index, offset));
+                        }
+                    }
+                    None
+                }
+                Section::Named(_, _) => None,
+            })?;
+        if let Section::Anonymous(data) = &mut self.sections[index] {
+            let rest = data.content.split_off(offset);
+            if !rest.is_empty() {
+                self.sections
+                    .insert(index + 1, Section::new_anonymous(0, 0));
+                self.sections[index + 1].get_data_mut().content = rest;
+            }
+        }
+        Some(index + 1)
+    }
+
+    // recalculate start and end lines of sections from their output
+    fn renumber_sections(&mut self) {
+        let mut currentline = 1;
+        for section in &mut self.sections {
+            let linecount = section.output(&self.commentsign).lines().count() as u32;
+            let data = section.get_data_mut();
+            data.startline = currentline;
+            data.endline = currentline + linecount.max(1) - 1;
+            currentline += linecount;
+        }
     }
 
     pub fn get_hashbang(&self) -> Option<String> {
-        let firstsection = self.sections.get(0).unwrap();
+        let firstsection = self.sections.first()?;
         if let Section::Anonymous(section_data) = firstsection {
             let firstline = section_data.content.split("\n").nth(0).unwrap();
-            if Regex::new("^#!/.*").unwrap().is_match(&firstline) {
+            if Regex::new("^#!/.*").unwrap().is_match(firstline) {
                 return Some(String::from(firstline));
             }
         }
         None
     }
 
+    // restore trailing newline, line ending and byte order mark of the original file
+    fn finish_output(&self, content: String) -> String {
+        let mut content = content;
+        if !self.trailingnewline && content.ends_with('\n') {
+            content.pop();
+        }
+        let content = self.lineending.apply(&content);
+        if self.bom {
+            format!("{}{}", BOM, content)
+        } else {
+            content
+        }
+    }
+
     fn get_property_comments(&self) -> String {
+        // properties parsed into fields are written from them so changes to them are kept
+        let properties = [
+            (CommentType::TargetInfo, self.targetfile.clone()),
+            (
+                CommentType::PermissionInfo,
+                self.permissions.as_ref().map(|p| p.to_string()),
+            ),
+            (CommentType::ProfileInfo, self.profile.clone()),
+            (CommentType::PreHookInfo, self.prehook.clone()),
+            (CommentType::PostHookInfo, self.posthook.clone()),
+        ];
         let mut retstr = String::new();
-        // TODO: do same thing with all "all" section comments
-        if let Some(target) = &self.targetfile {
-            retstr.push_str(&Specialcomment::new_string(
-                &self.commentsign,
-                CommentType::TargetInfo,
-                "all",
-                Some(&target),
-            ));
+        for (comment_type, value) in &properties {
+            if let Some(value) = value {
+                retstr.push_str(&Specialcomment::new_string(
+                    &self.commentsign,
+                    comment_type.clone(),
+                    "all",
+                    Some(value),
+                ));
+            }
         }
 
-        if let Some(permissions) = &self.permissions {
-            retstr.push_str(&Specialcomment::new_string(
-                &self.commentsign,
-                CommentType::PermissionInfo,
-                "all",
-                Some(&permissions.to_string()),
-            ));
+        // every other whole file comment is written back as it was read
+        for comment in &self.specialcomments {
+            if comment.section != "all"
+                || properties
+                    .iter()
+                    .any(|(comment_type, _)| *comment_type == comment.comment_type)
+            {
+                continue;
+            }
+            match comment.comment_type {
+                CommentType::SectionBegin | CommentType::SectionEnd | CommentType::HashInfo => {}
+                _ => retstr.push_str(&Specialcomment::new_string(
+                    &self.commentsign,
+                    comment.comment_type.clone(),
+                    "all",
+                    comment.argument.as_deref(),
+                )),
+            }
         }
 
         retstr
@@ -737,7 +2194,7 @@ impl ToString for DotFile {
                     Some(hashbang) => {
                         retstr.push_str(&format!("{}\n", hashbang));
                         retstr.push_str(&self.get_property_comments());
-                        let firstcontent = &self.sections.get(0).unwrap().get_data().content;
+                        let firstcontent = &self.sections.first().unwrap().get_data().content;
                         retstr
                             .push_str(&firstcontent.lines().collect::<Vec<&str>>()[1..].join("\n"));
                         if firstcontent.lines().count() > 1 {
@@ -754,139 +2211,491 @@ impl ToString for DotFile {
                 for i in outputsections {
                     retstr.push_str(&i.output(&self.commentsign));
                 }
-                return retstr;
-            }
-            Some(metafile) => {
-                return metafile.content.clone();
+                self.finish_output(retstr)
             }
+            Some(metafile) => String::from_utf8_lossy(&metafile.content).to_string(),
         }
     }
 }
 
-// detect comment syntax for file based on filename, extension and hashbang
-fn get_comment_sign(filename: &str, firstline: &str) -> String {
+// number of non empty lines at the top of a file looked at to detect its comment syntax
+const HEADER_LINES: usize = 10;
+
+// detect comment syntax for file based on imosid comments already in it,
+// filename, extension, hashbang and the comments at the top of the file
+pub fn get_comment_sign(filename: &str, content: &str) -> String {
+    if let Some(sign) = marker_comment_sign(content) {
+        return sign;
+    }
+
+    let languages = Languages::get();
     let fpath = Path::new(filename);
 
-    let file_name_commentsigns: HashMap<&str, &str> = HashMap::from([
-        ("dunstrc", "#"),
-        ("jgmenurc", "#"),
-        ("zshrc", "#"),
-        ("bashrc", "#"),
-        ("Xresources", "!"),
-        ("xsettingsd", "#"),
-        ("vimrc", "\""),
-    ]);
+    // get comment syntax via the end of the path
+    if let Some(sign) = languages.path_comment_sign(fpath) {
+        return String::from(sign);
+    }
 
     // get comment syntax via file name
     let fname = fpath.file_name().and_then(OsStr::to_str);
-    match fname {
-        Some(name) => {
-            let filename = String::from(String::from(name).trim_start_matches("."));
-            match file_name_commentsigns.get(filename.as_str()) {
-                Some(sign) => {
-                    return String::from(sign.deref());
-                }
-                None => {}
-            }
-        }
-        None => {}
-    }
-
-    let file_type_commentsigns: HashMap<&str, &str> = HashMap::from([
-        ("py", "#"),
-        ("sh", "#"),
-        ("zsh", "#"),
-        ("bash", "#"),
-        ("fish", "#"),
-        ("c", "//"),
-        ("cpp", "//"),
-        ("rasi", "//"),
-        ("desktop", "#"),
-        ("conf", "#"),
-        ("vim", "\""),
-        ("reg", ";"),
-        ("rc", "#"),
-        ("ini", ";"),
-        ("xresources", "!"),
-    ]);
+    if let Some(sign) = fname.and_then(|name| languages.filenames.get(name.trim_start_matches('.')))
+    {
+        return sign.clone();
+    }
 
+    // get comment syntax via file extension
     let ext = fpath.extension().and_then(OsStr::to_str);
+    if let Some(sign) = ext.and_then(|extension| languages.extensions.get(extension)) {
+        return sign.clone();
+    }
 
-    // get comment syntax via file extension
-    match ext {
-        Some(extension) => {
-            let tester = file_type_commentsigns.get(extension);
-            match tester {
-                Some(sign) => {
-                    return String::from(sign.deref());
-                }
-                None => {}
-            }
-        }
-        None => {}
+    // get comment syntax via vim modeline or emacs mode line
+    if let Some(sign) =
+        modeline_filetype(content).and_then(|ft| languages.filetype_comment_sign(&ft))
+    {
+        return String::from(sign);
     }
 
     // get comment syntax via #!/hashbang
+    // leading blank lines are skipped
+    let header: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(HEADER_LINES)
+        .collect();
+    let firstline = header.first().copied().unwrap_or("");
+    if let Some(captures) = Regex::new("^#!/.*[/ ](.*)$").unwrap().captures(firstline) {
+        if let Some(sign) = languages.hashbangs.get(&captures[1]) {
+            return sign.clone();
+        }
+    }
 
-    let file_hashbang_commentsigns: HashMap<&str, &str> = HashMap::from([
-        ("python", "#"),
-        ("sh", "#"),
-        ("bash", "#"),
-        ("zsh", "#"),
-        ("fish", "#"),
-        ("node", "//"),
-    ]);
+    String::from(header_comment_sign(&header).unwrap_or("#"))
+}
 
-    match Regex::new("^#!/.*[/ ](.*)$").unwrap().captures(&firstline) {
-        Some(captures) => {
-            let application = captures.get(1).unwrap().as_str();
-            match file_hashbang_commentsigns.get(application) {
-                Some(sign) => {
-                    return String::from(sign.deref());
-                }
-                None => {}
+// lines at the start and end of a file searched for modelines, vim does the same
+const MODELINE_LINES: usize = 5;
+
+// filetype set by a vim modeline like "vim: ft=conf" or "vim: set filetype=dosini :"
+// or an emacs mode line like "-*- mode: conf -*-" or "-*- conf -*-"
+fn modeline_filetype(content: &str) -> Option<String> {
+    let vim = Regex::new(r"(?:^|\s)(?:vim?|ex):.*\b(?:ft|filetype)=([\w.-]+)").unwrap();
+    let emacs = Regex::new(r"-\*-(.*?)-\*-").unwrap();
+    let emacsmode = Regex::new(r"(?:^|;)\s*mode:\s*([\w-]+)").unwrap();
+    let lines = content
+        .lines()
+        .take(MODELINE_LINES)
+        .chain(content.lines().rev().take(MODELINE_LINES));
+    for line in lines {
+        if let Some(captures) = vim.captures(line) {
+            return Some(String::from(&captures[1]));
+        }
+        if let Some(captures) = emacs.captures(line) {
+            let variables = captures[1].trim();
+            if !variables.contains(':') {
+                return Some(String::from(variables));
+            }
+            if let Some(mode) = emacsmode.captures(variables) {
+                return Some(String::from(&mode[1]));
             }
         }
-        None => {}
     }
+    None
+}
+
+// comment sign of the first imosid comment in content
+fn marker_comment_sign(content: &str) -> Option<String> {
+    let marker =
+        Regex::new(r"^\s*(\S+?)\s*\.\.\.\s*\S+\s+(begin|start|end|stop|hash|target)\b").unwrap();
+    content
+        .lines()
+        .filter(|line| line.contains("... "))
+        .find_map(|line| marker.captures(line))
+        .map(|captures| String::from(&captures[1]))
+}
 
-    return String::from("#");
+// most common comment sign starting the lines at the top of a file
+fn header_comment_sign(header: &[&str]) -> Option<&'static str> {
+    let mut best = None;
+    let mut bestcount = 0;
+    for sign in ["//", "#", ";", "\"", "!", "--"] {
+        let count = header
+            .iter()
+            .filter(|line| line.trim_start().starts_with(sign))
+            .count();
+        if count > bestcount {
+            best = Some(sign);
+            bestcount = count;
+        }
+    }
+    best
 }
 
-// expand tilde in path into the home folder
+// expand ~/, ~user/, xdg shorthands like xdg-config:foo
+// and environment variables like $HOME or ${XDG_CONFIG_HOME} in a path
 pub fn expand_tilde(input: &str) -> String {
-    let mut retstr = String::from(input);
-    if retstr.starts_with("~/") {
-        retstr = String::from(format!(
-            "{}/{}",
+    let input = resolve_os_target(input).unwrap_or(input);
+    if let Some(xdgpath) = expand_xdg_shorthand(input) {
+        return xdgpath;
+    }
+    let mut retstr = expand_path_variables(input);
+    // windows paths may use either separator
+    if retstr == "~" || retstr.starts_with("~/") || retstr.starts_with(HOME_PREFIX) {
+        retstr = format!(
+            "{}{}",
             home::home_dir()
                 .unwrap()
                 .into_os_string()
                 .into_string()
                 .unwrap(),
-            retstr.strip_prefix("~/").unwrap()
-        ));
+            retstr.strip_prefix("~").unwrap()
+        );
+    } else if let Some(userpath) = retstr.strip_prefix("~") {
+        let (user, rest) = match userpath.split_once('/') {
+            Some((user, rest)) => (user, format!("/{}", rest)),
+            None => (userpath, String::new()),
+        };
+        if let Some(userhome) = user_home(user) {
+            retstr = format!("{}{}", userhome, rest);
+        }
     }
     return retstr;
 }
 
-// create file with directory creation and
+/// None if the file at path is not deployed as a link
+/// otherwise true if its target still links to it
+pub fn link_intact(path: &Path) -> Option<bool> {
+    let source = path.canonicalize().ok()?;
+    let target = expand_tilde(&link_target(&source)?);
+    Some(match fs::read_link(target) {
+        Ok(destination) => destination == source,
+        Err(_) => false,
+    })
+}
+
+// group named sections which have their own target by that target
+fn get_section_targets(
+    sections: &[Section],
+    options: &ApplyOptions,
+) -> HashMap<String, Vec<Section>> {
+    let mut targetmap: HashMap<String, Vec<Section>> = HashMap::new();
+    for section in sections {
+        if !options.selects(section) {
+            continue;
+        }
+        if let Section::Named(_, named_data) = section {
+            let target = named_data
+                .target
+                .as_ref()
+                .filter(|target| resolve_os_target(target).is_some());
+            if let Some(target) = target {
+                targetmap
+                    .entry(target.clone())
+                    .or_default()
+                    .push(section.clone());
+            }
+        }
+    }
+    targetmap
+}
+
+// three way merge of a locally modified target section with a changed source section
+// the snapshot of the section as it was last applied is the common base
+// conflicts either skip the section or are written into it as markers
+fn merge_section(
+    target: &SectionData,
+    basehash: &str,
+    sectiondata: SectionData,
+    named_data: NamedSectionData,
+    conflict: ConflictStyle,
+) -> Option<Section> {
+    let base = match load_snapshot(basehash) {
+        Some(base) => base,
+        // without a base everything that differs is a conflict
+        None if conflict == ConflictStyle::Markers => String::new(),
+        None => {
+            warn!(
+                "{}",
+                format!(
+                    "no snapshot to merge section {} with, skipping",
+                    &named_data.name
+                )
+                .yellow()
+            );
+            return None;
+        }
+    };
+    let merged = match diffy::merge(&base, &target.content, &sectiondata.content) {
+        Ok(merged) => merged,
+        Err(conflicted) if conflict == ConflictStyle::Markers => {
+            warn!(
+                "{}",
+                format!(
+                    "conflict in section {}, wrote conflict markers",
+                    &named_data.name
+                )
+                .red()
+            );
+            store_snapshot(&named_data.hash, &sectiondata.content);
+            let mut conflicted = Section::Named(
+                SectionData {
+                    content: conflicted,
+                    ..sectiondata
+                },
+                named_data,
+            );
+            conflicted.finalize();
+            return Some(conflicted);
+        }
+        Err(_) => {
+            warn!(
+                "{}",
+                format!("conflict merging section {}, skipping", &named_data.name).red()
+            );
+            return None;
+        }
+    };
+    info!(
+        "merged local changes into section {}",
+        &named_data.name.bold()
+    );
+    // the source becomes the new base, local edits stay marked as modified
+    store_snapshot(&named_data.hash, &sectiondata.content);
+    let mut merged = Section::Named(
+        SectionData {
+            content: merged,
+            ..sectiondata
+        },
+        named_data,
+    );
+    merged.finalize();
+    Some(merged)
+}
+
+// replace home directory prefix with a tilde
+pub fn collapse_tilde(input: &str) -> String {
+    if let Some(home) = home::home_dir() {
+        let home = home.display().to_string();
+        if let Some(rest) = input.strip_prefix(&format!("{}{}", home, MAIN_SEPARATOR)) {
+            return format!("~{}{}", MAIN_SEPARATOR, rest);
+        }
+    }
+    String::from(input)
+}
+
+// sections sharing lines with other sections can not be told apart
+// all of them are removed, leaving their lines to anonymous sections
+fn demote_overlapping(sections: Vec<Section>, diagnostics: &mut Vec<Diagnostic>) -> Vec<Section> {
+    // sections are sorted by start line, a group ends before the first section starting after it
+    let mut groups: Vec<(u32, Vec<Section>)> = Vec::new();
+    for section in sections {
+        let (startline, endline) = (section.get_data().startline, section.get_data().endline);
+        match groups.last_mut() {
+            Some((groupend, group)) if startline <= *groupend => {
+                *groupend = (*groupend).max(endline);
+                group.push(section);
+            }
+            _ => groups.push((endline, vec![section])),
+        }
+    }
+
+    let mut kept = Vec::new();
+    for (groupend, mut group) in groups {
+        if group.len() == 1 {
+            kept.append(&mut group);
+            continue;
+        }
+        let mut names = Vec::new();
+        let mut column = 1;
+        for section in &group {
+            if let Section::Named(_, named_data) = section {
+                if names.is_empty() {
+                    column = named_data.indent.chars().count() + 1;
+                }
+                names.push(named_data.name.as_str());
+            }
+        }
+        let startline = group[0].get_data().startline;
+        diagnostics.push(Diagnostic::error(
+            startline,
+            column,
+            0,
+            format!(
+                "sections {} overlap on lines {}-{}",
+                names.join(", "),
+                startline,
+                groupend
+            ),
+        ));
+    }
+    kept
+}
+
+// file types which do not support comments and need a metafile
+pub fn needs_metafile(filename: &str) -> bool {
+    let binary_types = ["png", "jpg", "jpeg", "gif", "ttf", "otf", "woff2"];
+    if let Some(extension) = Path::new(filename).extension().and_then(OsStr::to_str) {
+        if binary_types.contains(&extension) {
+            return true;
+        }
+    }
+    // formats without comments like strict json, jsonc files use // comments
+    get_comment_sign(filename, "").is_empty()
+}
+
+#[cfg(unix)]
+fn symlink_file(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+// needs developer mode or administrator rights on windows
+#[cfg(windows)]
+fn symlink_file(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, target)
+}
+
+// hard to guess for other users, so they cannot place a file or link there first
+fn tmpfile_suffix() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.write_u32(std::process::id());
+    format!("{:016x}", hasher.finish())
+}
+
+// create a new temporary file next to the file called filename in dir
+// never opening a file or following a link which is already there
+fn create_tmpfile(
+    dir: &Path,
+    filename: &str,
+    existing: Option<&fs::Metadata>,
+) -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    // create with the final mode so secrets are never readable by others
+    #[cfg(unix)]
+    options.mode(existing.map_or(0o666, |metadata| metadata.permissions().mode()));
+    for _ in 0..TMPFILE_ATTEMPTS {
+        let tmppath = dir.join(format!(".{}.{}.imosid-tmp", filename, tmpfile_suffix()));
+        match options.open(&tmppath) {
+            Ok(tmpfile) => return Ok((tmppath, tmpfile)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::AlreadyExists,
+        "could not create a temporary file",
+    ))
+}
+
+// write and sync content, copying mode and owner of the file it replaces
+fn write_tmpfile(
+    mut tmpfile: File,
+    tmppath: &Path,
+    content: &[u8],
+    existing: Option<&fs::Metadata>,
+) -> io::Result<()> {
+    tmpfile.write_all(content)?;
+    if let Some(metadata) = existing {
+        fs::set_permissions(tmppath, metadata.permissions())?;
+        // changing the owner only works as root, keep going otherwise
+        #[cfg(unix)]
+        let _ = std::os::unix::fs::chown(tmppath, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    tmpfile.sync_all()
+}
+
+// write file by writing a temporary file in the same directory
+// and renaming it over the original, so the target is never left truncated
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    // write through symlinks instead of replacing them
+    let path = if path.is_symlink() {
+        fs::canonicalize(path)?
+    } else {
+        path.to_path_buf()
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let filename = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid file name"))?;
+    let existing = fs::metadata(&path).ok();
+    let (tmppath, tmpfile) = create_tmpfile(&dir, filename, existing.as_ref())?;
+    let result = write_tmpfile(tmpfile, &tmppath, content, existing.as_ref())
+        .and_then(|_| fs::rename(&tmppath, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmppath);
+        return result;
+    }
+
+    if let Ok(dirhandle) = File::open(&dir) {
+        let _ = dirhandle.sync_all();
+    }
+    Ok(())
+}
+
+// None if apply may write target, otherwise the result of leaving it alone
+// targets only root can write and read-only targets are handled as options say
+fn check_target(target: &str, options: &ApplyOptions) -> Option<ApplyResult> {
+    let realtarget = expand_tilde(target);
+    let path = Path::new(&realtarget);
+    if options.root != RootPolicy::Sudo && needs_root(path) {
+        if options.root == RootPolicy::Skip {
+            warn!(
+                "{} {}",
+                target.yellow(),
+                "is owned by root, skipping".yellow()
+            );
+            return Some(ApplyResult::Unchanged);
+        }
+        error!(
+            "{} {}, use --sudo or --skip-root",
+            target.red(),
+            "is owned by root".red()
+        );
+        return Some(ApplyResult::Error);
+    }
+    if !is_readonly(path) {
+        return None;
+    }
+    match options.readonly {
+        ReadonlyPolicy::Chmod => None,
+        ReadonlyPolicy::Skip => {
+            warn!("{} {}", target.yellow(), "is read-only, skipping".yellow());
+            Some(ApplyResult::Unchanged)
+        }
+        ReadonlyPolicy::Error => {
+            error!(
+                "{} {}, use --readonly skip or --readonly chmod",
+                target.red(),
+                "is read-only".red()
+            );
+            Some(ApplyResult::Error)
+        }
+    }
+}
+
+// create the directories of a new file with
 // parsing of the home tilde
-// MAYBETODO: support environment variables
 // return false if file already exists
-pub fn create_file(path: &str) -> bool {
+pub fn create_file(path: &str, root: RootPolicy) -> bool {
     let realtargetname = expand_tilde(path);
 
     let checkpath = Path::new(&realtargetname);
     if !checkpath.is_file() {
-        let bufpath = checkpath.to_path_buf();
-        match bufpath.parent() {
-            Some(parent) => {
-                std::fs::create_dir_all(parent.to_str().unwrap()).unwrap();
-            }
-            None => {}
+        // writing the file later reports the error
+        if let Err(e) = create_parent_dirs(checkpath, root) {
+            error!(
+                "could not create directory for {}: {}",
+                realtargetname.red(),
+                e
+            );
         }
-        File::create(&realtargetname).unwrap();
         return true;
     } else {
         return false;
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use toml::Value;
+
+use crate::config::Config;
+use crate::dotwalker::{walk_dotfiles, WalkOptions};
+use crate::files::{ApplyOptions, DotFile};
+use crate::remote::sources_dir;
+use crate::section::Section;
+use crate::snapshot::{snapshot_dir, snapshot_path};
+use crate::state::StateDb;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// how much of a directory gc keeps, None keeps everything
+// when there is too much, the oldest entries are removed first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retention {
+    pub max_age: Option<u64>, // days
+    pub max_count: Option<usize>,
+    pub max_size: Option<u64>, // bytes
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            max_age: Some(90),
+            max_count: None,
+            max_size: None,
+        }
+    }
+}
+
+impl Retention {
+    // [gc] table, limits which are not set keep their default
+    // a limit of 0 turns it off, sizes are bytes or strings like 100M
+    pub fn from_value(value: &Value) -> Option<Retention> {
+        let mut retention = Retention::default();
+        if let Some(max_age) = value.get("max_age") {
+            retention.max_age = limit(max_age.as_integer()? as u64);
+        }
+        if let Some(max_count) = value.get("max_count") {
+            retention.max_count = limit(max_count.as_integer()? as u64).map(|count| count as usize);
+        }
+        if let Some(max_size) = value.get("max_size") {
+            let size = match max_size {
+                Value::Integer(size) => *size as u64,
+                Value::String(size) => parse_size(size)?,
+                _ => return None,
+            };
+            retention.max_size = limit(size);
+        }
+        Some(retention)
+    }
+
+    /// entries which are not kept, entries have to be sorted from newest to oldest
+    pub fn expired(&self, entries: Vec<Entry>, now: SystemTime) -> Vec<Entry> {
+        let mut expired = Vec::new();
+        let mut count = 0;
+        let mut size = 0;
+        let mut full = false;
+        for entry in entries {
+            let age = now
+                .duration_since(entry.modified)
+                .unwrap_or(Duration::ZERO)
+                .as_secs();
+            full = full
+                || self.max_count.is_some_and(|max_count| count >= max_count)
+                || self
+                    .max_size
+                    .is_some_and(|max_size| size + entry.size > max_size);
+            if full || self.max_age.is_some_and(|max_age| age > max_age * DAY) {
+                expired.push(entry);
+                continue;
+            }
+            count += 1;
+            size += entry.size;
+        }
+        expired
+    }
+}
+
+fn limit(value: u64) -> Option<u64> {
+    match value {
+        0 => None,
+        value => Some(value),
+    }
+}
+
+/// size like 512, 10K, 100M or 2G in bytes
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, factor) = match text.chars().last()?.to_ascii_uppercase() {
+        'K' => (&text[..text.len() - 1], 1 << 10),
+        'M' => (&text[..text.len() - 1], 1 << 20),
+        'G' => (&text[..text.len() - 1], 1 << 30),
+        _ => (text, 1),
+    };
+    Some(number.trim().parse::<u64>().ok()? * factor)
+}
+
+pub fn format_size(size: u64) -> String {
+    match size {
+        size if size >= 1 << 30 => format!("{:.1}G", size as f64 / (1u64 << 30) as f64),
+        size if size >= 1 << 20 => format!("{:.1}M", size as f64 / (1u64 << 20) as f64),
+        size if size >= 1 << 10 => format!("{:.1}K", size as f64 / (1u64 << 10) as f64),
+        size => format!("{}B", size),
+    }
+}
+
+// a file or directory gc can remove
+pub struct Entry {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub size: u64, // including everything inside directories
+}
+
+impl Entry {
+    pub fn remove(&self) -> io::Result<()> {
+        if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        }
+    }
+}
+
+fn disk_size(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// entries of dir from newest to oldest
+fn list_entries(dir: &Path) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| Entry {
+                path: entry.path(),
+                modified: entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                size: disk_size(&entry.path()),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.modified));
+    entries
+}
+
+// hashes of the sections in managed files, which are the bases of merges and diffs
+// those are the files in the config source, their targets and every file in the apply history
+fn live_hashes(state: &StateDb) -> HashSet<String> {
+    let mut paths: HashSet<PathBuf> = state
+        .records
+        .iter()
+        .flat_map(|record| [&record.source, &record.target])
+        .map(PathBuf::from)
+        .collect();
+    let config = Config::load();
+    if let Some(source) = &config.source {
+        let facts = ApplyOptions::new(config.profile.clone()).facts;
+        for dotfile in walk_dotfiles(source, &WalkOptions::default()) {
+            paths.extend(
+                dotfile
+                    .get_target_paths(&facts)
+                    .into_iter()
+                    .map(PathBuf::from),
+            );
+            paths.insert(PathBuf::from(&dotfile.filename));
+        }
+    }
+
+    let mut hashes = HashSet::new();
+    for path in paths {
+        let dotfile = match DotFile::from_pathbuf(&path) {
+            Ok(dotfile) => dotfile,
+            Err(_) => continue,
+        };
+        for section in &dotfile.sections {
+            if let Section::Named(_, named_data) = section {
+                hashes.insert(named_data.hash.clone());
+                hashes.insert(named_data.targethash.clone());
+            }
+        }
+    }
+    hashes
+}
+
+/// cached sources and snapshots retention does not keep, by kind
+/// snapshots of sections in the apply history or in managed files are always kept
+/// so they stay available for merges, diffs and rollbacks
+pub fn find_garbage(retention: &Retention) -> Vec<(&'static str, Vec<Entry>)> {
+    let now = SystemTime::now();
+
+    // interrupted downloads are never reused
+    let (partial, sources): (Vec<Entry>, Vec<Entry>) = list_entries(&sources_dir())
+        .into_iter()
+        .partition(|entry| entry.path.extension().is_some_and(|ext| ext == "part"));
+    let mut sources = retention.expired(sources, now);
+    sources.extend(partial);
+
+    let state = StateDb::load();
+    let referenced: HashSet<PathBuf> = state
+        .records
+        .iter()
+        .flat_map(|record| record.hashes.iter().map(|(_, hash)| hash.clone()))
+        .chain(live_hashes(&state))
+        .map(|hash| snapshot_path(&hash))
+        .collect();
+    let unreferenced = list_entries(&snapshot_dir())
+        .into_iter()
+        .filter(|entry| !referenced.contains(&entry.path))
+        .collect();
+    let snapshots = retention.expired(unreferenced, now);
+
+    vec![("cached sources", sources), ("snapshots", snapshots)]
+}
@@ -0,0 +1,117 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use colored::Colorize;
+
+// run git in directory, None if git is missing or fails
+fn git_output(directory: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+    )
+}
+
+// directories of files are used as they are not repositories themselves
+fn get_directory(path: &Path) -> &Path {
+    if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(Path::new("."))
+    }
+}
+
+/// root of the git repository containing path
+pub fn repo_root(path: &Path) -> Option<PathBuf> {
+    git_output(get_directory(path), &["rev-parse", "--show-toplevel"]).map(PathBuf::from)
+}
+
+/// warn if the repository containing path has uncommitted changes
+/// return false if it is dirty and require_clean is set
+pub fn check_clean(path: &Path, require_clean: bool) -> bool {
+    let root = match repo_root(path) {
+        Some(root) => root,
+        None => return true,
+    };
+    let status = match git_output(&root, &["status", "--porcelain"]) {
+        Some(status) => status,
+        None => return true,
+    };
+    if status.is_empty() {
+        return true;
+    }
+    let rootname = root.display().to_string();
+    if require_clean {
+        eprintln!(
+            "{} has uncommitted changes, commit or stash them first",
+            rootname.red()
+        );
+        return false;
+    }
+    println!(
+        "{} {} {}",
+        "warning:".yellow().bold(),
+        rootname.yellow(),
+        "has uncommitted changes".yellow()
+    );
+    true
+}
+
+/// commit path if it is part of a git repository
+pub fn commit_file(path: &Path, message: &str) -> bool {
+    let root = match repo_root(path) {
+        Some(root) => root,
+        None => return false,
+    };
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let pathname = path.to_str().unwrap();
+    if git_output(&root, &["add", "--", pathname]).is_none() {
+        eprintln!("could not stage {}", pathname.red());
+        return false;
+    }
+    // nothing staged means there is nothing to commit
+    if git_output(&root, &["diff", "--cached", "--quiet", "--", pathname]).is_some() {
+        return false;
+    }
+    if git_output(&root, &["commit", "-m", message, "--", pathname]).is_none() {
+        eprintln!("could not commit {}", pathname.red());
+        return false;
+    }
+    println!("committed {}", pathname.bold());
+    true
+}
+
+/// clone repo into directory or fetch it if it was cloned before
+pub fn clone_or_fetch(repo: &str, directory: &Path) -> bool {
+    if directory.join(".git").is_dir() {
+        return git_output(directory, &["fetch", "--quiet", "--tags", "origin"]).is_some();
+    }
+    let parent = directory.parent().unwrap_or(Path::new("."));
+    let name = match directory.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    git_output(parent, &["clone", "--quiet", repo, name]).is_some()
+}
+
+/// check out a detached revision
+pub fn checkout(directory: &Path, revision: &str) -> bool {
+    git_output(directory, &["checkout", "--quiet", "--detach", revision]).is_some()
+}
+
+pub fn head_commit(directory: &Path) -> Option<String> {
+    git_output(directory, &["rev-parse", "HEAD"])
+}
@@ -16,3 +16,46 @@ impl From<ChangeState> for bool {
         }
     }
 }
+
+// hashes without an algorithm tag are sha256 from older versions
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// algorithm used for hashes that do not exist yet
+    pub const DEFAULT: HashAlgorithm = HashAlgorithm::Blake3;
+
+    pub fn from_tag(tag: &str) -> Option<HashAlgorithm> {
+        match tag {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// detect the algorithm an existing hash was created with
+    /// falls back to the default for empty hashes
+    pub fn from_hash(hash: &str) -> HashAlgorithm {
+        if hash.is_empty() {
+            return HashAlgorithm::DEFAULT;
+        }
+        match hash.split_once(':') {
+            Some((tag, _)) => HashAlgorithm::from_tag(tag).unwrap_or(HashAlgorithm::DEFAULT),
+            None => HashAlgorithm::Sha256,
+        }
+    }
+
+    /// hash content, tagging the result with the algorithm name
+    /// sha256 stays untagged so older imosid versions can read it
+    pub fn digest(&self, content: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha256 => sha256::digest(content).to_uppercase(),
+            HashAlgorithm::Blake3 => {
+                format!("blake3:{}", blake3::hash(content).to_hex().to_uppercase())
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use toml::Value;
+
+use crate::config::cache_dir;
+use crate::files::{write_atomic, ApplyOptions, DotFile, ManagementState};
+use crate::section::Section;
+
+// identifies a version of a file without reading it
+#[derive(Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    pub mtime: i64, // nanoseconds since the epoch
+    pub size: i64,
+}
+
+impl FileStamp {
+    pub fn from_path(path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+        Some(FileStamp {
+            mtime: i64::try_from(mtime).ok()?,
+            size: i64::try_from(metadata.len()).ok()?,
+        })
+    }
+
+    fn from_value(value: &Value) -> Option<FileStamp> {
+        Some(FileStamp {
+            mtime: value.get("mtime")?.as_integer()?,
+            size: value.get("size")?.as_integer()?,
+        })
+    }
+
+    fn to_map(self) -> toml::map::Map<String, Value> {
+        let mut map = toml::map::Map::new();
+        map.insert("mtime".into(), Value::Integer(self.mtime));
+        map.insert("size".into(), Value::Integer(self.size));
+        map
+    }
+}
+
+// results of parsing a source file the last time it was seen
+pub struct CacheEntry {
+    pub stamp: FileStamp,
+    pub modified: bool,
+    pub state: ManagementState,
+    applied: Option<AppliedState>,
+}
+
+// targets as they were right after the source was last applied
+struct AppliedState {
+    options: String,
+    targets: HashMap<String, FileStamp>,
+}
+
+pub struct HashCache {
+    path: PathBuf,
+    enabled: bool,
+    changed: bool,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl HashCache {
+    /// read the cache, a disabled cache never returns or stores anything
+    pub fn load(enabled: bool) -> HashCache {
+        let mut cache = HashCache {
+            path: cache_dir().join("hashcache"),
+            enabled,
+            changed: false,
+            entries: HashMap::new(),
+        };
+        if !enabled {
+            return cache;
+        }
+        // a broken cache is simply rebuilt
+        let value = match fs::read_to_string(&cache.path)
+            .ok()
+            .and_then(|content| content.parse::<Value>().ok())
+        {
+            Some(Value::Table(table)) => table,
+            _ => return cache,
+        };
+        for (path, entry) in value {
+            if let Some(entry) = parse_entry(&entry) {
+                cache.entries.insert(path, entry);
+            }
+        }
+        cache
+    }
+
+    /// cached results for path, None if the file changed since
+    pub fn get(&self, path: &Path) -> Option<&CacheEntry> {
+        let entry = self.entries.get(&cache_key(path)?)?;
+        if FileStamp::from_path(path)? != entry.stamp {
+            return None;
+        }
+        Some(entry)
+    }
+
+    pub fn insert(&mut self, dotfile: &DotFile) {
+        if !self.enabled || !is_cacheable(dotfile) {
+            return;
+        }
+        let path = Path::new(&dotfile.filename);
+        let (key, stamp) = match (cache_key(path), FileStamp::from_path(path)) {
+            (Some(key), Some(stamp)) => (key, stamp),
+            _ => return,
+        };
+        self.entries.insert(
+            key,
+            CacheEntry {
+                stamp,
+                modified: dotfile.modified,
+                state: dotfile.management_state(),
+                applied: None,
+            },
+        );
+        self.changed = true;
+    }
+
+    /// true if neither the source nor its targets changed since it was last applied
+    pub fn is_applied(&self, path: &Path, options: &ApplyOptions) -> bool {
+        let applied = match self.get(path).and_then(|entry| entry.applied.as_ref()) {
+            Some(applied) => applied,
+            None => return false,
+        };
+        if applied.options != options_key(options) || applied.targets.is_empty() {
+            return false;
+        }
+        applied
+            .targets
+            .iter()
+            .all(|(target, stamp)| FileStamp::from_path(Path::new(target)) == Some(*stamp))
+    }
+
+    /// true if the source did not change since it was last applied with options
+    /// no matter what happened to its targets since
+    pub fn is_source_applied(&self, path: &Path, options: &ApplyOptions) -> bool {
+        self.get(path)
+            .and_then(|entry| entry.applied.as_ref())
+            .is_some_and(|applied| applied.options == options_key(options))
+    }
+
+    /// remember the state of all targets after applying dotfile
+    pub fn set_applied(&mut self, dotfile: &DotFile, options: &ApplyOptions) {
+        self.insert(dotfile);
+        let entry = match cache_key(Path::new(&dotfile.filename))
+            .and_then(|key| self.entries.get_mut(&key))
+        {
+            Some(entry) => entry,
+            None => return,
+        };
+        let mut targets = HashMap::new();
+        for target in dotfile.get_target_paths(&options.facts) {
+            match FileStamp::from_path(Path::new(&target)) {
+                Some(stamp) => {
+                    targets.insert(target, stamp);
+                }
+                // unknown target state, apply again next time
+                None => return,
+            }
+        }
+        entry.applied = Some(AppliedState {
+            options: options_key(options),
+            targets,
+        });
+    }
+
+    pub fn save(&self) {
+        if !self.enabled || !self.changed {
+            return;
+        }
+        let mut table = toml::map::Map::new();
+        for (path, entry) in &self.entries {
+            table.insert(path.clone(), entry_to_value(entry));
+        }
+        if let Some(parent) = self.path.parent() {
+            if fs::create_dir_all(parent).is_err() {
+                return;
+            }
+        }
+        let content = toml::to_string(&Value::Table(table)).unwrap_or_default();
+        // the cache is only an optimization, failing to write it is not an error
+        let _ = write_atomic(&self.path, content.as_bytes());
+    }
+}
+
+fn cache_key(path: &Path) -> Option<String> {
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|path| path.to_str().map(String::from))
+}
+
+// sections depending on the environment have to be rendered again every time
+fn is_cacheable(dotfile: &DotFile) -> bool {
+    if let Some(metafile) = &dotfile.metafile {
+        return !metafile.link;
+    }
+    !dotfile.sections.iter().any(|section| match section {
+        Section::Named(_, named_data) => {
+            named_data.expand || named_data.template || named_data.condition.is_some()
+        }
+        Section::Anonymous(_) => false,
+    })
+}
+
+// options which influence what gets written to targets
+fn options_key(options: &ApplyOptions) -> String {
+    format!(
+        "profile={} add_missing={} prune_sections={} link={} only_sections={} skip_sections={} root={:?} readonly={:?} merge={} conflict={:?}",
+        options.facts.profile.clone().unwrap_or_default(),
+        options.add_missing,
+        options.prune_sections,
+        options.link,
+        options.only_sections,
+        options.skip_sections,
+        options.root,
+        options.readonly,
+        options.merge,
+        options.conflict
+    )
+}
+
+fn parse_entry(value: &Value) -> Option<CacheEntry> {
+    let applied = value.get("applied").and_then(|applied| {
+        let mut targets = HashMap::new();
+        for (target, stamp) in applied.get("targets")?.as_table()? {
+            targets.insert(target.clone(), FileStamp::from_value(stamp)?);
+        }
+        Some(AppliedState {
+            options: applied.get("options")?.as_str()?.to_string(),
+            targets,
+        })
+    });
+    Some(CacheEntry {
+        stamp: FileStamp::from_value(value)?,
+        modified: value.get("modified")?.as_bool()?,
+        state: ManagementState::parse(value.get("state")?.as_str()?)?,
+        applied,
+    })
+}
+
+fn entry_to_value(entry: &CacheEntry) -> Value {
+    let mut map = entry.stamp.to_map();
+    map.insert("modified".into(), Value::Boolean(entry.modified));
+    map.insert("state".into(), Value::String(entry.state.as_str().into()));
+    if let Some(applied) = &entry.applied {
+        let mut targets = toml::map::Map::new();
+        for (target, stamp) in &applied.targets {
+            targets.insert(target.clone(), Value::Table(stamp.to_map()));
+        }
+        let mut appliedmap = toml::map::Map::new();
+        appliedmap.insert("options".into(), Value::String(applied.options.clone()));
+        appliedmap.insert("targets".into(), Value::Table(targets));
+        map.insert("applied".into(), Value::Table(appliedmap));
+    }
+    Value::Table(map)
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::process::Command;
+
+use colored::Colorize;
+use toml::Value;
+
+use crate::config::Config;
+use crate::files::{expand_tilde, ApplyOptions, ApplyResult, DotFile};
+use crate::init::MANIFEST_NAME;
+use crate::status::{get_status, FileStatus};
+
+// commands run before and after a target changes
+#[derive(Default)]
+struct HookCommands {
+    pre_apply: Vec<String>,
+    post_apply: Vec<String>,
+}
+
+impl HookCommands {
+    fn from_value(value: &Value) -> HookCommands {
+        let get = |key: &str| {
+            value
+                .get(key)
+                .and_then(|command| command.as_str())
+                .map(String::from)
+                .into_iter()
+                .collect()
+        };
+        HookCommands {
+            pre_apply: get("pre_apply"),
+            post_apply: get("post_apply"),
+        }
+    }
+
+    fn extend(&mut self, other: HookCommands) {
+        self.pre_apply.extend(other.pre_apply);
+        self.post_apply.extend(other.post_apply);
+    }
+}
+
+// hooks from the user config and the manifest of the config directory
+// global hooks run once per apply, target hooks once per changed target
+#[derive(Default)]
+pub struct Hooks {
+    global: HookCommands,
+    targets: HashMap<String, HookCommands>,
+    preapplied: bool, // global pre hooks already ran
+}
+
+impl Hooks {
+    pub fn load(configdir: Option<&Path>) -> Hooks {
+        let mut hooks = Hooks::default();
+        let mut files = vec![Config::load().path];
+        if let Some(configdir) = configdir {
+            files.push(configdir.join(MANIFEST_NAME));
+        }
+        for file in files {
+            if let Some(value) = read_to_string(&file)
+                .ok()
+                .and_then(|content| content.parse::<Value>().ok())
+            {
+                hooks.add_value(&value);
+            }
+        }
+        hooks
+    }
+
+    fn add_value(&mut self, value: &Value) {
+        self.global.extend(HookCommands::from_value(value));
+        if let Some(targets) = value.get("hooks").and_then(|hooks| hooks.as_table()) {
+            for (target, commands) in targets {
+                self.targets
+                    .entry(expand_tilde(target))
+                    .or_default()
+                    .extend(HookCommands::from_value(commands));
+            }
+        }
+    }
+
+    pub fn has_pre_apply(&self) -> bool {
+        !self.global.pre_apply.is_empty()
+            || self.targets.values().any(|hooks| !hooks.pre_apply.is_empty())
+    }
+
+    /// run before targets are about to change
+    pub fn run_pre_apply(&mut self, targets: &[String]) {
+        if !self.preapplied {
+            self.preapplied = true;
+            run_commands(&self.global.pre_apply);
+        }
+        for target in targets {
+            if let Some(hooks) = self.targets.get(target) {
+                run_commands(&hooks.pre_apply);
+            }
+        }
+    }
+
+    /// run after apply with all targets that changed
+    pub fn run_post_apply(&self, changed: &[String]) {
+        if changed.is_empty() {
+            return;
+        }
+        for target in changed {
+            if let Some(hooks) = self.targets.get(target) {
+                run_commands(&hooks.post_apply);
+            }
+        }
+        run_commands(&self.global.post_apply);
+    }
+}
+
+// true if applying file would change one of its targets
+fn needs_apply(file: &DotFile, options: &ApplyOptions) -> bool {
+    matches!(
+        get_status(file, options),
+        Some(FileStatus::NeedsApply)
+            | Some(FileStatus::MissingTarget)
+            | Some(FileStatus::ReadonlyTarget)
+    )
+}
+
+// apply file, running configured hooks and the hooks of the file itself
+pub fn apply_with_hooks(
+    file: &DotFile,
+    options: &ApplyOptions,
+    hooks: &mut Hooks,
+    changes: &mut Vec<String>,
+) -> ApplyResult {
+    if (hooks.has_pre_apply() || file.prehook.is_some()) && needs_apply(file, options) {
+        hooks.run_pre_apply(&file.get_target_paths(&options.facts));
+        if let Some(prehook) = &file.prehook {
+            run_hook(prehook);
+        }
+    }
+    let changecount = changes.len();
+    let result = file.apply(options, changes);
+    if changes.len() > changecount {
+        if let Some(posthook) = &file.posthook {
+            run_hook(posthook);
+        }
+    }
+    result
+}
+
+fn run_commands(commands: &[String]) {
+    for command in commands {
+        run_hook(command);
+    }
+}
+
+// run command through the shell, failures are reported but do not stop apply
+fn run_hook(command: &str) {
+    println!("{} {}", "running hook".bold(), command);
+    match Command::new("sh").arg("-c").arg(command).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("hook {} failed with {}", command.red(), status),
+        Err(_) => eprintln!("could not run hook {}", command.red()),
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use walkdir::WalkDir;
+
+use crate::files::{
+    collapse_tilde, expand_tilde, is_text_file, needs_metafile, DotFile, ManagementState,
+};
+use crate::metafile::MetaFile;
+use crate::permissions::Permissions;
+
+// turn an existing file into an imosid managed file applying to target
+// files which cannot hold comments get a metafile
+pub fn manage_file(
+    path: &Path,
+    target: &str,
+    name: Option<&str>,
+    metafile: bool,
+    permissions: Option<Permissions>,
+) -> io::Result<bool> {
+    if metafile || needs_metafile(path.to_str().unwrap()) || !is_text_file(path)? {
+        let mut newmetafile = MetaFile::from(path.to_path_buf());
+        newmetafile.targetfile = Some(String::from(target));
+        newmetafile.permissions = permissions;
+        newmetafile.write_to_file();
+        return Ok(true);
+    }
+
+    let mut dotfile = DotFile::from_pathbuf(&path.to_path_buf())?;
+    if dotfile.management_state() == ManagementState::Unmanaged {
+        let name = match name {
+            Some(name) => String::from(name),
+            None => default_section_name(path),
+        };
+        let linecount = fs::read_to_string(path)?.lines().count();
+        if linecount == 0 || !dotfile.wrap_lines(&name, 1, linecount as u32) {
+            return Ok(false);
+        }
+    }
+    dotfile.targetfile = Some(String::from(target));
+    if permissions.is_some() {
+        dotfile.permissions = permissions;
+    }
+    dotfile.compile();
+    dotfile.write_to_file();
+    Ok(true)
+}
+
+// derive a section name from the file name
+fn default_section_name(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("main")
+        .trim_start_matches('.')
+        .replace(char::is_whitespace, "_")
+}
+
+// convert a single file found by an importer, reporting the result
+fn import_file(
+    path: &Path,
+    target: &Path,
+    metafile: bool,
+    permissions: Option<Permissions>,
+) -> bool {
+    let source = path.display().to_string();
+    if DotFile::from_pathbuf(&path.to_path_buf()).is_ok_and(|file| file.is_managed()) {
+        println!("{} {}", source.yellow(), "is already managed".yellow());
+        return false;
+    }
+    let target = collapse_tilde(&target.display().to_string());
+    match manage_file(path, &target, None, metafile, permissions) {
+        Ok(true) => {
+            println!("{} -> {}", source.green(), target.bold());
+            true
+        }
+        _ => {
+            eprintln!("could not import {}", source.red());
+            false
+        }
+    }
+}
+
+// stow --dotfiles stores .bashrc as dot-bashrc
+fn decode_stow_name(name: &str) -> String {
+    match name.strip_prefix("dot-") {
+        Some(rest) => format!(".{}", rest),
+        None => String::from(name),
+    }
+}
+
+// convert every file of every package in a stow directory in place
+// packages mirror the target directory, the parent of the stow directory by default
+pub fn import_stow(stowdir: &Path, targetdir: Option<&Path>) -> io::Result<usize> {
+    let stowdir = stowdir.canonicalize()?;
+    let targetdir = match targetdir {
+        Some(dir) => dir.to_path_buf(),
+        None => stowdir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("/")),
+    };
+
+    let mut count = 0;
+    for package in fs::read_dir(&stowdir)? {
+        let package = package?.path();
+        if !package.is_dir() || package.file_name().is_none_or(|name| name == ".git") {
+            continue;
+        }
+        for entry in WalkDir::new(&package)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+        {
+            let path = entry.path();
+            if path
+                .to_str()
+                .is_none_or(|path| path.ends_with(".imosid.toml"))
+            {
+                continue;
+            }
+            let relative = path.strip_prefix(&package).unwrap();
+            let mut target = targetdir.clone();
+            for component in relative.iter() {
+                target.push(decode_stow_name(&component.to_string_lossy()));
+            }
+            if import_file(path, &target, false, None) {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+// target name and attributes encoded in a chezmoi source file name
+struct ChezmoiName {
+    name: String,
+    private: bool,
+    executable: bool,
+    readonly: bool,
+    template: bool,
+    unsupported: Option<&'static str>, // chezmoi feature imosid has no equivalent for
+}
+
+fn decode_chezmoi_name(encoded: &str) -> ChezmoiName {
+    let mut decoded = ChezmoiName {
+        name: String::new(),
+        private: false,
+        executable: false,
+        readonly: false,
+        template: false,
+        unsupported: None,
+    };
+    let mut rest = encoded;
+    if let Some(stripped) = rest.strip_suffix(".tmpl") {
+        decoded.template = true;
+        rest = stripped;
+    }
+    // attribute prefixes come before the actual name
+    loop {
+        if let Some(stripped) = rest.strip_prefix("private_") {
+            decoded.private = true;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("executable_") {
+            decoded.executable = true;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("readonly_") {
+            decoded.readonly = true;
+            rest = stripped;
+        } else if let Some(stripped) = rest
+            .strip_prefix("exact_")
+            .or_else(|| rest.strip_prefix("empty_"))
+            .or_else(|| rest.strip_prefix("create_"))
+        {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("encrypted_") {
+            decoded.unsupported = Some("encrypted");
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("modify_") {
+            decoded.unsupported = Some("modify script");
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("symlink_") {
+            decoded.unsupported = Some("symlink");
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("remove_") {
+            decoded.unsupported = Some("remove");
+            rest = stripped;
+        } else if rest.starts_with("run_") {
+            decoded.unsupported = Some("script");
+            break;
+        } else {
+            break;
+        }
+    }
+    decoded.name = if let Some(stripped) = rest.strip_prefix("literal_") {
+        String::from(stripped)
+    } else if let Some(stripped) = rest.strip_prefix("dot_") {
+        format!(".{}", stripped)
+    } else {
+        String::from(rest)
+    };
+    decoded
+}
+
+// convert a chezmoi source directory in place
+// templates are imported as they are and reported for manual conversion
+pub fn import_chezmoi(sourcedir: &Path, targetdir: Option<&Path>) -> io::Result<usize> {
+    let sourcedir = sourcedir.canonicalize()?;
+    let targetdir = match targetdir {
+        Some(dir) => dir.to_path_buf(),
+        None => home::home_dir().unwrap_or_else(|| PathBuf::from("/")),
+    };
+
+    let mut count = 0;
+    let mut templates = Vec::new();
+    for entry in WalkDir::new(&sourcedir)
+        .into_iter()
+        // chezmoi configuration and git data are not dotfiles
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name.starts_with(".chezmoi") || name == ".git")
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+    {
+        let path = entry.path();
+        let source = path.display().to_string();
+        if source.ends_with(".imosid.toml") {
+            continue;
+        }
+
+        let mut target = targetdir.clone();
+        let mut file = None;
+        for component in path.strip_prefix(&sourcedir).unwrap().iter() {
+            let decoded = decode_chezmoi_name(&component.to_string_lossy());
+            target.push(&decoded.name);
+            file = Some(decoded);
+        }
+        let file = match file {
+            Some(file) => file,
+            None => continue,
+        };
+        if let Some(feature) = file.unsupported {
+            println!(
+                "{} {} {}",
+                source.yellow(),
+                "skipped, unsupported".yellow(),
+                feature
+            );
+            continue;
+        }
+        let permissions = if file.private || file.executable || file.readonly {
+            let mut mode = if file.private { 0o600 } else { 0o644 };
+            if file.executable {
+                mode |= if file.private { 0o100 } else { 0o111 };
+            }
+            if file.readonly {
+                mode &= !0o222;
+            }
+            Some(Permissions {
+                mode,
+                owner: None,
+                group: None,
+            })
+        } else {
+            None
+        };
+
+        if import_file(path, &target, false, permissions) {
+            count += 1;
+            if file.template {
+                templates.push(source);
+            }
+        }
+    }
+
+    if !templates.is_empty() {
+        println!(
+            "{}",
+            "these files use chezmoi templates and need manual conversion:".yellow()
+        );
+        for template in templates {
+            println!("  {}", template);
+        }
+    }
+    Ok(count)
+}
+
+// convert the files linked by a dotbot config in place
+// linked directories have every file inside converted
+pub fn import_dotbot(config: &Path, metafile: bool) -> io::Result<usize> {
+    let config = config.canonicalize()?;
+    let basedir = config.parent().unwrap().to_path_buf();
+    let value: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(&config)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut count = 0;
+    let directives = value.as_sequence().cloned().unwrap_or_default();
+    for directive in &directives {
+        let links = match directive.get("link").and_then(|links| links.as_mapping()) {
+            Some(links) => links,
+            None => continue,
+        };
+        for (target, link) in links {
+            let target = match target.as_str() {
+                Some(target) => target,
+                None => continue,
+            };
+            // the source is either given directly, in a path option or derived from the target
+            let source = match link {
+                serde_yaml::Value::String(source) => Some(source.clone()),
+                serde_yaml::Value::Mapping(options) => {
+                    if options
+                        .get("glob")
+                        .and_then(|glob| glob.as_bool())
+                        .unwrap_or(false)
+                    {
+                        println!(
+                            "{} {}",
+                            target.yellow(),
+                            "skipped, globs are not supported".yellow()
+                        );
+                        continue;
+                    }
+                    options
+                        .get("path")
+                        .and_then(|path| path.as_str())
+                        .map(String::from)
+                }
+                _ => None,
+            };
+            let source = source.unwrap_or_else(|| {
+                Path::new(target).file_name().map_or(String::new(), |name| {
+                    String::from(name.to_string_lossy().trim_start_matches('.'))
+                })
+            });
+            if source.is_empty() {
+                continue;
+            }
+            let source = basedir.join(source);
+            let target = PathBuf::from(expand_tilde(target));
+
+            if source.is_dir() {
+                for entry in WalkDir::new(&source)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().is_file())
+                {
+                    let path = entry.path();
+                    if path.display().to_string().ends_with(".imosid.toml") {
+                        continue;
+                    }
+                    let relative = path.strip_prefix(&source).unwrap();
+                    if import_file(path, &target.join(relative), metafile, None) {
+                        count += 1;
+                    }
+                }
+            } else if source.is_file() {
+                if import_file(&source, &target, metafile, None) {
+                    count += 1;
+                }
+            } else {
+                eprintln!("{} does not exist", source.display().to_string().red());
+            }
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,90 @@
+use crate::config::config_dir;
+use crate::files::{collapse_tilde, DotFile};
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+pub const MANIFEST_NAME: &str = "imosid.toml";
+pub const IGNORE_NAME: &str = ".imosidignore";
+
+const IGNORE_TEMPLATE: &str = "# files imosid should not manage, one pattern per line
+*.md
+LICENSE
+";
+
+const MANIFEST_TEMPLATE: &str = "# imosid config repository
+";
+
+// write file unless it already exists
+fn create_skeleton_file(path: &Path, content: &str) -> Result<(), std::io::Error> {
+    if path.exists() {
+        println!("{} already exists", path.display().to_string().yellow());
+        return Ok(());
+    }
+    fs::write(path, content)?;
+    println!("created {}", path.display().to_string().bold());
+    Ok(())
+}
+
+// create skeleton config repository
+pub fn init_config_dir(dir: &PathBuf, manifest: bool, git: bool) -> Result<(), std::io::Error> {
+    fs::create_dir_all(dir)?;
+    let dir = dir.canonicalize()?;
+
+    create_skeleton_file(&dir.join(IGNORE_NAME), IGNORE_TEMPLATE)?;
+    if manifest {
+        create_skeleton_file(&dir.join(MANIFEST_NAME), MANIFEST_TEMPLATE)?;
+    }
+
+    // point the user config to the new repository
+    let configpath = config_dir().join("config.toml");
+    if !configpath.exists() {
+        fs::create_dir_all(config_dir())?;
+        create_skeleton_file(
+            &configpath,
+            &format!(
+                "source = \"{}\"\n",
+                collapse_tilde(&dir.display().to_string())
+            ),
+        )?;
+    }
+
+    if git && !dir.join(".git").exists() {
+        let status = Command::new("git").arg("init").arg(&dir).status()?;
+        if !status.success() {
+            eprintln!("{}", "git init failed".red());
+        }
+    }
+    Ok(())
+}
+
+// list unmanaged files in ~/.config which could be adopted
+pub fn suggest_candidates(into: &Path) {
+    let configdir = match home::home_dir() {
+        Some(home) => home.join(".config"),
+        None => return,
+    };
+    for entry in WalkDir::new(&configdir)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+    {
+        // skip large files, those are rarely hand written configs
+        match entry.metadata() {
+            Ok(metadata) if metadata.len() < 100_000 => {}
+            _ => continue,
+        }
+        if let Ok(file) = DotFile::from_pathbuf(&entry.path().to_path_buf()) {
+            if !file.is_managed() {
+                println!(
+                    "imosid adopt {} --into {}",
+                    collapse_tilde(&entry.path().display().to_string()),
+                    into.display()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::config::config_dir;
+
+const BUILTIN_LANGUAGES: &str = include_str!("languages.toml");
+
+// comment signs of config formats by file name, extension, hashbang and filetype
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Languages {
+    pub paths: HashMap<String, String>,
+    pub filenames: HashMap<String, String>,
+    pub extensions: HashMap<String, String>,
+    pub hashbangs: HashMap<String, String>,
+    pub filetypes: HashMap<String, String>,
+}
+
+impl Languages {
+    pub fn parse(content: &str) -> Result<Languages, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// the built in table with the entries of the user languages.toml on top
+    /// the table is only read once per run
+    pub fn get() -> &'static Languages {
+        static LANGUAGES: OnceLock<Languages> = OnceLock::new();
+        LANGUAGES.get_or_init(|| {
+            let mut languages =
+                Languages::parse(BUILTIN_LANGUAGES).expect("built in languages.toml is valid");
+            let path = languages_path();
+            if let Ok(content) = read_to_string(&path) {
+                match Languages::parse(&content) {
+                    Ok(user) => languages.extend(user),
+                    Err(e) => eprintln!("could not parse {}: {}", path.display(), e),
+                }
+            }
+            languages
+        })
+    }
+
+    // entries of other replace the ones already present
+    pub fn extend(&mut self, other: Languages) {
+        self.paths.extend(other.paths);
+        self.filenames.extend(other.filenames);
+        self.extensions.extend(other.extensions);
+        self.hashbangs.extend(other.hashbangs);
+        self.filetypes.extend(other.filetypes);
+    }
+
+    // comment sign of the longest entry of paths path ends with
+    pub fn path_comment_sign(&self, path: &Path) -> Option<&str> {
+        self.paths
+            .iter()
+            .filter(|(end, _)| path.ends_with(end))
+            .max_by_key(|(end, _)| end.len())
+            .map(|(_, sign)| sign.as_str())
+    }
+
+    // comment sign of a vim filetype or emacs mode, variants like conf-unix fall back to conf
+    pub fn filetype_comment_sign(&self, filetype: &str) -> Option<&str> {
+        let filetype = filetype.to_lowercase();
+        self.filetypes
+            .get(filetype.as_str())
+            .or_else(|| self.filetypes.get(filetype.split('-').next()?))
+            .map(|sign| sign.as_str())
+    }
+}
+
+pub fn languages_path() -> PathBuf {
+    config_dir().join("languages.toml")
+}
@@ -0,0 +1,94 @@
+use crate::files::DotFile;
+use crate::section::Section;
+use colored::Colorize;
+
+pub struct ListFilter {
+    pub section: Option<String>,
+    pub target: Option<String>,
+    pub modified: bool,
+}
+
+struct ListRow {
+    file: String,
+    section: String,
+    modified: bool,
+    target: String,
+    source: String,
+}
+
+fn get_rows(file: &DotFile) -> Vec<ListRow> {
+    let mut rows = Vec::new();
+    let filetarget = file.targetfile.clone().unwrap_or_default();
+    if let Some(metafile) = &file.metafile {
+        rows.push(ListRow {
+            file: file.filename.clone(),
+            section: String::from("-"),
+            modified: metafile.modified,
+            target: filetarget,
+            source: metafile.sourcefile.clone().unwrap_or_default(),
+        });
+        return rows;
+    }
+    for section in &file.sections {
+        if let Section::Named(_, named_data) = section {
+            rows.push(ListRow {
+                file: file.filename.clone(),
+                section: named_data.name.clone(),
+                modified: named_data.hash != named_data.targethash,
+                target: named_data.target.clone().unwrap_or(filetarget.clone()),
+                source: named_data.source.clone().unwrap_or_default(),
+            });
+        }
+    }
+    rows
+}
+
+impl ListFilter {
+    fn matches(&self, row: &ListRow) -> bool {
+        if let Some(section) = &self.section {
+            if &row.section != section {
+                return false;
+            }
+        }
+        if let Some(target) = &self.target {
+            if !row.target.contains(target.as_str()) {
+                return false;
+            }
+        }
+        !self.modified || row.modified
+    }
+}
+
+// print managed files and their sections as a table
+pub fn print_list(files: &[DotFile], filter: &ListFilter) {
+    let rows: Vec<ListRow> = files
+        .iter()
+        .filter(|file| file.is_managed())
+        .flat_map(get_rows)
+        .filter(|row| filter.matches(row))
+        .collect();
+
+    if rows.is_empty() {
+        println!("{}", "no managed files found".bold());
+        return;
+    }
+
+    let filewidth = rows.iter().map(|row| row.file.len()).max().unwrap_or(0);
+    let sectionwidth = rows.iter().map(|row| row.section.len()).max().unwrap_or(0);
+    let targetwidth = rows.iter().map(|row| row.target.len()).max().unwrap_or(0);
+
+    for row in rows {
+        println!(
+            "{} {:sectionwidth$} {} {:targetwidth$} {}",
+            format!("{:width$}", row.file, width = filewidth).bold(),
+            row.section,
+            if row.modified {
+                "modified".red()
+            } else {
+                "ok      ".green()
+            },
+            row.target,
+            row.source.dimmed()
+        );
+    }
+}
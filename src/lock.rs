@@ -0,0 +1,59 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+
+use colored::Colorize;
+use fs2::FileExt;
+use tracing::{debug, warn};
+
+use crate::config::state_dir;
+
+pub fn lock_path() -> PathBuf {
+    state_dir().join("lock")
+}
+
+// advisory lock held while imosid writes to files
+// it is released when dropped or when the process exits
+pub struct Lock {
+    file: File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// take the lock, waiting for other imosid runs which hold it to finish
+/// returns None if the lock file can not be used, imosid then runs unlocked
+pub fn acquire() -> Option<Lock> {
+    let path = lock_path();
+    let file = match fs::create_dir_all(state_dir()).and_then(|_| {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+    }) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("could not open lock file {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match file.try_lock_exclusive() {
+        Ok(()) => {}
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            eprintln!("{}", "waiting for another imosid run to finish".yellow());
+            if let Err(e) = file.lock_exclusive() {
+                warn!("could not lock {}: {}", path.display(), e);
+                return None;
+            }
+        }
+        Err(e) => {
+            warn!("could not lock {}: {}", path.display(), e);
+            return None;
+        }
+    }
+    debug!("locked {}", path.display());
+    Some(Lock { file })
+}
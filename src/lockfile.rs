@@ -0,0 +1,206 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use toml::Value;
+use tracing::error;
+
+use crate::files::write_atomic;
+use crate::git;
+use crate::init::MANIFEST_NAME;
+use crate::remote::{resolve_source, source_commit, Pin, SourceLocation, SourceSpec};
+
+pub const LOCKFILE_NAME: &str = "imosid.lock";
+
+const LOCKFILE_HEADER: &str = "# versions of remote sources used by imosid update, do not edit\n";
+
+// the version of a remote source an update resolved to
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LockedSource {
+    pub source: String,         // source comment argument
+    pub url: String,            // url or git repository
+    pub commit: Option<String>, // only git sources have commits
+    pub sha256: String,         // hash of the source file content
+}
+
+impl LockedSource {
+    // the source spec pinned to exactly this version
+    fn pinned(&self, spec: &SourceSpec) -> SourceSpec {
+        let pin = match (&spec.location, &self.commit) {
+            (SourceLocation::Git { .. }, Some(commit)) => Pin::GitRef(commit.clone()),
+            _ => Pin::Sha256(self.sha256.clone()),
+        };
+        SourceSpec {
+            location: spec.location.clone(),
+            pin: Some(pin),
+        }
+    }
+}
+
+// imosid.lock in the root of a config repository
+pub struct LockFile {
+    pub path: PathBuf,
+    pub sources: Vec<LockedSource>,
+    pub locked: bool, // only use the versions in the file instead of recording new ones
+    pub changed: bool, // sources were recorded since loading
+}
+
+impl LockFile {
+    /// lock file of the config repository containing path
+    /// that is the closest directory with an imosid.toml or imosid.lock,
+    /// the git repository or the directory of path
+    pub fn find(path: &Path) -> LockFile {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(Path::new("."))
+        };
+        let root = dir
+            .ancestors()
+            .find(|dir| dir.join(MANIFEST_NAME).is_file() || dir.join(LOCKFILE_NAME).is_file())
+            .map(Path::to_path_buf)
+            .or_else(|| git::repo_root(dir))
+            .unwrap_or_else(|| dir.to_path_buf());
+        LockFile::load(root.join(LOCKFILE_NAME))
+    }
+
+    pub fn load(path: PathBuf) -> LockFile {
+        let mut lockfile = LockFile {
+            path,
+            sources: Vec::new(),
+            locked: false,
+            changed: false,
+        };
+        let content = match fs::read_to_string(&lockfile.path) {
+            Ok(content) => content,
+            Err(_) => return lockfile,
+        };
+        lockfile.sources = LockFile::parse(&content).unwrap_or_else(|| {
+            error!(
+                "could not parse {}",
+                lockfile.path.display().to_string().red()
+            );
+            Vec::new()
+        });
+        lockfile
+    }
+
+    pub fn parse(content: &str) -> Option<Vec<LockedSource>> {
+        let value = content.parse::<Value>().ok()?;
+        let mut sources = Vec::new();
+        let entries = match value.get("source") {
+            Some(entries) => entries.as_array()?,
+            None => return Some(sources),
+        };
+        for source in entries {
+            sources.push(LockedSource {
+                source: source.get("source")?.as_str()?.to_string(),
+                url: source.get("url")?.as_str()?.to_string(),
+                commit: source
+                    .get("commit")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                sha256: source.get("sha256")?.as_str()?.to_string(),
+            });
+        }
+        Some(sources)
+    }
+
+    pub fn get(&self, source: &str) -> Option<&LockedSource> {
+        self.sources.iter().find(|locked| locked.source == source)
+    }
+
+    // add or replace the entry of a source
+    pub fn insert(&mut self, locked: LockedSource) {
+        match self.sources.iter_mut().find(|s| s.source == locked.source) {
+            Some(existing) if *existing == locked => return,
+            Some(existing) => *existing = locked,
+            None => self.sources.push(locked),
+        }
+        self.changed = true;
+    }
+
+    /// local path of source like remote::resolve_source
+    /// remote sources are checked against their entry if locked is set, recorded otherwise
+    pub fn resolve(&mut self, source: &str, offline: bool) -> Option<PathBuf> {
+        let spec = SourceSpec::parse(source)?;
+        if !spec.is_remote() {
+            return resolve_source(&spec, offline);
+        }
+        if !self.locked {
+            let path = resolve_source(&spec, offline)?;
+            let sha256 = sha256::digest(fs::read(&path).ok()?.as_slice());
+            let url = match &spec.location {
+                SourceLocation::Git { repo, .. } => repo.clone(),
+                SourceLocation::Url(url) | SourceLocation::Local(url) => url.clone(),
+            };
+            self.insert(LockedSource {
+                source: source.to_string(),
+                url,
+                commit: source_commit(&spec),
+                sha256,
+            });
+            return Some(path);
+        }
+
+        let locked = match self.get(source) {
+            Some(locked) => locked,
+            None => {
+                error!(
+                    "{} is not in {}, run imosid update without --locked first",
+                    source.red(),
+                    self.path.display()
+                );
+                return None;
+            }
+        };
+        let path = resolve_source(&locked.pinned(&spec), offline)?;
+        // a locked commit could still have a different file at path
+        let sha256 = sha256::digest(fs::read(&path).ok()?.as_slice());
+        if sha256 != locked.sha256 {
+            error!(
+                "{} {} has hash {} instead of {}",
+                "lock mismatch:".red().bold(),
+                source.red(),
+                sha256,
+                locked.sha256
+            );
+            return None;
+        }
+        Some(path)
+    }
+
+    pub fn save(&self) -> bool {
+        if write_atomic(&self.path, self.to_string().as_bytes()).is_err() {
+            eprintln!("could not write {}", self.path.display().to_string().red());
+            return false;
+        }
+        true
+    }
+}
+
+impl fmt::Display for LockFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sources = self.sources.clone();
+        sources.sort_by(|a, b| a.source.cmp(&b.source));
+        let sources = sources
+            .iter()
+            .map(|locked| {
+                let mut map = toml::map::Map::new();
+                map.insert("source".into(), Value::String(locked.source.clone()));
+                map.insert("url".into(), Value::String(locked.url.clone()));
+                if let Some(commit) = &locked.commit {
+                    map.insert("commit".into(), Value::String(commit.clone()));
+                }
+                map.insert("sha256".into(), Value::String(locked.sha256.clone()));
+                Value::Table(map)
+            })
+            .collect();
+        let mut map = toml::map::Map::new();
+        map.insert("source".into(), Value::Array(sources));
+        let content = toml::to_string(&Value::Table(map)).expect("lock entries are valid toml");
+        write!(f, "{}{}", LOCKFILE_HEADER, content)
+    }
+}
@@ -0,0 +1,54 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use colored::Colorize;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+/// send messages to stderr, keeping stdout free for command output
+/// verbosity is the number of -v flags, quiet only leaves errors
+/// everything from info up is also appended to logfile
+pub fn init_logging(verbosity: u8, quiet: bool, logfile: Option<&Path>) {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    // plain messages unless the user asked for details
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .without_time()
+        .with_target(false)
+        .with_level(verbosity > 0)
+        .with_ansi(colored::control::SHOULD_COLORIZE.should_colorize())
+        .with_filter(level);
+
+    let file =
+        logfile.and_then(
+            |path| match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(
+                    fmt::layer()
+                        .with_writer(Mutex::new(file))
+                        .with_ansi(false)
+                        .with_filter(if quiet { LevelFilter::INFO } else { level }),
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "could not open log file {}: {}",
+                        path.to_str().unwrap().red(),
+                        e
+                    );
+                    None
+                }
+            },
+        );
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .init();
+}
@@ -1,23 +1,88 @@
 mod app;
+mod archive;
 mod dotwalker;
 mod test;
 use colored::Colorize;
-use dotwalker::{apply_config_dir, walk_config_dir, walk_dotfiles};
+use dotwalker::{
+    apply_config_dir, report_open_error, walk_config_dir, walk_dotfiles, walk_managed_dirs,
+    WalkOptions,
+};
+use clap::ArgMatches;
+use regex::Regex;
 mod comment;
 mod commentmap;
-mod contentline;
+mod condition;
+mod config;
+mod deploy;
+mod diagnostic;
+mod dirmeta;
+mod encryption;
+mod expand;
+mod facts;
 mod files;
+mod gc;
+mod git;
 mod hashable;
+mod hashcache;
+mod hooks;
+mod importer;
+mod init;
+mod languages;
+mod list;
+mod lock;
+mod lockfile;
+mod logging;
+mod matcher;
 mod metafile;
+mod notify;
+mod permissions;
+mod privilege;
+mod picker;
+mod reconcile;
+mod region;
+mod remote;
+mod repair;
+mod secret;
 mod section;
-use std::{path::PathBuf, println};
+mod serve;
+mod snapshot;
+mod state;
+mod status;
+mod systemd;
+mod template;
+mod tui;
+mod verify;
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    println,
+};
 
 use crate::{
-    app::get_vec_args,
-    files::{ApplyResult, DotFile},
-    hashable::Hashable,
+    app::{get_section_matcher, get_vec_args, get_walk_options, pick_section_matcher},
+    config::Config,
+    deploy::Deployment,
+    diagnostic::Severity,
+    dirmeta::{DirMetaFile, DIR_METAFILE_NAME},
+    files::{
+        collapse_tilde, expand_tilde, is_text_file, link_intact, needs_metafile, ApplyOptions,
+        ApplyResult, ConflictStyle, DotFile, ManagementState, ReadonlyPolicy,
+    },
+    gc::{find_garbage, format_size, parse_size},
+    hashable::{HashAlgorithm, Hashable},
+    hashcache::HashCache,
+    hooks::{apply_with_hooks, Hooks},
+    list::{print_list, ListFilter},
+    lockfile::LockFile,
+    matcher::SectionMatcher,
     metafile::MetaFile,
-    section::Section,
+    permissions::Permissions,
+    privilege::RootPolicy,
+    remote::fetch_sources,
+    section::{hashed_content, Section},
+    state::{format_time, Action, StateDb},
+    status::{get_status, FileStatus},
+    verify::verify_file,
 };
 
 pub mod built_info {
@@ -48,77 +113,922 @@ macro_rules! get_dotfile {
     };
 }
 
+// compile a single file or directory, return false if it failed
+fn compile_path(filename: &PathBuf, compile_matches: &ArgMatches) -> io::Result<bool> {
+    let stdout = compile_matches.get_flag("stdout");
+    // directories are managed as a whole by a dir.imosid.toml
+    if filename.is_dir() {
+        if stdout {
+            eprintln!("{}", "directories cannot be printed to stdout".red());
+            return Ok(false);
+        }
+        let mut dirmetafile = match DirMetaFile::from(filename) {
+            Ok(dirmetafile) => dirmetafile,
+            Err(e) => {
+                eprintln!("invalid directory metafile: {}", e.red());
+                return Ok(false);
+            }
+        };
+        if dirmetafile.compile()? || !filename.join(DIR_METAFILE_NAME).is_file() {
+            dirmetafile.write_to_file();
+            println!("compiled {}", filename.to_str().unwrap().bold());
+            if compile_matches.get_flag("commit") || Config::load().auto_commit {
+                git::commit_file(
+                    &filename.join(DIR_METAFILE_NAME),
+                    &format!("imosid: compile {}", filename.to_str().unwrap()),
+                );
+            }
+        } else {
+            println!(
+                "{} already compiled, no change",
+                filename.to_str().unwrap().bold().green()
+            );
+        }
+        return Ok(true);
+    }
+    if !filename.is_file() {
+        eprintln!(
+            "{} {}",
+            "file does not exist".red().bold(),
+            filename.to_str().unwrap()
+        );
+        return Ok(false);
+    }
+    // binary files and formats without comments can only be managed by metafiles
+    let commit = compile_matches.get_flag("commit") || Config::load().auto_commit;
+    let message = format!(
+        "imosid: compile {}",
+        filename.file_name().unwrap().to_str().unwrap()
+    );
+    if *compile_matches.get_one("metafile").unwrap()
+        || !is_text_file(filename)?
+        || needs_metafile(filename.to_str().unwrap())
+    {
+        if stdout {
+            eprintln!("{}", "files managed by metafiles cannot be printed to stdout".red());
+            return Ok(false);
+        }
+        let mut newmetafile = MetaFile::from(filename.to_path_buf());
+        newmetafile.compile();
+        newmetafile.write_to_file();
+        println!("compiled {}", &filename.to_str().unwrap().bold());
+        if let Ok(compiled) = DotFile::from_pathbuf(filename) {
+            record_compile(&compiled);
+        }
+        if commit {
+            let metapath = format!("{}.imosid.toml", filename.to_str().unwrap());
+            git::commit_file(Path::new(&metapath), &message);
+        }
+        return Ok(true);
+    }
+    let opened = if compile_matches.get_flag("strict") {
+        DotFile::from_pathbuf_strict(filename)
+    } else {
+        DotFile::from_pathbuf(filename)
+    };
+    let mut compfile = match opened {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            eprintln!(
+                "{} {}",
+                filename.to_str().unwrap().red(),
+                "has malformed imosid comments, not compiling".red()
+            );
+            return Ok(false);
+        }
+        Err(_) => {
+            eprintln!("could not open file {}", filename.to_str().unwrap().red());
+            return Ok(false);
+        }
+    };
+    if stdout {
+        compfile.compile();
+        print!("{}", compfile.to_string());
+        return Ok(true);
+    }
+    if compfile.compile() {
+        compfile.write_to_file();
+        println!("compiled {}", filename.to_str().unwrap().bold());
+        record_compile(&compfile);
+        if commit {
+            git::commit_file(filename, &message);
+        }
+    } else {
+        println!(
+            "{} already compiled, no change",
+            filename.to_str().unwrap().bold().green()
+        );
+    }
+    Ok(true)
+}
+
+fn record_compile(dotfile: &DotFile) {
+    let mut state = StateDb::load();
+    state.record_compile(dotfile);
+    state.save();
+}
+
+// apply a single file or directory, return false if it failed
+fn apply_path(
+    path: &PathBuf,
+    config: &Config,
+    walkoptions: &WalkOptions,
+    options: &ApplyOptions,
+) -> bool {
+    if path.is_dir() {
+        if !apply_config_dir(path, walkoptions, options) {
+            println!("{} {}", "nothing to do in".bold(), path.to_str().unwrap());
+        }
+        return true;
+    }
+    if !path.is_file() {
+        eprintln!(
+            "{} {}",
+            "file does not exist".red().bold(),
+            path.to_str().unwrap()
+        );
+        return false;
+    }
+    let opened = if options.strict {
+        DotFile::from_pathbuf_strict(path)
+    } else {
+        DotFile::from_pathbuf(path)
+    };
+    let tmpsource = match opened {
+        Ok(file) => file,
+        Err(_) => {
+            eprintln!("could not open file {}", path.to_str().unwrap().red());
+            return false;
+        }
+    };
+    let mut hooks = Hooks::load(config.source.as_deref());
+    let mut changes = Vec::new();
+    let result = apply_with_hooks(&tmpsource, options, &mut hooks, &mut changes);
+    if let ApplyResult::Changed = result {
+        let mut state = StateDb::load();
+        state.record(&tmpsource, &changes, &options.facts);
+        state.save();
+        notify::after_apply(config, &changes, options.notify);
+    }
+    hooks.run_post_apply(&changes);
+    !matches!(result, ApplyResult::Error)
+}
+
+// print info about a single file or managed directory
+// return false if it is modified or could not be read
+fn info_path(filename: &PathBuf, diff: bool) -> io::Result<bool> {
+    if filename.join(DIR_METAFILE_NAME).is_file() {
+        return Ok(match DirMetaFile::new(filename) {
+            Ok(dirmetafile) => {
+                println!("{}", dirmetafile.pretty_info());
+                !dirmetafile.modified
+            }
+            Err(e) => {
+                eprintln!("invalid directory metafile: {}", e.red());
+                false
+            }
+        });
+    }
+    if !filename.is_file() {
+        eprintln!(
+            "{} {}",
+            "file does not exist".red().bold(),
+            filename.to_str().unwrap()
+        );
+        return Ok(false);
+    }
+    let infofile = DotFile::from_pathbuf(filename)?;
+    println!("{}", infofile.pretty_info());
+    if diff {
+        for (name, sectiondiff) in infofile.section_diffs() {
+            println!("{} {}", "section".bold(), name.bold());
+            match sectiondiff {
+                Some(sectiondiff) => print!("{}", sectiondiff),
+                None => println!("{}", "no snapshot to compare with".yellow()),
+            }
+        }
+    }
+    Ok(!infofile.modified)
+}
+
+// print the diagnostics of a single file, return the number of errors and warnings
+fn lint_file(path: &Path) -> io::Result<(usize, usize)> {
+    let filename = path.to_str().unwrap();
+    // metafiles manage files without looking at their content
+    if Path::new(&format!("{}.imosid.toml", filename)).is_file()
+        || needs_metafile(filename)
+        || !is_text_file(path)?
+    {
+        return Ok((0, 0));
+    }
+    let content = std::fs::read_to_string(path)?;
+    let diagnostics = DotFile::diagnose(&content, filename);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic.render(filename, &content));
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    Ok((errors, diagnostics.len() - errors))
+}
+
+// migrate a single file to the current syntax, return true if it is or was out of date
+fn migrate_file(path: &Path, check: bool) -> io::Result<bool> {
+    let mut dotfile = DotFile::from_pathbuf(&path.to_path_buf())?;
+    let version = match &dotfile.metafile {
+        Some(metafile) => metafile.syntaxversion().to_string(),
+        None => dotfile.syntaxversion.to_string(),
+    };
+    if !dotfile.migrate() {
+        return Ok(false);
+    }
+    if check {
+        println!(
+            "{} uses syntax version {}",
+            path.to_str().unwrap().yellow().bold(),
+            version
+        );
+        return Ok(true);
+    }
+    dotfile.write_to_file();
+    println!(
+        "migrated {} from syntax version {}",
+        path.to_str().unwrap().bold(),
+        version
+    );
+    Ok(true)
+}
+
 fn main() -> Result<(), std::io::Error> {
     let imosidapp = app::build_app();
     let matches = imosidapp.get_matches();
+    app::set_color_choice(&matches);
+    logging::init_logging(
+        matches.get_count("verbose"),
+        matches.get_flag("quiet"),
+        matches.get_one::<PathBuf>("log-file").map(|p| p.as_path()),
+    );
+
+    // commands writing to files must not interleave with other runs
+    let _lock = match matches.subcommand_name() {
+        Some(
+            "compile" | "tui" | "import" | "clean" | "update" | "delete" | "edit" | "section"
+            | "adopt" | "init" | "rename" | "repair" | "migrate" | "set" | "apply" | "merge"
+            | "sync" | "rollback" | "gc",
+        ) => lock::acquire(),
+        _ => None,
+    };
 
     match matches.subcommand() {
         // compile a file, making it an unmodified imosid file
         Some(("compile", compile_matches)) => {
-            let filename = compile_matches.get_one::<PathBuf>("file").unwrap();
-            check_file_arg!(filename);
-            if *compile_matches.get_one("metafile").unwrap() {
-                let mut newmetafile = MetaFile::from(filename.to_path_buf());
-                newmetafile.compile();
-                newmetafile.write_to_file();
-                println!("compiled {}", &filename.to_str().unwrap().bold());
+            // pipeline mode, nothing on disk is touched
+            if compile_matches.get_flag("stdin") {
+                let mut content = String::new();
+                io::stdin().read_to_string(&mut content)?;
+                let mut compfile = match compile_matches.get_one::<String>("comment-sign") {
+                    Some(commentsign) => {
+                        DotFile::from_str_with_comment_sign(&content, "stdin", commentsign)
+                    }
+                    None => DotFile::from_str(&content, "stdin"),
+                };
+                compfile.compile();
+                print!("{}", compfile.to_string());
                 return Ok(());
             }
-            let mut compfile = get_dotfile!(filename);
-            if compfile.compile() {
-                compfile.write_to_file();
-                println!("compiled {}", filename.to_str().unwrap().bold());
-            } else {
-                println!(
-                    "{} already compiled, no change",
-                    filename.to_str().unwrap().bold().green()
-                );
+            let mut failed = false;
+            for filename in compile_matches.get_many::<PathBuf>("file").unwrap() {
+                failed |= !compile_path(filename, compile_matches)?;
+            }
+            if failed {
+                std::process::exit(1);
             }
         }
         Some(("check", check_matches)) => {
             let filename = check_matches.get_one::<PathBuf>("directory").unwrap();
+            let quiet = check_matches.get_flag("quiet");
             if !filename.is_dir() {
                 eprintln!(
                     "{} is not a directory, only directories can be checked",
                     filename.to_str().unwrap().red()
                 );
-                return Ok(());
+                std::process::exit(2);
             }
             let mut anymodified = false;
-            for dotfile in walk_dotfiles(filename) {
-                if dotfile.modified {
-                    println!("{} {}", dotfile.filename.red().bold(), "modified".red());
+            let mut anyunmanaged = false;
+            let mut anyoutdated = false;
+            let mut anymalformed = false;
+            let checktargets = check_matches.get_flag("targets");
+            let strict = check_matches.get_flag("strict");
+            let options = ApplyOptions::new(Config::load().profile);
+            // the cache does not remember parse problems
+            let mut cache = HashCache::load(!check_matches.get_flag("no-cache") && !strict);
+            for entry in walk_config_dir(filename, &get_walk_options(check_matches)) {
+                let path = entry.path();
+                let mut parsed = None;
+                // the cache only saves hashing the content
+                let (modified, state) = match cache.get(path) {
+                    Some(cached) => (cached.modified, cached.state),
+                    None => {
+                        let opened = if strict {
+                            DotFile::from_pathbuf_strict(&path.to_path_buf())
+                        } else {
+                            DotFile::from_pathbuf(&path.to_path_buf())
+                        };
+                        let dotfile = match opened {
+                            Ok(dotfile) => dotfile,
+                            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                                if !quiet {
+                                    println!(
+                                        "{} {}",
+                                        path.to_str().unwrap().red().bold(),
+                                        "has malformed imosid comments".red()
+                                    );
+                                }
+                                anymalformed = true;
+                                continue;
+                            }
+                            Err(e) => {
+                                report_open_error(path, &e);
+                                continue;
+                            }
+                        };
+                        cache.insert(&dotfile);
+                        let result = (dotfile.modified, dotfile.management_state());
+                        parsed = Some(dotfile);
+                        result
+                    }
+                };
+                let entryname = path.to_str().unwrap();
+                // links can break without the source changing
+                if link_intact(path) == Some(false) {
+                    if !quiet {
+                        println!("{} {}", entryname.red().bold(), "link broken".red());
+                    }
+                    anymodified = true;
+                }
+                if modified {
+                    if !quiet {
+                        println!(
+                            "{} {} ({})",
+                            entryname.red().bold(),
+                            "modified".red(),
+                            state
+                        );
+                    }
                     anymodified = true;
                 }
-                if !dotfile.is_managed() {
+                if state == ManagementState::Unmanaged {
+                    if !quiet {
+                        println!("{} {}", entryname.yellow().bold(), "is unmanaged".yellow())
+                    }
+                    anyunmanaged = true;
+                }
+                // targets can only be out of date if the source is unmodified
+                if !checktargets || modified || state == ManagementState::Unmanaged {
+                    continue;
+                }
+                let dotfile = match parsed {
+                    Some(dotfile) => dotfile,
+                    None => match DotFile::from_pathbuf(&path.to_path_buf()) {
+                        Ok(dotfile) => dotfile,
+                        Err(_) => continue,
+                    },
+                };
+                if !options.facts.matches_profile(&dotfile.profile) {
+                    continue;
+                }
+                let outdated = match get_status(&dotfile, &options) {
+                    Some(FileStatus::NeedsApply) => "target out of date",
+                    Some(FileStatus::MissingTarget) => "target missing",
+                    Some(FileStatus::ReadonlyTarget) => "target out of date and read-only",
+                    _ => continue,
+                };
+                if !quiet {
+                    println!("{} {}", entryname.blue().bold(), outdated.blue());
+                }
+                anyoutdated = true;
+            }
+            cache.save();
+            for dir in walk_managed_dirs(filename, &get_walk_options(check_matches)) {
+                if dir.modified {
+                    if !quiet {
+                        println!(
+                            "{} {} (managed by directory metafile)",
+                            dir.dir.to_str().unwrap().red().bold(),
+                            "modified".red()
+                        );
+                    }
+                    anymodified = true;
+                }
+            }
+            let failed = match check_matches.get_one::<String>("fail-on").unwrap().as_str() {
+                "modified" => anymodified,
+                "unmanaged" => anyunmanaged,
+                "outdated" => anyoutdated,
+                _ => anymodified || anyunmanaged || anyoutdated,
+            };
+            if failed || anymalformed {
+                std::process::exit(1);
+            }
+        }
+
+        Some(("list", list_matches)) => {
+            let path = list_matches.get_one::<PathBuf>("path").unwrap();
+            let files = if path.is_dir() {
+                walk_dotfiles(path, &get_walk_options(list_matches))
+            } else if path.is_file() {
+                vec![get_dotfile!(path)]
+            } else {
+                eprintln!("{}", "file does not exist".red().bold());
+                return Ok(());
+            };
+            let filter = ListFilter {
+                section: list_matches.get_one::<String>("section").cloned(),
+                target: list_matches.get_one::<String>("target").cloned(),
+                modified: list_matches.get_flag("modified"),
+            };
+            print_list(&files, &filter);
+        }
+
+        Some(("status", status_matches)) => {
+            let directory = status_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                return Ok(());
+            }
+            let options = ApplyOptions::new(Config::load().profile);
+            for dotfile in walk_dotfiles(directory, &get_walk_options(status_matches)) {
+                if let Some(status) = get_status(&dotfile, &options) {
+                    println!("{}  {}", status.label(), dotfile.filename);
+                }
+            }
+        }
+
+        Some(("tui", tui_matches)) => {
+            let directory = tui_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                return Ok(());
+            }
+            let options = ApplyOptions::new(Config::load().profile);
+            tui::run_tui(directory, get_walk_options(tui_matches), options)?;
+        }
+
+        Some(("fetch", fetch_matches)) => {
+            let directory = fetch_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                std::process::exit(2);
+            }
+            let dotfiles = walk_dotfiles(directory, &get_walk_options(fetch_matches));
+            if !fetch_sources(&dotfiles) {
+                std::process::exit(1);
+            }
+        }
+        Some(("verify", verify_matches)) => {
+            let directory = verify_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                std::process::exit(2);
+            }
+            let options = ApplyOptions::new(Config::load().profile);
+            let mut anydrift = false;
+            for dotfile in walk_dotfiles(directory, &get_walk_options(verify_matches)) {
+                for (target, drift) in verify_file(&dotfile, &options) {
+                    println!("{} {}", target.bold(), drift.describe());
+                    anydrift = true;
+                }
+            }
+            if anydrift {
+                std::process::exit(1);
+            }
+            println!("{}", "all targets match their sources".green());
+        }
+        Some(("migrate", migrate_matches)) => {
+            let walkoptions = get_walk_options(migrate_matches);
+            let check = migrate_matches.get_flag("check");
+            let mut outdated = false;
+            for path in migrate_matches.get_many::<PathBuf>("path").unwrap() {
+                let files: Vec<PathBuf> = if path.is_dir() {
+                    walk_config_dir(path, &walkoptions)
+                        .map(|entry| entry.path().to_path_buf())
+                        .collect()
+                } else {
+                    vec![path.clone()]
+                };
+                for file in files {
+                    match migrate_file(&file, check) {
+                        Ok(migrated) => outdated |= migrated,
+                        Err(e) => report_open_error(&file, &e),
+                    }
+                }
+            }
+            if !outdated {
+                println!("{}", "all files use the current syntax".green());
+            } else if check {
+                std::process::exit(1);
+            }
+        }
+        Some(("lint", lint_matches)) => {
+            let walkoptions = get_walk_options(lint_matches);
+            let mut errors = 0;
+            let mut warnings = 0;
+            for path in lint_matches.get_many::<PathBuf>("path").unwrap() {
+                let files: Vec<PathBuf> = if path.is_dir() {
+                    walk_config_dir(path, &walkoptions)
+                        .map(|entry| entry.path().to_path_buf())
+                        .collect()
+                } else {
+                    vec![path.clone()]
+                };
+                for file in files {
+                    match lint_file(&file) {
+                        Ok((fileerrors, filewarnings)) => {
+                            errors += fileerrors;
+                            warnings += filewarnings;
+                        }
+                        Err(e) => {
+                            report_open_error(&file, &e);
+                            errors += 1;
+                        }
+                    }
+                }
+            }
+            if errors + warnings == 0 {
+                println!("{}", "no problems found".green());
+                return Ok(());
+            }
+            println!("{} errors, {} warnings", errors, warnings);
+            if errors > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(("export", export_matches)) => {
+            let directory = export_matches.get_one::<PathBuf>("directory").unwrap();
+            let output = export_matches.get_one::<PathBuf>("output").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                return Ok(());
+            }
+            let count =
+                archive::export_archive(directory, &get_walk_options(export_matches), output)?;
+            println!(
+                "exported {} files to {}",
+                count,
+                output.to_str().unwrap().bold()
+            );
+        }
+
+        Some(("systemd", systemd_matches)) => match systemd_matches.subcommand() {
+            Some(("install", install_matches)) => {
+                let directory = match install_matches
+                    .get_one::<PathBuf>("directory")
+                    .cloned()
+                    .or(Config::load().source)
+                {
+                    Some(directory) => directory,
+                    None => {
+                        eprintln!("no config directory set, pass one to install");
+                        std::process::exit(2);
+                    }
+                };
+                // units do not run in the current directory
+                let directory = match directory.canonicalize() {
+                    Ok(directory) if directory.is_dir() => directory,
+                    _ => {
+                        eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                        std::process::exit(2);
+                    }
+                };
+                let interval = install_matches.get_one::<String>("interval").unwrap();
+                let interval = match systemd::parse_interval(interval) {
+                    Some(interval) => interval,
+                    None => {
+                        eprintln!("invalid interval {}, use something like 1h", interval.red());
+                        std::process::exit(2);
+                    }
+                };
+                let apply_args: Vec<String> = get_vec_args(install_matches, "apply-args")
+                    .into_iter()
+                    .map(String::from)
+                    .collect();
+                let service =
+                    systemd::service_unit(&std::env::current_exe()?, &directory, &apply_args);
+                let timer = systemd::timer_unit(&interval);
+                if install_matches.get_flag("print") {
+                    println!("# {}\n{}", systemd::SERVICE_NAME, service);
+                    println!("# {}\n{}", systemd::TIMER_NAME, timer);
+                    return Ok(());
+                }
+                if !systemd::install(&service, &timer)? {
+                    eprintln!("{}", "could not start the timer".red());
+                    std::process::exit(1);
+                }
+                println!("applying {} every {}", directory.display().to_string().bold(), interval);
+            }
+            Some(("uninstall", _)) if !systemd::uninstall()? => {
+                println!("{}", "no timer installed".bold());
+            }
+            _ => {}
+        },
+
+        Some(("serve", serve_matches)) => {
+            let directory = serve_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                std::process::exit(2);
+            }
+            let port = *serve_matches.get_one::<u16>("port").unwrap();
+            let address = serve_matches.get_one::<String>("bind").unwrap();
+            serve::serve(directory, address, port)?;
+        }
+
+        Some(("deploy", deploy_matches)) => {
+            let directory = deploy_matches.get_one::<PathBuf>("directory").unwrap();
+            if !directory.is_dir() {
+                eprintln!("{} is not a directory", directory.to_str().unwrap().red());
+                std::process::exit(2);
+            }
+            let deployment = Deployment {
+                remote_dir: deploy_matches.get_one::<String>("remote-dir").unwrap().clone(),
+                imosid: deploy_matches.get_one::<String>("remote-imosid").unwrap().clone(),
+                apply_args: get_vec_args(deploy_matches, "apply-args")
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            };
+            let hosts: Vec<String> = get_vec_args(deploy_matches, "host")
+                .into_iter()
+                .map(String::from)
+                .collect();
+            let failed =
+                match deployment.deploy(directory, &get_walk_options(deploy_matches), &hosts) {
+                    Ok(failed) => failed,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                        eprintln!("{}", e.to_string().red());
+                        std::process::exit(2);
+                    }
+                    Err(e) => return Err(e),
+                };
+            if !failed.is_empty() {
+                eprintln!("deploy failed on {}", failed.join(", ").red());
+                std::process::exit(1);
+            }
+        }
+
+        Some(("import", import_matches)) => {
+            if let Some(("dotbot", dotbot_matches)) = import_matches.subcommand() {
+                let config = dotbot_matches.get_one::<PathBuf>("config").unwrap();
+                check_file_arg!(config);
+                let count =
+                    importer::import_dotbot(config, dotbot_matches.get_flag("metafile"))?;
+                println!("imported {} files", count);
+                return Ok(());
+            }
+            if let Some((kind, kind_matches)) = import_matches.subcommand() {
+                let sourcedir = kind_matches.get_one::<PathBuf>("directory").unwrap();
+                if !sourcedir.is_dir() {
+                    eprintln!("{} is not a directory", sourcedir.to_str().unwrap().red());
+                    return Ok(());
+                }
+                let targetdir = kind_matches
+                    .get_one::<PathBuf>("target")
+                    .map(PathBuf::as_path);
+                let count = match kind {
+                    "stow" => importer::import_stow(sourcedir, targetdir)?,
+                    "chezmoi" => importer::import_chezmoi(sourcedir, targetdir)?,
+                    _ => return Ok(()),
+                };
+                println!("imported {} files", count);
+                return Ok(());
+            }
+            let archivepath = import_matches.get_one::<PathBuf>("archive").unwrap();
+            check_file_arg!(archivepath);
+            let config = Config::load();
+            let into = match import_matches
+                .get_one::<PathBuf>("into")
+                .cloned()
+                .or(config.source)
+            {
+                Some(into) => into,
+                None => {
+                    eprintln!("no config directory set, use --into to choose one");
+                    return Ok(());
+                }
+            };
+            archive::import_archive(archivepath, &into)?;
+            println!("imported into {}", into.to_str().unwrap().bold());
+            if import_matches.get_flag("no-apply") {
+                return Ok(());
+            }
+            let options = ApplyOptions::new(config.profile);
+            if !apply_config_dir(&into, &WalkOptions::default(), &options) {
+                println!("{}", "nothing to do".bold());
+            }
+        }
+
+        Some(("log", log_matches)) => {
+            let state = StateDb::load();
+            let target = log_matches.get_one::<String>("target");
+            let history = state.history(target.map(|target| target.as_str()));
+            if history.is_empty() {
+                println!("{}", "nothing recorded yet".bold());
+                return Ok(());
+            }
+            let limit = log_matches.get_one::<usize>("limit").copied().unwrap_or(usize::MAX);
+            let skip = history.len().saturating_sub(limit);
+            for (id, record, previous) in history.iter().skip(skip) {
+                let action = match record.action {
+                    Action::Apply => record.action.as_str().green().bold(),
+                    Action::Compile => record.action.as_str().blue().bold(),
+                    Action::Rollback => record.action.as_str().yellow().bold(),
+                };
+                print!(
+                    "{} {} {:<8} {}",
+                    format!("#{}", id).dimmed(),
+                    format_time(record.time),
+                    action,
+                    collapse_tilde(&record.target).bold()
+                );
+                if record.action != Action::Compile {
+                    print!(" from {}", collapse_tilde(&record.source));
+                }
+                println!();
+                let changes = record.changes(*previous);
+                if changes.is_empty() {
+                    println!("    {}", "no changes".dimmed());
+                }
+                for change in changes {
+                    println!("    {}", change);
+                }
+                for (name, hash) in &record.hashes {
+                    println!("    {} {}", name.dimmed(), hash.dimmed());
+                }
+            }
+        }
+
+        Some(("rollback", rollback_matches)) => {
+            let filename = rollback_matches.get_one::<PathBuf>("target").unwrap();
+            check_file_arg!(filename);
+            let target = filename.to_str().unwrap();
+            let mut state = StateDb::load();
+            let to = rollback_matches.get_one::<String>("to").map(|to| to.as_str());
+            let record = match state.find_rollback(target, to) {
+                Ok(record) => record.clone(),
+                Err(e) => {
+                    eprintln!("{}", e.red());
+                    std::process::exit(1);
+                }
+            };
+            let mut targetfile = get_dotfile!(filename);
+            if !targetfile.rollback(&record.hashes, rollback_matches.get_flag("force")) {
+                println!("{} left unchanged", target.bold());
+                return Ok(());
+            }
+            targetfile.write_to_file();
+            state.record_rollback(target, &record);
+            state.save();
+            println!(
+                "rolled back {} to {} from {}",
+                target.bold(),
+                format_time(record.time),
+                collapse_tilde(&record.source)
+            );
+        }
+
+        Some(("gc", gc_matches)) => {
+            let mut retention = Config::load().retention;
+            if let Some(max_age) = gc_matches.get_one::<u64>("max-age") {
+                retention.max_age = Some(*max_age).filter(|days| *days > 0);
+            }
+            if let Some(max_count) = gc_matches.get_one::<u64>("max-count") {
+                retention.max_count = Some(*max_count as usize).filter(|count| *count > 0);
+            }
+            if let Some(max_size) = gc_matches.get_one::<String>("max-size") {
+                match parse_size(max_size) {
+                    Some(size) => retention.max_size = Some(size).filter(|size| *size > 0),
+                    None => {
+                        eprintln!("{} {}", "invalid size".red(), max_size);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            let dryrun = gc_matches.get_flag("dry-run");
+            let mut summary = Vec::new();
+            let mut freed = 0;
+            for (kind, entries) in find_garbage(&retention) {
+                let mut removed = 0;
+                for entry in entries {
+                    if dryrun {
+                        println!("would remove {}", entry.path.to_str().unwrap());
+                    } else if let Err(e) = entry.remove() {
+                        eprintln!("could not remove {}: {}", entry.path.to_str().unwrap().red(), e);
+                        continue;
+                    }
+                    removed += 1;
+                    freed += entry.size;
+                }
+                if removed > 0 {
+                    summary.push(format!("{} {}", removed, kind));
+                }
+            }
+            if summary.is_empty() {
+                println!("{}", "nothing to remove".bold());
+            } else if dryrun {
+                println!("would remove {}, {}", summary.join(", "), format_size(freed).bold());
+            } else {
+                println!("removed {}, freed {}", summary.join(", "), format_size(freed).bold());
+            }
+        }
+
+        Some(("clean", clean_matches)) => {
+            let delete = clean_matches.get_flag("delete");
+            let state = StateDb::load();
+            let orphans = state.find_orphans(&ApplyOptions::new(Config::load().profile).facts);
+            if orphans.is_empty() {
+                println!("{}", "no orphaned targets".bold());
+                return Ok(());
+            }
+            for record in orphans {
+                let target = collapse_tilde(&record.target);
+                if !delete {
+                    println!("{} {}", target.yellow().bold(), "is orphaned".yellow());
+                    continue;
+                }
+                // only remove what imosid wrote and nobody touched since
+                let untouched = match DotFile::new(&record.target) {
+                    Ok(targetfile) => targetfile.is_managed() && !targetfile.modified,
+                    Err(_) => false,
+                };
+                if !untouched {
                     println!(
                         "{} {}",
-                        dotfile.filename.yellow().bold(),
-                        "is unmanaged".yellow()
-                    )
+                        target.red().bold(),
+                        "has local changes, keeping".red()
+                    );
+                    continue;
+                }
+                match std::fs::remove_file(&record.target) {
+                    Ok(_) => println!("deleted {}", target.green()),
+                    Err(_) => eprintln!("could not delete {}", target.red()),
                 }
             }
         }
 
         Some(("query", query_matches)) => {
             let filename = query_matches.get_one::<PathBuf>("file").unwrap();
-            let query_sections = get_vec_args(query_matches, "section");
+            let all = query_matches.get_flag("all");
+            let raw = query_matches.get_flag("raw");
 
             check_file_arg!(filename);
 
             let queryfile = get_dotfile!(filename);
 
-            if queryfile.metafile.is_some() {
-                todo!("add message for this");
+            let field = query_matches.get_one::<String>("field");
+            if let Some(metafile) = &queryfile.metafile {
+                if let Some(field) = field {
+                    match metafile.get_field(field) {
+                        Some(value) => println!("{}", value),
+                        None => std::process::exit(1),
+                    }
+                    return Ok(());
+                }
+                // metafiles have no sections, the whole file is printed
+                if !raw {
+                    println!("{}", metafile.pretty_info());
+                    for field in ["target", "source"] {
+                        if let Some(value) = metafile.get_field(field) {
+                            println!("{}: {}", field, value.bold());
+                        }
+                    }
+                }
+                io::stdout().write_all(&metafile.content)?;
+                return Ok(());
+            }
+            if field.is_some() {
+                eprintln!("fields can only be queried from files managed by a metafile");
                 return Ok(());
             }
+            let matcher = if all {
+                SectionMatcher::default()
+            } else {
+                match pick_section_matcher(query_matches, &queryfile.section_names()) {
+                    Some(matcher) => matcher,
+                    None => return Ok(()),
+                }
+            };
 
             for i in &queryfile.sections {
-                if let Section::Named(_, named_data) = i {
-                    for query in &query_sections {
-                        if query.eq(&named_data.name) {
-                            println!("{}", i.output(&queryfile.commentsign));
-                        }
+                if let Section::Named(data, named_data) = i {
+                    if !all && !matcher.matches(&named_data.name) {
+                        continue;
+                    }
+                    if raw {
+                        print!("{}", data.content);
+                    } else {
+                        println!("{}", i.output(&queryfile.commentsign));
                     }
                 }
             }
@@ -127,70 +1037,460 @@ fn main() -> Result<(), std::io::Error> {
         Some(("update", update_matches)) => {
             let filename = update_matches.get_one::<PathBuf>("file").unwrap();
 
-            let sections = get_vec_args(update_matches, "section");
+            let matcher = match get_section_matcher(update_matches) {
+                Some(matcher) => matcher,
+                None => return Ok(()),
+            };
 
             check_file_arg!(filename);
+            if !git::check_clean(filename, update_matches.get_flag("require-clean")) {
+                std::process::exit(1);
+            }
 
             let mut updatefile = get_dotfile!(filename);
-            updatefile.update();
+            let mut lockfile = LockFile::find(filename);
+            lockfile.locked = update_matches.get_flag("locked");
+            let offline = update_matches.get_flag("offline");
+            let updated = updatefile.update(&matcher, offline, &mut lockfile);
 
-            match updatefile.metafile {
-                Some(_) => {
-                    eprintln!("cannot update metafile");
-                    return Ok(());
-                }
-                None => {}
+            if update_matches.get_flag("print") {
+                print!("{}", updatefile.to_string());
+                return Ok(());
             }
-
-            if sections.is_empty() {
-                // update all sections
+            if updated {
+                updatefile.write_to_file();
+            }
+            if lockfile.changed && lockfile.save() {
+                println!("updated {}", lockfile.path.to_str().unwrap().bold());
             }
         }
         Some(("delete", delete_matches)) => {
             let filename = delete_matches.get_one::<PathBuf>("file").unwrap();
 
-            let sections = get_vec_args(delete_matches, "section");
-
             check_file_arg!(filename);
 
             let mut deletefile = get_dotfile!(filename);
+            let matcher = match pick_section_matcher(delete_matches, &deletefile.section_names()) {
+                Some(matcher) => matcher,
+                None => return Ok(()),
+            };
+            let print = delete_matches.get_flag("print");
 
-            for i in sections {
-                if deletefile.deletesection(i) {
+            for pattern in matcher.unmatched(&deletefile.section_names()) {
+                eprintln!("could not find section {}", pattern.red());
+            }
+            let names: Vec<String> = deletefile
+                .section_names()
+                .into_iter()
+                .filter(|name| matcher.matches(name))
+                .map(String::from)
+                .collect();
+            for i in &names {
+                // keep printed output clean for piping
+                if deletefile.deletesection(i) && !print {
                     println!("deleted section {}", i.bold());
-                } else {
-                    println!("could not find section {}", i.red());
                 }
             }
-            deletefile.write_to_file();
+            if print {
+                print!("{}", deletefile.to_string());
+            } else {
+                deletefile.write_to_file();
+            }
         }
 
-        Some(("apply", apply_matches)) => {
-            let path = apply_matches.get_one::<PathBuf>("file").unwrap();
-            if path.is_dir() {
-                if !apply_config_dir(path) {
-                    println!("{}", "nothing to do".bold());
+        Some(("edit", edit_matches)) => {
+            let filename = edit_matches.get_one::<PathBuf>("file").unwrap();
+            check_file_arg!(filename);
+            let mut editfile = get_dotfile!(filename);
+            if editfile.metafile.is_some() {
+                eprintln!("files managed by a metafile have no sections to edit");
+                return Ok(());
+            }
+            let name = match edit_matches.get_one::<String>("section") {
+                Some(name) => name.clone(),
+                None => {
+                    let names: Vec<String> = editfile
+                        .section_names()
+                        .into_iter()
+                        .map(String::from)
+                        .collect();
+                    let picked = picker::pick("section", &names)
+                        .and_then(|picked| picked.into_iter().next());
+                    match picked {
+                        Some(name) => name,
+                        None => {
+                            eprintln!("no section given");
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+            let content = match editfile.get_section(&name) {
+                Some(Section::Named(data, _)) => data.content,
+                _ => {
+                    eprintln!("could not find section {}", name.red());
+                    return Ok(());
                 }
+            };
+            match reconcile::edit_content(&name, &content) {
+                Some(edited) if edited != content => {
+                    editfile.set_section_content(&name, &edited);
+                    editfile.write_to_file();
+                    println!("edited section {}", name.bold());
+                }
+                Some(_) => println!("section {} unchanged", name.bold()),
+                None => {}
+            }
+        }
+
+        Some(("section", section_matches)) => {
+            if let Some(("add", add_matches)) = section_matches.subcommand() {
+                let filename = add_matches.get_one::<PathBuf>("file").unwrap();
+                let name = add_matches.get_one::<String>("name").unwrap();
+
+                check_file_arg!(filename);
+
+                let mut addfile = get_dotfile!(filename);
+
+                let range = match add_matches.get_one::<String>("around-match") {
+                    Some(pattern) => {
+                        let regex = match Regex::new(pattern) {
+                            Ok(regex) => regex,
+                            Err(_) => {
+                                eprintln!("invalid regex {}", pattern.red());
+                                return Ok(());
+                            }
+                        };
+                        match addfile.find_match_range(&regex) {
+                            Some(range) => range,
+                            None => {
+                                eprintln!("no line matches {}", pattern.red());
+                                return Ok(());
+                            }
+                        }
+                    }
+                    None => match (
+                        add_matches.get_one::<u32>("from-line"),
+                        add_matches.get_one::<u32>("to-line"),
+                    ) {
+                        (Some(from), Some(to)) => (*from, *to),
+                        _ => {
+                            eprintln!(
+                                "either --around-match or --from-line and --to-line required"
+                            );
+                            return Ok(());
+                        }
+                    },
+                };
+
+                if addfile.wrap_lines(name, range.0, range.1) {
+                    println!(
+                        "added section {} for lines {}-{}",
+                        name.bold(),
+                        range.0,
+                        range.1
+                    );
+                }
+            }
+        }
+
+        Some(("adopt", adopt_matches)) => {
+            let target = adopt_matches.get_one::<PathBuf>("target").unwrap();
+            let into = adopt_matches.get_one::<PathBuf>("into").unwrap();
+
+            check_file_arg!(target);
+
+            let targetpath = target.canonicalize()?;
+            let targetname = collapse_tilde(&targetpath.display().to_string());
+
+            // mirror the location relative to the home directory inside the config dir
+            let destination = match targetname.strip_prefix("~/") {
+                Some(relative) => into.join(relative),
+                None => into.join(targetpath.file_name().unwrap()),
+            };
+            if destination.exists() {
+                eprintln!("{} already exists", destination.to_str().unwrap().red());
                 return Ok(());
-            } else if path.is_file() {
-                let tmpsource = get_dotfile!(path);
-                tmpsource.apply();
+            }
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(&targetpath, &destination)?;
+
+            let name = adopt_matches.get_one::<String>("name").map(String::as_str);
+            let metafile = *adopt_matches.get_one::<bool>("metafile").unwrap();
+            if !importer::manage_file(&destination, &targetname, name, metafile, None)? {
+                eprintln!("could not wrap {} into a section", targetname.red());
+                return Ok(());
+            }
+            println!(
+                "adopted {} into {}",
+                targetname.bold(),
+                destination.to_str().unwrap().bold()
+            );
+        }
+
+        Some(("init", init_matches)) => {
+            let dir = init_matches.get_one::<PathBuf>("directory").unwrap();
+            init::init_config_dir(
+                dir,
+                init_matches.get_flag("manifest"),
+                init_matches.get_flag("git"),
+            )?;
+            if init_matches.get_flag("scan") {
+                init::suggest_candidates(dir);
+            }
+        }
+
+        Some(("repair", repair_matches)) => {
+            let filename = repair_matches.get_one::<PathBuf>("file").unwrap();
+            check_file_arg!(filename);
+            if needs_metafile(filename.to_str().unwrap())
+                || Path::new(&format!("{}.imosid.toml", filename.to_str().unwrap())).is_file()
+            {
+                eprintln!("cannot repair file managed by metafile");
+                return Ok(());
+            }
+            if repair::repair_file(filename, repair_matches.get_flag("assume-yes"))? {
+                println!("repaired {}", filename.to_str().unwrap().bold());
             } else {
-                eprintln!("{}", "file does not exist".red().bold());
+                println!("{} left unchanged", filename.to_str().unwrap().bold());
+            }
+        }
+        Some(("rename", rename_matches)) => {
+            let filename = rename_matches.get_one::<PathBuf>("file").unwrap();
+            let from = rename_matches.get_one::<String>("from").unwrap();
+            let to = rename_matches.get_one::<String>("to").unwrap();
+
+            check_file_arg!(filename);
+
+            let mut renamefile = get_dotfile!(filename);
+            if renamefile.metafile.is_some() {
+                eprintln!("cannot rename sections of file managed by metafile");
                 return Ok(());
             }
+
+            let target = renamefile.get_section_target(from);
+            if !renamefile.renamesection(from, to) {
+                eprintln!("could not rename section {} to {}", from.red(), to.red());
+                return Ok(());
+            }
+            renamefile.write_to_file();
+            println!("renamed section {} to {}", from.bold(), to.bold());
+
+            if rename_matches.get_flag("propagate") {
+                if let Some(target) = target {
+                    let targetpath = expand_tilde(&target);
+                    if !PathBuf::from(&targetpath).is_file() {
+                        println!("target {} does not exist yet", target.bold());
+                        return Ok(());
+                    }
+                    let mut targetfile = get_dotfile!(&PathBuf::from(&targetpath));
+                    if targetfile.renamesection(from, to) {
+                        targetfile.write_to_file();
+                        println!("renamed section {} in {}", from.bold(), target.bold());
+                    } else {
+                        println!(
+                            "could not rename section {} in {}",
+                            from.red(),
+                            target.red()
+                        );
+                    }
+                }
+            }
         }
-        Some(("info", info_matches)) => {
-            let filename = info_matches.get_one::<PathBuf>("file").unwrap();
+
+        Some(("set", set_matches)) => {
+            let filename = set_matches.get_one::<PathBuf>("file").unwrap();
+            let target = set_matches.get_one::<String>("target").map(|s| s.as_str());
+            let source = set_matches.get_one::<String>("source").map(|s| s.as_str());
+            let permissions = match set_matches.get_one::<String>("permissions") {
+                Some(mode) => match Permissions::parse(mode) {
+                    Some(permissions) => Some(permissions),
+                    None => {
+                        eprintln!("invalid permissions {}", mode.red());
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            if target.is_none() && source.is_none() && permissions.is_none() {
+                eprintln!("nothing to set, use --target, --permissions or --source");
+                std::process::exit(1);
+            }
+
             check_file_arg!(filename);
-            let infofile = DotFile::from_pathbuf(filename)?;
-            println!("{}", infofile.pretty_info());
+            let mut setfile = get_dotfile!(filename);
+            match set_matches.get_one::<String>("section") {
+                Some(section) => {
+                    if setfile.metafile.is_some() {
+                        eprintln!("cannot set section properties of file managed by metafile");
+                        std::process::exit(1);
+                    }
+                    if !setfile.set_section_info(section, target, source) {
+                        eprintln!("could not find section {}", section.red());
+                        std::process::exit(1);
+                    }
+                }
+                None => {
+                    if !setfile.set_file_info(target, permissions, source) {
+                        eprintln!(
+                            "only sections and files managed by metafiles have a source, use {}",
+                            "--section".bold()
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+            setfile.write_to_file();
+            println!("updated {}", filename.to_str().unwrap().bold());
+        }
 
-            if infofile.modified {
+        Some(("apply", apply_matches)) => {
+            let config = Config::load();
+            let profile = apply_matches
+                .get_one::<String>("profile")
+                .cloned()
+                .or(config.profile.clone());
+            let mut options = ApplyOptions::new(profile);
+            options.notify = !apply_matches.get_flag("no-notify")
+                && (apply_matches.get_flag("notify") || config.notify);
+            options.add_missing = apply_matches.get_flag("add-missing");
+            options.prune_sections = apply_matches.get_flag("prune-sections");
+            options.link = apply_matches.get_flag("link");
+            options.strict = apply_matches.get_flag("strict");
+            options.force = apply_matches.get_flag("force");
+            if apply_matches.get_flag("sudo") {
+                options.root = RootPolicy::Sudo;
+            } else if apply_matches.get_flag("skip-root") {
+                options.root = RootPolicy::Skip;
+            }
+            let readonly = apply_matches.get_one::<String>("readonly").unwrap();
+            options.readonly = match readonly.as_str() {
+                "skip" => ReadonlyPolicy::Skip,
+                "chmod" => ReadonlyPolicy::Chmod,
+                _ => ReadonlyPolicy::Error,
+            };
+            options.preserve_mtime = apply_matches.get_flag("preserve-mtime");
+            options.cache = !apply_matches.get_flag("no-cache");
+            options.changed_only = apply_matches.get_flag("changed-only");
+            options.merge = apply_matches.get_flag("merge");
+            if apply_matches.get_one::<String>("conflict").unwrap() == "markers" {
+                options.conflict = ConflictStyle::Markers;
+            }
+            match (
+                SectionMatcher::new(&get_vec_args(apply_matches, "only-section"), &[]),
+                SectionMatcher::new(&get_vec_args(apply_matches, "skip-section"), &[]),
+            ) {
+                (Ok(only), Ok(skip)) => {
+                    options.only_sections = only;
+                    options.skip_sections = skip;
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("invalid section pattern: {}", e);
+                    return Ok(());
+                }
+            }
+            let mut walkoptions = get_walk_options(apply_matches);
+            walkoptions.only.extend(
+                get_vec_args(apply_matches, "only-glob")
+                    .into_iter()
+                    .map(String::from),
+            );
+            let mut failed = false;
+            for path in apply_matches.get_many::<PathBuf>("file").unwrap() {
+                if !git::check_clean(path, apply_matches.get_flag("require-clean")) {
+                    failed = true;
+                    continue;
+                }
+                failed |= !apply_path(path, &config, &walkoptions, &options);
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Some(("merge", merge_matches)) => {
+            let target = merge_matches.get_one::<PathBuf>("target").unwrap();
+            check_file_arg!(target);
+            reconcile::reconcile_target(target);
+        }
+        Some(("sync", sync_matches)) => {
+            let target = sync_matches.get_one::<PathBuf>("target").unwrap();
+            check_file_arg!(target);
+            let source = match reconcile::sync_target(target, sync_matches.get_flag("dry-run")) {
+                Some(source) => source,
+                None => return Ok(()),
+            };
+            if sync_matches.get_flag("commit") || Config::load().auto_commit {
+                git::commit_file(
+                    Path::new(&source),
+                    &format!("imosid: sync {}", target.to_str().unwrap()),
+                );
+            }
+        }
+        Some(("info", info_matches)) => {
+            let mut failed = false;
+            for filename in info_matches.get_many::<PathBuf>("file").unwrap() {
+                failed |= !info_path(filename, info_matches.get_flag("diff"))?;
+            }
+            if failed {
                 // give caller an easy way to tell if a file is modified
                 std::process::exit(1);
             }
         }
+        Some(("hash", hash_matches)) => {
+            let filename = hash_matches.get_one::<PathBuf>("file").unwrap();
+            check_file_arg!(filename);
+            let algorithm = hash_matches
+                .get_one::<String>("algorithm")
+                .and_then(|tag| HashAlgorithm::from_tag(tag));
+            let section = hash_matches.get_one::<String>("section");
+            let hashfile = get_dotfile!(filename);
+            // output is only the hash, failures are told by the exit code
+            let hash = match (section, &hashfile.metafile) {
+                (None, Some(metafile)) => Some(
+                    algorithm
+                        .unwrap_or(HashAlgorithm::from_hash(&metafile.hash))
+                        .digest(&metafile.content),
+                ),
+                (None, None) => Some(
+                    algorithm
+                        .unwrap_or(HashAlgorithm::DEFAULT)
+                        .digest(&std::fs::read(filename)?),
+                ),
+                (Some(name), Some(metafile)) => metafile.get_region(name).and_then(|region| {
+                    let content = String::from_utf8_lossy(&metafile.content);
+                    match algorithm {
+                        Some(algorithm) => region
+                            .content(&content)
+                            .map(|region| algorithm.digest(region.as_bytes())),
+                        None => region.get_content_hash(&content),
+                    }
+                }),
+                (Some(name), None) => match hashfile.get_section(name) {
+                    Some(Section::Named(data, named_data)) => Some(match algorithm {
+                        Some(algorithm) => algorithm
+                            .digest(hashed_content(&named_data.name, &data.content).as_bytes()),
+                        None => named_data.hash,
+                    }),
+                    _ => None,
+                },
+            };
+            match hash {
+                Some(hash) => println!("{}", hash),
+                None => {
+                    eprintln!("could not find section {}", section.unwrap().red());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(("env", env_matches)) => {
+            let config = Config::load();
+            match env_matches.get_one::<String>("format").unwrap().as_str() {
+                "json" => print!("{}", config.to_json()),
+                _ => print!("{}", config.to_shell()),
+            }
+        }
         Some((&_, _)) => {
             //TODO: do this better
             return Ok(());
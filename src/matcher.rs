@@ -0,0 +1,75 @@
+use regex::Regex;
+use std::fmt;
+
+// selects named sections by exact name, glob pattern or regex
+// an empty matcher matches every section
+#[derive(Clone, Default)]
+pub struct SectionMatcher {
+    patterns: Vec<(String, Regex)>,
+}
+
+// translate a glob with * and ? wildcards into an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+impl SectionMatcher {
+    pub fn new(globs: &[&str], regexes: &[&str]) -> Result<SectionMatcher, regex::Error> {
+        let mut patterns = Vec::new();
+        for glob in globs {
+            patterns.push((String::from(*glob), Regex::new(&glob_to_regex(glob))?));
+        }
+        for regex in regexes {
+            patterns.push((String::from(*regex), Regex::new(regex)?));
+        }
+        Ok(SectionMatcher { patterns })
+    }
+
+    /// match exactly names, without treating them as patterns
+    pub fn exact(names: &[String]) -> SectionMatcher {
+        let patterns = names
+            .iter()
+            .map(|name| {
+                let regex = format!("^{}$", regex::escape(name));
+                (
+                    name.clone(),
+                    Regex::new(&regex).expect("escaped names are valid regexes"),
+                )
+            })
+            .collect();
+        SectionMatcher { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_empty() || self.patterns.iter().any(|(_, regex)| regex.is_match(name))
+    }
+
+    /// patterns which match none of names
+    pub fn unmatched(&self, names: &[&str]) -> Vec<&str> {
+        self.patterns
+            .iter()
+            .filter(|(_, regex)| !names.iter().any(|name| regex.is_match(name)))
+            .map(|(pattern, _)| pattern.as_str())
+            .collect()
+    }
+}
+
+impl fmt::Display for SectionMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let patterns: Vec<&str> = self.patterns.iter().map(|(p, _)| p.as_str()).collect();
+        write!(f, "{}", patterns.join(","))
+    }
+}
@@ -1,13 +1,73 @@
-use crate::built_info;
-use crate::hashable::{ChangeState, Hashable};
+use crate::files::{check_imosid_version, imosid_version, ApplyOptions};
+use crate::hashable::{ChangeState, HashAlgorithm, Hashable};
+use crate::permissions::Permissions;
+use crate::privilege::{set_permissions, write_file, RootPolicy};
+use crate::region::{Region, RegionData, RegionLocation};
 use colored::Colorize;
 use semver::Version;
-use sha256::digest;
-use std::fs::{self, read_to_string, File};
-use std::io::Write;
-use std::os::unix::prelude::PermissionsExt;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, read_to_string};
+use std::path::{Path, PathBuf};
+use toml::map::Map;
 use toml::Value;
+use tracing::{error, warn};
+
+// metafiles with regions need at least this syntax version
+const REGION_SYNTAX_VERSION: i64 = 2;
+// version written by this imosid, version 1 stored permissions as octal integers
+pub const METAFILE_SYNTAX_VERSION: i64 = 2;
+
+// a number which older metafiles stored as a string
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NumberField {
+    Integer(i64),
+    String(String),
+}
+
+impl NumberField {
+    fn as_string(&self) -> String {
+        match self {
+            NumberField::Integer(number) => number.to_string(),
+            NumberField::String(string) => string.clone(),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+// the fields of a .imosid.toml file as they are stored on disk
+#[derive(Serialize, Deserialize)]
+struct MetaFileData {
+    hash: String,
+    parent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    // integers are read as octal digits for older metafiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<NumberField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    link: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    syntaxversion: Option<NumberField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    imosidversion: Option<String>,
+    #[serde(default, rename = "region", skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<RegionData>,
+    // fields of newer versions are kept when the metafile is written again
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
 
 // a file containing metadata about an imosid file for file types which do not support comments
 pub struct MetaFile {
@@ -19,100 +79,137 @@ pub struct MetaFile {
     pub modified: bool,
     imosidversion: Version,
     syntaxversion: i64,
-    value: Value,
-    pub content: String,
+    extra: Map<String, Value>,
+    pub content: Vec<u8>, // raw bytes, metafiles can manage binary files
     path: PathBuf,
-    pub permissions: Option<u32>,
+    pub permissions: Option<Permissions>,
+    pub profile: Option<String>,
+    pub link: bool,           // target is a symlink to the source
+    pub regions: Vec<Region>, // only these parts of the file are managed if there are any
 }
 
 impl Hashable for MetaFile {
     // check for modifications
     fn finalize(&mut self) {
         self.currenthash = self.get_content_hash();
-        self.modified = self.hash != self.currenthash;
+        if self.regions.is_empty() {
+            self.modified = self.hash != self.currenthash;
+            return;
+        }
+        // the rest of the file belongs to the user
+        let content = String::from_utf8_lossy(&self.content).to_string();
+        for region in &mut self.regions {
+            region.modified = region
+                .get_content_hash(&content)
+                .is_none_or(|hash| hash != region.hash);
+        }
+        self.modified = self.regions.iter().any(|region| region.modified);
     }
 
     fn compile(&mut self) -> ChangeState {
+        // versions of newer imosids are kept, so they still know the file is theirs
+        self.imosidversion = self.imosidversion.clone().max(imosid_version());
         let contenthash = self.get_content_hash();
         self.modified = false;
-        if self.hash == contenthash {
-            ChangeState::Unchanged
-        } else {
-            self.hash = contenthash;
+        let mut changed = self.hash != contenthash;
+        self.hash = contenthash;
+        let content = String::from_utf8_lossy(&self.content).to_string();
+        for region in &mut self.regions {
+            region.modified = false;
+            if let Some(hash) = region.get_content_hash(&content) {
+                if hash != region.hash {
+                    region.hash = hash;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
             ChangeState::Changed
+        } else {
+            ChangeState::Unchanged
         }
     }
 }
 
 impl MetaFile {
-    //TODO: Result
-    //TODO: serde DTO
-    pub fn new(path: PathBuf, content: &str) -> Option<MetaFile> {
-        let mcontent = read_to_string(&path).unwrap();
-        let value = mcontent.parse::<Value>().expect("failed to read toml");
-
-        //TODO: fileinfo struct for fields in both dotfile and metafile
-        let mut retfile = MetaFile {
-            currenthash: String::from(""),
-            targetfile: None,
-            sourcefile: None,
-            hash: String::from(""),
-            parentfile: String::from(""),
-            // default version strings
-            imosidversion: Version::new(0, 0, 0),
-            syntaxversion: 1,
-            value: value.clone(),
-            content: String::from(content),
-            modified: false,
-            permissions: Option::None,
-            path,
-        };
-
-        // hash and parent are mandatory
-        retfile.hash = value.get("hash")?.as_str()?.to_string();
-        retfile.parentfile = value.get("parent")?.as_str()?.to_string();
-
-        if let Some(Value::String(targetfile)) = value.get("target") {
-            retfile.targetfile = Some(String::from(targetfile));
-        }
-
-        if let Some(Value::String(sourcefile)) = value.get("source") {
-            retfile.sourcefile = Some(String::from(sourcefile));
-        }
-
-        if let Some(Value::Integer(permissions)) = value.get("permissions") {
-            //TODO check if permissions smaller than 777
-            retfile.permissions = Some(*permissions as u32);
-        }
+    /// read the metafile at path describing a file with content
+    /// the error describes which field is missing or invalid
+    pub fn new(path: PathBuf, content: &[u8]) -> Result<MetaFile, String> {
+        let mcontent = read_to_string(&path).map_err(|e| e.to_string())?;
+        let data: MetaFileData = toml::from_str(&mcontent).map_err(|e| e.to_string())?;
 
-        if let Some(Value::Integer(syntaxversion)) = value.get("syntaxversion") {
-            retfile.syntaxversion = syntaxversion.clone();
+        let mut permissions = match &data.permissions {
+            Some(permissions) => Some(
+                Permissions::parse(&permissions.as_string())
+                    .ok_or_else(|| format!("invalid permissions {}", permissions.as_string()))?,
+            ),
+            None => None,
+        };
+        if let Some(permissions) = &mut permissions {
+            permissions.owner = data.owner;
+            permissions.group = data.group;
         }
 
-        if let Some(Value::String(imosidversion)) = value.get("imosidversion") {
-            if let Ok(version) = Version::parse(imosidversion) {
-                retfile.imosidversion = version;
-            }
+        let syntaxversion = match &data.syntaxversion {
+            Some(syntaxversion) => syntaxversion
+                .as_string()
+                .parse()
+                .map_err(|_| format!("invalid syntaxversion {}", syntaxversion.as_string()))?,
+            None => 1,
+        };
+        if syntaxversion > METAFILE_SYNTAX_VERSION {
+            return Err(format!(
+                "syntax version {} is newer than {}, the newest this imosid supports",
+                syntaxversion, METAFILE_SYNTAX_VERSION
+            ));
         }
+        let imosidversion = match &data.imosidversion {
+            Some(imosidversion) => Version::parse(imosidversion)
+                .map_err(|_| format!("invalid imosidversion {}", imosidversion))?,
+            None => Version::new(0, 0, 0),
+        };
+        check_imosid_version(&path.display().to_string(), &imosidversion);
+        let regions = data
+            .regions
+            .into_iter()
+            .map(Region::from_data)
+            .collect::<Result<Vec<Region>, String>>()?;
 
-        Some(retfile)
+        Ok(MetaFile {
+            currenthash: String::from(""),
+            hash: data.hash,
+            parentfile: data.parent,
+            targetfile: data.target,
+            sourcefile: data.source,
+            modified: false,
+            imosidversion,
+            syntaxversion,
+            extra: data.extra,
+            content: content.to_vec(),
+            path,
+            permissions,
+            profile: data.profile,
+            link: data.link,
+            regions,
+        })
     }
 
     fn get_parent_file(&self) -> PathBuf {
-        let mut path = self.path.clone();
-        path.push(&self.parentfile);
-        path.pop();
-        path
+        self.path.with_file_name(&self.parentfile)
     }
 
     // TODO incorporate this into normal write
-    pub fn write_permissions(&self) {
+    pub fn write_permissions(&self, root: RootPolicy) {
         let parentpath = self.get_parent_file();
         if let Some(permissions) = &self.permissions {
-            let mut perms = fs::metadata(&parentpath).unwrap().permissions();
-            let permint = u32::from_str_radix(&format!("{}", permissions + 1000000), 8).unwrap();
-            perms.set_mode(permint);
-            fs::set_permissions(&parentpath, perms).expect("failed to set permissions");
+            if let Err(e) = set_permissions(&parentpath, permissions, root) {
+                error!(
+                    "could not set permissions {} on {}: {}",
+                    permissions,
+                    parentpath.display().to_string().red(),
+                    e
+                );
+            }
         }
     }
 
@@ -120,11 +217,15 @@ impl MetaFile {
     // TODO maybe return result?
     // TODO split this up, this doesn't need to write to disk
     pub fn from(sourcepath: PathBuf) -> MetaFile {
+        MetaFile::from_with_policy(sourcepath, RootPolicy::Error)
+    }
+
+    // like from, writing a new metafile through sudo if root is Sudo
+    pub fn from_with_policy(sourcepath: PathBuf, root: RootPolicy) -> MetaFile {
         let mut path = sourcepath.clone();
         //
         //TODO handle result
-        let filecontent =
-            read_to_string(&path).expect("could not read file content to create metafile");
+        let filecontent = fs::read(&path).expect("could not read file content to create metafile");
 
         let parentname = path
             .file_name()
@@ -143,7 +244,6 @@ impl MetaFile {
         //Maybe distinguish between new and from path?
         if path.is_file() {
             retfile = MetaFile::new(path.clone(), &filecontent).expect("could not create metafile");
-            retfile.update();
             retfile.finalize();
         } else {
             retfile = MetaFile {
@@ -152,76 +252,189 @@ impl MetaFile {
                 sourcefile: None,
                 hash: String::from(""),
                 parentfile: String::from(&parentname),
-                imosidversion: Version::parse(built_info::PKG_VERSION).unwrap(),
-                syntaxversion: 0,
-                value: Value::Integer(0),
-                content: String::from(&filecontent),
+                imosidversion: imosid_version(),
+                syntaxversion: METAFILE_SYNTAX_VERSION,
+                extra: Map::new(),
+                content: filecontent,
                 modified: false,
                 permissions: Option::None,
+                profile: Option::None,
+                link: false,
                 path,
+                regions: Vec::new(),
             };
 
-            retfile.update();
             retfile.compile();
-            retfile.write_to_file();
+            retfile.write_with_policy(root);
         }
 
         retfile
     }
 
-    fn get_content_hash(&self) -> String {
-        digest(self.content.clone()).to_uppercase()
+    pub fn imosidversion(&self) -> &Version {
+        &self.imosidversion
     }
 
-    // populate toml value with data
-    fn update(&mut self) {
-        let mut selfmap = toml::map::Map::new();
-        selfmap.insert("hash".into(), Value::String((&self.hash).to_string()));
-        selfmap.insert("parent".into(), Value::String((&self.parentfile).into()));
+    pub fn syntaxversion(&self) -> i64 {
+        self.syntaxversion
+    }
 
-        if let Some(targetfile) = &self.targetfile {
-            selfmap.insert(
-                String::from("target"),
-                Value::String(targetfile.to_string()),
-            );
+    /// upgrade the metafile to the current syntax version, fields are always written
+    /// in the current format
+    /// return false if it is already up to date
+    pub fn migrate(&mut self) -> bool {
+        if self.syntaxversion >= METAFILE_SYNTAX_VERSION || self.imosidversion > imosid_version() {
+            return false;
         }
-        if let Some(sourcefile) = &self.sourcefile {
-            selfmap.insert(
-                String::from("source"),
-                Value::String(String::from(sourcefile)),
-            );
+        self.syntaxversion = METAFILE_SYNTAX_VERSION;
+        true
+    }
+
+    fn get_content_hash(&self) -> String {
+        HashAlgorithm::from_hash(&self.hash).digest(&self.content)
+    }
+
+    fn to_data(&self) -> MetaFileData {
+        MetaFileData {
+            hash: self.hash.clone(),
+            parent: self.parentfile.clone(),
+            target: self.targetfile.clone(),
+            source: self.sourcefile.clone(),
+            permissions: self
+                .permissions
+                .as_ref()
+                .map(|permissions| NumberField::String(format!("{:04o}", permissions.mode))),
+            owner: self.permissions.as_ref().and_then(|p| p.owner.clone()),
+            group: self.permissions.as_ref().and_then(|p| p.group.clone()),
+            profile: self.profile.clone(),
+            link: self.link,
+            syntaxversion: Some(NumberField::Integer(if self.regions.is_empty() {
+                self.syntaxversion
+            } else {
+                self.syntaxversion.max(REGION_SYNTAX_VERSION)
+            })),
+            imosidversion: Some(self.imosidversion.to_string()),
+            regions: self.regions.iter().map(|region| region.to_data()).collect(),
+            extra: self.extra.clone(),
         }
+    }
 
-        // TODO: store syntax version somewhere central
-        selfmap.insert(String::from("syntaxversion"), Value::Integer(0));
+    pub fn output(&self) -> String {
+        toml::to_string(&self.to_data()).expect("metafile fields are always valid toml")
+    }
 
-        selfmap.insert(
-            String::from("imosidversion"),
-            Value::String(self.imosidversion.to_string()),
-        );
+    pub fn write_to_file(&mut self) {
+        self.write_with_policy(RootPolicy::Error);
+    }
 
-        selfmap.insert(
-            String::from("syntaxversion"),
-            Value::String(self.syntaxversion.to_string()),
-        );
-        self.value = Value::Table(selfmap);
+    pub fn write_with_policy(&mut self, root: RootPolicy) {
+        let output = self.output();
+        if write_file(&self.path, output.as_bytes(), root).is_err() {
+            error!("{}", "could not write metafile".red());
+        }
     }
 
-    pub fn output(&mut self) -> String {
-        self.update();
-        self.value.to_string()
+    pub fn get_region(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|region| region.name == name)
     }
 
-    pub fn write_to_file(&mut self) {
-        let newfile = File::create(&self.path);
-        match newfile {
-            Err(_) => {
-                eprintln!("{}", "Error: could not write metafile".red());
+    /// current content of the region called name
+    pub fn region_content(&self, name: &str) -> Option<String> {
+        self.get_region(name)?
+            .content(&String::from_utf8_lossy(&self.content))
+    }
+
+    /// replace the content of the region called name with new
+    /// return false if the region cannot be found in the file
+    pub fn replace_region(&mut self, name: &str, new: &str) -> bool {
+        let index = match self.regions.iter().position(|region| region.name == name) {
+            Some(index) => index,
+            None => return false,
+        };
+        // an empty region could not be found again
+        if new.lines().next().is_none() {
+            return false;
+        }
+        let content = String::from_utf8_lossy(&self.content).to_string();
+        let lines: Vec<&str> = content.lines().collect();
+        let last = match self.regions[index].find(&lines) {
+            Some((_, last)) => last + 1,
+            None => return false,
+        };
+        let (output, growth) = match self.regions[index].replace(&content, new) {
+            Some(replaced) => replaced,
+            None => return false,
+        };
+
+        // line numbers below the region move with its content
+        let shift = |line: usize| (line as isize + growth) as usize;
+        for (i, region) in self.regions.iter_mut().enumerate() {
+            if let RegionLocation::Lines(first, end) = &mut region.location {
+                if i == index {
+                    *end = shift(*end);
+                } else if *first > last {
+                    *first = shift(*first);
+                    *end = shift(*end);
+                }
+            }
+        }
+
+        let region = &mut self.regions[index];
+        region.hash = region.get_content_hash(&output).unwrap_or_default();
+        region.modified = false;
+        self.content = output.into_bytes();
+        self.hash = self.get_content_hash();
+        self.currenthash = self.hash.clone();
+        true
+    }
+
+    /// apply the regions of other to the regions with the same name
+    /// regions modified on either side are skipped
+    /// return the number of regions which changed
+    pub fn apply_regions(&mut self, other: &MetaFile, options: &ApplyOptions) -> usize {
+        let mut applied = 0;
+        for region in &other.regions {
+            if !options.selects_name(&region.name) {
+                continue;
+            }
+            let target = match self.get_region(&region.name) {
+                Some(target) => target,
+                None => continue,
+            };
+            if region.modified {
+                warn!(
+                    "{}",
+                    format!("region {} modified in source, skipping", region.name).yellow()
+                );
+                continue;
             }
-            Ok(mut file) => {
-                file.write_all(self.output().as_bytes())
-                    .expect("could not write metafile");
+            if target.modified {
+                warn!(
+                    "{}",
+                    format!("region {} modified in target, skipping", region.name).yellow()
+                );
+                continue;
             }
+            if target.hash == region.hash {
+                continue;
+            }
+            match other.region_content(&region.name) {
+                Some(content) if self.replace_region(&region.name, &content) => applied += 1,
+                _ => error!("could not apply region {}", region.name.red()),
+            }
+        }
+        applied
+    }
+
+    /// value of a metadata field, None if it is not set
+    pub fn get_field(&self, field: &str) -> Option<String> {
+        match field {
+            "target" => self.targetfile.clone(),
+            "source" => self.sourcefile.clone(),
+            "hash" => Some(self.hash.clone()),
+            "parent" => Some(self.parentfile.clone()),
+            "profile" => self.profile.clone(),
+            _ => None,
         }
     }
 
@@ -233,6 +446,28 @@ impl MetaFile {
         } else {
             ret.push_str(&"unmodified".green().bold());
         }
+        for region in &self.regions {
+            ret.push_str(&format!(
+                "\nregion {}: {}",
+                region.name.bold(),
+                if region.modified {
+                    "modified".red()
+                } else {
+                    "unmodified".green()
+                }
+            ));
+        }
         ret
     }
 }
+
+/// the target of the file at sourcepath if it is deployed as a link,
+/// read from its metafile without hashing the file itself
+pub fn link_target(sourcepath: &Path) -> Option<String> {
+    let metapath = format!("{}.imosid.toml", sourcepath.display());
+    let data: MetaFileData = toml::from_str(&read_to_string(metapath).ok()?).ok()?;
+    if !data.link {
+        return None;
+    }
+    data.target
+}
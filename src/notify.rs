@@ -0,0 +1,102 @@
+use std::path::Path;
+use std::process::Command;
+
+use colored::Colorize;
+use toml::Value;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::files::{collapse_tilde, expand_tilde};
+
+// command reloading an application once one of its files changed
+pub struct Reload {
+    pub name: String,
+    pub paths: Vec<String>, // files or directories the application reads
+    pub command: String,
+}
+
+impl Reload {
+    // [reload.<name>] table with a command and the paths it applies to
+    pub fn from_value(name: &str, value: &Value) -> Option<Reload> {
+        let command = value.get("command")?.as_str()?;
+        let paths = match value.get("paths")? {
+            Value::String(path) => vec![expand_tilde(path)],
+            Value::Array(paths) => paths
+                .iter()
+                .filter_map(|path| path.as_str())
+                .map(expand_tilde)
+                .collect(),
+            _ => return None,
+        };
+        Some(Reload {
+            name: name.to_string(),
+            paths,
+            command: command.to_string(),
+        })
+    }
+
+    pub fn matches(&self, target: &str) -> bool {
+        self.paths
+            .iter()
+            .any(|path| Path::new(target).starts_with(path))
+    }
+}
+
+/// notify the user and reload applications after targets changed
+/// changed only holds targets of files which were applied successfully
+pub fn after_apply(config: &Config, changed: &[String], notify: bool) {
+    if changed.is_empty() {
+        return;
+    }
+    if notify {
+        send_notification(changed);
+    }
+    for reload in &config.reload {
+        if changed.iter().any(|target| reload.matches(target)) {
+            run_reload(reload);
+        }
+    }
+}
+
+fn send_notification(changed: &[String]) {
+    let title = if changed.len() == 1 {
+        String::from("imosid applied 1 file")
+    } else {
+        format!("imosid applied {} files", changed.len())
+    };
+    let body = changed
+        .iter()
+        .map(|target| collapse_tilde(target))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let status = if cfg!(target_os = "macos") {
+        Command::new("osascript")
+            .arg("-e")
+            .arg(format!(
+                "display notification {:?} with title {:?}",
+                body, title
+            ))
+            .status()
+    } else {
+        Command::new("notify-send")
+            .arg("--app-name=imosid")
+            .arg(&title)
+            .arg(&body)
+            .status()
+    };
+    // a missing notification daemon is no reason to fail apply
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("could not send notification: {}", status),
+        Err(e) => warn!("could not send notification: {}", e),
+    }
+}
+
+fn run_reload(reload: &Reload) {
+    info!("{} {}", "reloading".bold(), reload.name);
+    match Command::new("sh").arg("-c").arg(&reload.command).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("reloading {} failed with {}", reload.name.red(), status),
+        Err(_) => error!("could not run {}", reload.command.red()),
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::prelude::PermissionsExt;
+use std::path::Path;
+
+// file mode with optional owner and group
+// written as mode[:owner[:group]], for example 0644 or 0600:root:wheel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub mode: u32,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl Permissions {
+    pub fn parse(input: &str) -> Option<Permissions> {
+        let mut parts = input.split(':');
+        let mode = parse_mode(parts.next()?)?;
+        let owner = parts
+            .next()
+            .filter(|owner| !owner.is_empty())
+            .map(String::from);
+        let group = parts
+            .next()
+            .filter(|group| !group.is_empty())
+            .map(String::from);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Permissions { mode, owner, group })
+    }
+
+    // set mode and, if possible, owner and group of a file
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(self.mode);
+        fs::set_permissions(path, perms)?;
+
+        if self.owner.is_none() && self.group.is_none() {
+            return Ok(());
+        }
+        let uid = match &self.owner {
+            Some(owner) => Some(lookup_id("/etc/passwd", owner).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown user {}", owner))
+            })?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(lookup_id("/etc/group", group).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown group {}", group))
+            })?),
+            None => None,
+        };
+        // only works when running as root
+        std::os::unix::fs::chown(path, uid, gid)
+    }
+
+    // without unix modes a file without any write bit becomes readonly
+    // owner and group cannot be set
+    #[cfg(not(unix))]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_readonly(self.mode & 0o222 == 0);
+        fs::set_permissions(path, perms)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04o}", self.mode)?;
+        match (&self.owner, &self.group) {
+            (Some(owner), Some(group)) => write!(f, ":{}:{}", owner, group),
+            (Some(owner), None) => write!(f, ":{}", owner),
+            (None, Some(group)) => write!(f, "::{}", group),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// true if path exists and nobody has write permission
+pub fn is_readonly(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly())
+}
+
+// parse octal mode like 644, 0644 or 0o644
+fn parse_mode(input: &str) -> Option<u32> {
+    let digits = input.strip_prefix("0o").unwrap_or(input);
+    if digits.is_empty() || digits.len() > 5 || !digits.chars().all(|c| ('0'..='7').contains(&c)) {
+        return None;
+    }
+    let mode = u32::from_str_radix(digits, 8).ok()?;
+    if mode > 0o7777 {
+        return None;
+    }
+    Some(mode)
+}
+
+// resolve user or group name to its id using a passwd style file
+// numeric ids are used directly
+#[cfg(unix)]
+fn lookup_id(database: &str, name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Some(id);
+    }
+    let content = fs::read_to_string(database).ok()?;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() > 2 && fields[0] == name {
+            return fields[2].parse::<u32>().ok();
+        }
+    }
+    None
+}
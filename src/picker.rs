@@ -0,0 +1,192 @@
+use std::io::{self, IsTerminal, Stderr};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Terminal;
+
+/// score how well pattern matches candidate, None if it does not match
+/// every character of pattern has to appear in candidate in order,
+/// consecutive characters and characters starting a word score higher
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut last: Option<usize> = None;
+    for c in pattern.chars().filter(|c| !c.is_whitespace()) {
+        let c = c.to_ascii_lowercase();
+        let found = candidate[position..]
+            .iter()
+            .position(|other| other.to_ascii_lowercase() == c)?
+            + position;
+        score += 1;
+        if last.is_some_and(|last| last + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        // skipped characters make a match worse
+        score -= (found - last.map_or(0, |last| last + 1)) as i64;
+        last = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+// candidates matching pattern, best matches first
+fn filter<'a>(candidates: &'a [String], pattern: &str) -> Vec<&'a String> {
+    let mut matches: Vec<(i64, &String)> = candidates
+        .iter()
+        .filter_map(|candidate| Some((fuzzy_score(pattern, candidate)?, candidate)))
+        .collect();
+    // stable sort keeps the file order for equal scores
+    matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+struct Picker<'a> {
+    prompt: &'a str,
+    candidates: &'a [String],
+    pattern: String,
+    marked: Vec<String>,
+    state: ListState,
+}
+
+impl<'a> Picker<'a> {
+    fn run(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+    ) -> io::Result<Option<Vec<String>>> {
+        loop {
+            let matches = filter(self.candidates, &self.pattern);
+            if matches.is_empty() {
+                self.state.select(None);
+            } else {
+                let selected = self.state.selected().unwrap_or(0);
+                self.state.select(Some(selected.min(matches.len() - 1)));
+            }
+            terminal.draw(|frame| {
+                let areas = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
+                    .split(frame.size());
+                let input = Paragraph::new(format!("> {}", self.pattern))
+                    .block(Block::default().borders(Borders::ALL).title(self.prompt));
+                frame.render_widget(input, areas[0]);
+                let items: Vec<ListItem> = matches
+                    .iter()
+                    .map(|candidate| {
+                        if self.marked.contains(candidate) {
+                            ListItem::new(Line::from(vec![
+                                Span::styled("* ", Style::default().fg(Color::Green)),
+                                Span::raw(candidate.as_str()),
+                            ]))
+                        } else {
+                            ListItem::new(format!("  {}", candidate))
+                        }
+                    })
+                    .collect();
+                let list = List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(format!(
+                        "{}/{}  tab marks, enter accepts",
+                        matches.len(),
+                        self.candidates.len()
+                    )))
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                frame.render_stateful_widget(list, areas[1], &mut self.state);
+            })?;
+
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            let selected = self.state.selected();
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if control => return Ok(None),
+                KeyCode::Enter => {
+                    if !self.marked.is_empty() {
+                        return Ok(Some(self.marked.clone()));
+                    }
+                    return Ok(selected.map(|index| vec![matches[index].clone()]));
+                }
+                KeyCode::Tab => {
+                    if let Some(index) = selected {
+                        let candidate = matches[index].clone();
+                        match self.marked.iter().position(|marked| *marked == candidate) {
+                            Some(position) => {
+                                self.marked.remove(position);
+                            }
+                            None => self.marked.push(candidate),
+                        }
+                        self.state.select(Some(index + 1));
+                    }
+                }
+                KeyCode::Up => self
+                    .state
+                    .select(selected.map(|index| index.saturating_sub(1))),
+                KeyCode::Char('p') if control => self
+                    .state
+                    .select(selected.map(|index| index.saturating_sub(1))),
+                KeyCode::Down => self.state.select(selected.map(|index| index + 1)),
+                KeyCode::Char('n') if control => self.state.select(selected.map(|index| index + 1)),
+                KeyCode::Backspace => {
+                    self.pattern.pop();
+                    self.state.select(Some(0));
+                }
+                KeyCode::Char(c) if !control => {
+                    self.pattern.push(c);
+                    self.state.select(Some(0));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// let the user fuzzy search through candidates and pick one or more of them
+/// the picker is drawn on stderr so stdout stays free for command output
+/// returns None if the user cancelled or there is no terminal to ask on
+pub fn pick(prompt: &str, candidates: &[String]) -> Option<Vec<String>> {
+    if candidates.is_empty() || !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return None;
+    }
+    let mut picker = Picker {
+        prompt,
+        candidates,
+        pattern: String::new(),
+        marked: Vec::new(),
+        state: ListState::default(),
+    };
+    let result = (|| -> io::Result<Option<Vec<String>>> {
+        enable_raw_mode()?;
+        let mut stderr = io::stderr();
+        execute!(stderr, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stderr))?;
+        let picked = picker.run(&mut terminal);
+        disable_raw_mode()?;
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        terminal.show_cursor()?;
+        picked
+    })();
+    match result {
+        Ok(picked) => picked,
+        Err(e) => {
+            let _ = disable_raw_mode();
+            eprintln!("could not show section picker: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::files::write_atomic;
+use crate::permissions::Permissions;
+
+// what apply does with targets only root can write
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RootPolicy {
+    Error, // fail the file
+    Skip,  // leave the target alone and report it
+    Sudo,  // write the target through sudo
+}
+
+// the owner of /proc/self is the effective user of this process
+#[cfg(unix)]
+fn current_uid() -> Option<u32> {
+    fs::metadata("/proc/self")
+        .ok()
+        .map(|metadata| metadata.uid())
+}
+
+/// true if only root can write path: imosid is not running as root
+/// and the closest existing directory of path belongs to root and is not writable by others
+#[cfg(unix)]
+pub fn needs_root(path: &Path) -> bool {
+    if current_uid() == Some(0) {
+        return false;
+    }
+    // files are replaced by renaming, so the directory is what has to be writable
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path
+        .parent()
+        .and_then(|parent| parent.ancestors().find(|dir| dir.is_dir()))
+    {
+        Some(dir) => dir,
+        None => return false,
+    };
+    match fs::metadata(dir) {
+        Ok(metadata) => metadata.uid() == 0 && metadata.mode() & 0o002 == 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn needs_root(_path: &Path) -> bool {
+    false
+}
+
+// run sudo with args, content is passed on stdin
+fn sudo(args: &[&str], content: Option<&[u8]>) -> io::Result<()> {
+    let mut child = Command::new("sudo")
+        .arg("--")
+        .args(args)
+        .stdin(if content.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let (Some(content), Some(mut stdin)) = (content, child.stdin.take()) {
+        stdin.write_all(content)?;
+    }
+    if child.wait()?.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("sudo {} failed", args.join(" ")),
+        ))
+    }
+}
+
+fn sudo_path(path: &Path) -> io::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"))
+}
+
+/// write content to path like write_atomic, through sudo if root is Sudo
+/// and only root can write it, tee keeps mode and owner of existing files
+pub fn write_file(path: &Path, content: &[u8], root: RootPolicy) -> io::Result<()> {
+    if root != RootPolicy::Sudo || !needs_root(path) {
+        return write_atomic(path, content);
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.exists()) {
+        sudo(&["mkdir", "-p", sudo_path(parent)?], None)?;
+    }
+    sudo(&["tee", sudo_path(path)?], Some(content))
+}
+
+/// create the directories leading to path, through sudo if root is Sudo
+/// and only root can write them
+pub fn create_parent_dirs(path: &Path, root: RootPolicy) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.exists() => parent,
+        _ => return Ok(()),
+    };
+    if root == RootPolicy::Sudo && needs_root(path) {
+        return sudo(&["mkdir", "-p", sudo_path(parent)?], None);
+    }
+    fs::create_dir_all(parent)
+}
+
+/// set permissions on path, through sudo if root is Sudo and only root can write it
+pub fn set_permissions(path: &Path, permissions: &Permissions, root: RootPolicy) -> io::Result<()> {
+    if root != RootPolicy::Sudo || !needs_root(path) {
+        return permissions.apply(path);
+    }
+    let target = sudo_path(path)?;
+    sudo(&["chmod", &format!("{:o}", permissions.mode), target], None)?;
+    let owner = match (&permissions.owner, &permissions.group) {
+        (None, None) => return Ok(()),
+        (Some(owner), Some(group)) => format!("{}:{}", owner, group),
+        (Some(owner), None) => owner.clone(),
+        (None, Some(group)) => format!(":{}", group),
+    };
+    sudo(&["chown", &owner, target], None)
+}
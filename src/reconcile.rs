@@ -0,0 +1,318 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::Command;
+
+use colored::Colorize;
+use tempdir::TempDir;
+
+use crate::files::{expand_tilde, DotFile};
+use crate::hashable::Hashable;
+use crate::section::{Section, SectionData};
+use crate::snapshot::{diff_snapshot, format_patch, load_snapshot, store_snapshot};
+use crate::state::StateDb;
+
+// what the user chose to do with a modified section
+enum Resolution {
+    KeepLocal,
+    TakeSource,
+    Edit,
+    Skip,
+}
+
+// the source last applied to target, or the source named by one of its sections
+fn find_source(target: &Path, targetfile: &DotFile) -> Option<DotFile> {
+    let canonical = target.canonicalize().ok()?;
+    let state = StateDb::load();
+    let recorded = state
+        .latest()
+        .into_iter()
+        .find(|(path, _)| {
+            Path::new(path)
+                .canonicalize()
+                .is_ok_and(|path| path == canonical)
+        })
+        .map(|(_, record)| record.source.clone());
+    let source = recorded.or_else(|| {
+        targetfile
+            .sections
+            .iter()
+            .find_map(|section| match section {
+                Section::Named(_, named_data) => named_data.source.clone(),
+                Section::Anonymous(_) => None,
+            })
+    })?;
+    match DotFile::new(&expand_tilde(&source)) {
+        Ok(sourcefile) => Some(sourcefile),
+        Err(_) => {
+            eprintln!("could not open source file {}", source.red());
+            None
+        }
+    }
+}
+
+fn print_diff(source: &str, local: &str) {
+    print!("{}", format_patch(source, local));
+}
+
+fn prompt_resolution() -> Resolution {
+    let stdin = io::stdin();
+    loop {
+        print!("[k]eep local, [t]ake source, [e]dit, [s]kip: ");
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        // closed stdin leaves the section alone
+        if stdin
+            .lock()
+            .read_line(&mut answer)
+            .map_or(true, |read| read == 0)
+        {
+            return Resolution::Skip;
+        }
+        match answer.trim() {
+            "k" | "keep" => return Resolution::KeepLocal,
+            "t" | "take" => return Resolution::TakeSource,
+            "e" | "edit" => return Resolution::Edit,
+            "s" | "skip" | "" => return Resolution::Skip,
+            _ => continue,
+        }
+    }
+}
+
+// let the user edit content in their editor, None if the editor fails
+pub fn edit_content(name: &str, content: &str) -> Option<String> {
+    let dir = TempDir::new("imosidmerge").ok()?;
+    let path = dir.path().join(name);
+    fs::write(&path, content).ok()?;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // editors are allowed to come with arguments
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status()
+        .ok()?;
+    if !status.success() {
+        eprintln!("editor {} exited with {}", editor.red(), status);
+        return None;
+    }
+    let mut edited = fs::read_to_string(&path).ok()?;
+    if !edited.is_empty() && !edited.ends_with('\n') {
+        edited.push('\n');
+    }
+    Some(edited)
+}
+
+/// go through the locally modified sections of target and let the user
+/// keep them, replace them with the source or edit them
+/// return true if target was written
+pub fn reconcile_target(target: &Path) -> bool {
+    let mut targetfile = match DotFile::from_pathbuf(&target.to_path_buf()) {
+        Ok(file) => file,
+        Err(_) => {
+            eprintln!("could not open file {}", target.to_str().unwrap().red());
+            return false;
+        }
+    };
+    if targetfile.metafile.is_some() {
+        eprintln!("cannot merge file managed by metafile");
+        return false;
+    }
+    let source = match find_source(target, &targetfile) {
+        Some(source) => source,
+        None => {
+            eprintln!("no source found for {}", target.to_str().unwrap().red());
+            return false;
+        }
+    };
+
+    let mut modifiedcount = 0;
+    let mut resolvedcount = 0;
+    for index in 0..targetfile.sections.len() {
+        let (local, local_named) = match &targetfile.sections[index] {
+            Section::Named(data, named_data) if named_data.hash != named_data.targethash => {
+                (data.clone(), named_data.clone())
+            }
+            _ => continue,
+        };
+        modifiedcount += 1;
+        let name = local_named.name.clone();
+        // the target holds rendered sections, compare against what apply would write
+        let (theirs, theirs_named) = match source.get_section(&name).and_then(|s| s.render()) {
+            Some(Section::Named(data, named_data)) => (data, named_data),
+            _ => {
+                println!(
+                    "{}",
+                    format!("section {} has no source section, skipping", &name).yellow()
+                );
+                continue;
+            }
+        };
+
+        println!("{} {}", "section".bold(), name.bold());
+        // what was changed locally since the section was last applied
+        if let Some(diff) = diff_snapshot(&local_named.targethash, &local.content) {
+            println!("{}", "local changes:".dimmed());
+            print!("{}", diff);
+            println!("{}", "difference to the source:".dimmed());
+        }
+        print_diff(&theirs.content, &local.content);
+        let content = match prompt_resolution() {
+            Resolution::KeepLocal => local.content.clone(),
+            Resolution::TakeSource => theirs.content.clone(),
+            Resolution::Edit => {
+                // start from a three way merge if the applied version is known
+                let start = match load_snapshot(&local_named.targethash) {
+                    Some(base) => match diffy::merge(&base, &local.content, &theirs.content) {
+                        Ok(merged) => merged,
+                        Err(conflicted) => conflicted,
+                    },
+                    None => local.content.clone(),
+                };
+                match edit_content(&name, &start) {
+                    Some(edited) => edited,
+                    None => continue,
+                }
+            }
+            Resolution::Skip => continue,
+        };
+
+        // the section is now based on the current source, it stays
+        // modified only if its content differs from the source
+        store_snapshot(&theirs_named.hash, &theirs.content);
+        let mut resolved = Section::Named(SectionData { content, ..local }, theirs_named);
+        resolved.finalize();
+        targetfile.sections[index] = resolved;
+        resolvedcount += 1;
+    }
+
+    if modifiedcount == 0 {
+        println!(
+            "{} has no modified sections",
+            target.to_str().unwrap().bold()
+        );
+        return false;
+    }
+    if resolvedcount == 0 {
+        return false;
+    }
+    targetfile.write_to_file();
+    println!(
+        "merged {} sections into {}",
+        resolvedcount,
+        target.to_str().unwrap().bold()
+    );
+    true
+}
+
+/// copy the locally modified sections of target back into their source
+/// and compile them there, so edits made in place become the source
+/// return the path of the source if it was written
+pub fn sync_target(target: &Path, dryrun: bool) -> Option<String> {
+    let mut targetfile = match DotFile::from_pathbuf(&target.to_path_buf()) {
+        Ok(file) => file,
+        Err(_) => {
+            eprintln!("could not open file {}", target.to_str().unwrap().red());
+            return None;
+        }
+    };
+    if targetfile.metafile.is_some() {
+        eprintln!("cannot sync file managed by metafile");
+        return None;
+    }
+    let mut source = match find_source(target, &targetfile) {
+        Some(source) => source,
+        None => {
+            eprintln!("no source found for {}", target.to_str().unwrap().red());
+            return None;
+        }
+    };
+
+    let mut synced = Vec::new();
+    for section in &mut targetfile.sections {
+        let (local, local_named) = match section {
+            Section::Named(data, named_data) if named_data.hash != named_data.targethash => {
+                (data.content.clone(), named_data.name.clone())
+            }
+            _ => continue,
+        };
+        let theirs = match source.get_section(&local_named) {
+            Some(Section::Named(_, named_data)) => named_data,
+            _ => {
+                println!(
+                    "{}",
+                    format!("section {} has no source section, skipping", local_named).yellow()
+                );
+                continue;
+            }
+        };
+        // rendered content cannot be turned back into its source
+        if theirs.expand || theirs.template || theirs.encrypted.is_some() {
+            println!(
+                "{}",
+                format!(
+                    "section {} is generated from its source, skipping",
+                    local_named
+                )
+                .yellow()
+            );
+            continue;
+        }
+        if theirs.hash != theirs.targethash {
+            println!(
+                "{}",
+                format!(
+                    "section {} is modified in the source too, skipping",
+                    local_named
+                )
+                .yellow()
+            );
+            continue;
+        }
+        println!("{} {}", "syncing section".bold(), local_named.bold());
+        if dryrun {
+            let sourcecontent = match source.get_section(&local_named) {
+                Some(Section::Named(data, _)) => data.content,
+                _ => String::new(),
+            };
+            print_diff(&sourcecontent, &local);
+            continue;
+        }
+        source.set_section_content(&local_named, &local);
+        section.compile();
+        synced.push(local_named);
+    }
+
+    if synced.is_empty() {
+        if !dryrun {
+            println!(
+                "{} has no sections to sync",
+                target.to_str().unwrap().bold()
+            );
+        }
+        return None;
+    }
+    for section in &mut source.sections {
+        if !matches!(section, Section::Named(_, named_data) if synced.contains(&named_data.name)) {
+            continue;
+        }
+        section.compile();
+        if let Section::Named(data, named_data) = section {
+            store_snapshot(&named_data.hash, &data.content);
+        }
+    }
+    source.write_to_file();
+    // the target now holds the source, it is not modified anymore
+    targetfile.write_to_file();
+    println!(
+        "synced {} sections from {} to {}",
+        synced.len(),
+        target.to_str().unwrap().bold(),
+        source.filename.bold()
+    );
+    Some(source.filename)
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hashable::HashAlgorithm;
+
+// where a region of a comment-less file is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegionLocation {
+    Lines(usize, usize), // first and last line, counting from 1
+    // from the first line containing start up to the next line containing end
+    Anchors(String, String),
+}
+
+// a named part of a file managed by a metafile with syntax version 2
+// regions are what sections are for files with comments
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub name: String,
+    pub location: RegionLocation,
+    pub hash: String,
+    pub source: Option<String>,
+    pub modified: bool,
+}
+
+// a [[region]] table of a metafile
+#[derive(Serialize, Deserialize)]
+pub struct RegionData {
+    name: String,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lines: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+// lines are written as first-last
+fn parse_lines(lines: &str) -> Option<(usize, usize)> {
+    let (first, last) = lines.split_once('-')?;
+    let first: usize = first.trim().parse().ok()?;
+    let last: usize = last.trim().parse().ok()?;
+    if first == 0 || last < first {
+        return None;
+    }
+    Some((first, last))
+}
+
+impl Region {
+    pub fn from_data(data: RegionData) -> Result<Region, String> {
+        let location = match (&data.lines, data.start, data.end) {
+            (Some(lines), None, None) => {
+                let (first, last) = parse_lines(lines)
+                    .ok_or_else(|| format!("invalid lines {} in region {}", lines, data.name))?;
+                RegionLocation::Lines(first, last)
+            }
+            (None, Some(start), Some(end)) => RegionLocation::Anchors(start, end),
+            _ => {
+                return Err(format!(
+                    "region {} needs either lines or start and end",
+                    data.name
+                ))
+            }
+        };
+        Ok(Region {
+            name: data.name,
+            location,
+            hash: data.hash,
+            source: data.source,
+            modified: false,
+        })
+    }
+
+    pub fn to_data(&self) -> RegionData {
+        let (lines, start, end) = match &self.location {
+            RegionLocation::Lines(first, last) => (Some(format!("{}-{}", first, last)), None, None),
+            RegionLocation::Anchors(start, end) => (None, Some(start.clone()), Some(end.clone())),
+        };
+        RegionData {
+            name: self.name.clone(),
+            hash: self.hash.clone(),
+            lines,
+            start,
+            end,
+            source: self.source.clone(),
+        }
+    }
+
+    /// indices of the first and last line of the region in lines
+    pub fn find(&self, lines: &[&str]) -> Option<(usize, usize)> {
+        match &self.location {
+            RegionLocation::Lines(first, last) => {
+                if *last > lines.len() {
+                    return None;
+                }
+                Some((first - 1, last - 1))
+            }
+            RegionLocation::Anchors(start, end) => {
+                let first = lines
+                    .iter()
+                    .position(|line| line.contains(start.as_str()))?;
+                let last = lines[first + 1..]
+                    .iter()
+                    .position(|line| line.contains(end.as_str()))?;
+                Some((first, first + 1 + last))
+            }
+        }
+    }
+
+    /// content of the region in content, None if it cannot be found
+    pub fn content(&self, content: &str) -> Option<String> {
+        let lines: Vec<&str> = content.lines().collect();
+        let (first, last) = self.find(&lines)?;
+        let mut region = lines[first..=last].join("\n");
+        region.push('\n');
+        Some(region)
+    }
+
+    pub fn get_content_hash(&self, content: &str) -> Option<String> {
+        let region = self.content(content)?;
+        Some(HashAlgorithm::from_hash(&self.hash).digest(region.as_bytes()))
+    }
+
+    /// replace the region in content with new, None if it cannot be found
+    /// returns the new content of the whole file and by how many lines it grew
+    pub fn replace(&self, content: &str, new: &str) -> Option<(String, isize)> {
+        let lines: Vec<&str> = content.lines().collect();
+        let (first, last) = self.find(&lines)?;
+        let newlines: Vec<&str> = new.lines().collect();
+        let mut result: Vec<&str> = Vec::new();
+        result.extend_from_slice(&lines[..first]);
+        result.extend_from_slice(&newlines);
+        result.extend_from_slice(&lines[last + 1..]);
+        let mut output = result.join("\n");
+        if content.ends_with('\n') || content.is_empty() {
+            output.push('\n');
+        }
+        let growth = newlines.len() as isize - (last + 1 - first) as isize;
+        Some((output, growth))
+    }
+}
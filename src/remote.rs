@@ -0,0 +1,271 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use colored::Colorize;
+
+use crate::config::cache_dir;
+use crate::files::{expand_tilde, DotFile};
+use crate::git;
+use crate::section::Section;
+
+// what a source has to match before it is applied
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Pin {
+    Sha256(String), // hash of the source file content
+    GitRef(String), // tag or commit of a git source
+}
+
+// where sections are updated from
+// files in git repositories are written as repo.git//path/in/repo
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SourceLocation {
+    Local(String),
+    Url(String),
+    Git { repo: String, path: String },
+}
+
+// a source comment argument, optionally pinned with @pin
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceSpec {
+    pub location: SourceLocation,
+    pub pin: Option<Pin>,
+}
+
+// pins are plain words, this keeps user@host in urls from being read as one
+fn is_pin(input: &str) -> bool {
+    !input.is_empty()
+        && input
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+fn is_sha256(input: &str) -> bool {
+    input.len() == 64 && input.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl SourceSpec {
+    pub fn is_remote(&self) -> bool {
+        !matches!(self.location, SourceLocation::Local(_))
+    }
+
+    pub fn parse(input: &str) -> Option<SourceSpec> {
+        let (location, pin) = match input.rsplit_once('@') {
+            Some((location, pin)) if !location.is_empty() && is_pin(pin) => (location, Some(pin)),
+            _ => (input, None),
+        };
+        let location = if let Some(index) = location.find(".git//") {
+            SourceLocation::Git {
+                repo: String::from(&location[..index + 4]),
+                path: String::from(&location[index + 6..]),
+            }
+        } else if location.starts_with("https://") || location.starts_with("http://") {
+            SourceLocation::Url(String::from(location))
+        } else {
+            SourceLocation::Local(String::from(location))
+        };
+        let pin = match (&location, pin) {
+            (_, None) => None,
+            (SourceLocation::Git { .. }, Some(pin)) => Some(Pin::GitRef(String::from(pin))),
+            (_, Some(pin)) if is_sha256(pin) => Some(Pin::Sha256(pin.to_lowercase())),
+            // only git sources have versions
+            _ => return None,
+        };
+        Some(SourceSpec { location, pin })
+    }
+}
+
+// remote sources are kept in the cache so they can be compared and reused
+pub fn sources_dir() -> PathBuf {
+    cache_dir().join("sources")
+}
+
+fn cache_name(location: &str) -> String {
+    location
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn download(url: &str, offline: bool) -> Option<PathBuf> {
+    let path = sources_dir().join(cache_name(url));
+    if offline {
+        return cached(path, url);
+    }
+    fs::create_dir_all(sources_dir()).ok()?;
+    let tmppath = path.with_extension("part");
+    // servers like imosid serve answer 304 if the cached copy is still current
+    let etagpath = path.with_extension("etag");
+    let mut command = Command::new("curl");
+    command.arg("-fsSL").arg("-w").arg("%{http_code}");
+    if path.exists() && etagpath.exists() {
+        command.arg("--etag-compare").arg(&etagpath);
+    }
+    let output = command
+        .arg("--etag-save")
+        .arg(&etagpath)
+        .arg("-o")
+        .arg(&tmppath)
+        .arg(url)
+        .output();
+    match output {
+        Ok(output) if output.status.success() && output.stdout == b"304" => {
+            let _ = fs::remove_file(&tmppath);
+            return Some(path);
+        }
+        Ok(output) if output.status.success() => {}
+        _ => {
+            eprintln!("could not download {}", url.red());
+            let _ = fs::remove_file(&tmppath);
+            return None;
+        }
+    }
+    fs::rename(&tmppath, &path).ok()?;
+    Some(path)
+}
+
+fn checkout_repo(repo: &str, pin: Option<&str>, offline: bool) -> Option<PathBuf> {
+    let dir = sources_dir().join(cache_name(repo));
+    if offline {
+        cached(dir.join(".git"), repo)?;
+    } else {
+        fs::create_dir_all(sources_dir()).ok()?;
+        if !git::clone_or_fetch(repo, &dir) {
+            eprintln!("could not fetch {}", repo.red());
+            return None;
+        }
+    }
+    let revision = pin.unwrap_or("origin/HEAD");
+    if !git::checkout(&dir, revision) {
+        eprintln!(
+            "{} {} has no version {}",
+            "pin mismatch:".red().bold(),
+            repo.red(),
+            revision.bold()
+        );
+        return None;
+    }
+    Some(dir)
+}
+
+// path of an already fetched source
+fn cached(path: PathBuf, location: &str) -> Option<PathBuf> {
+    if path.exists() {
+        return Some(path);
+    }
+    eprintln!(
+        "{} {}",
+        location.red(),
+        "is not cached, run imosid fetch first".red()
+    );
+    None
+}
+
+/// local path of the source file, remote sources are fetched into the cache
+/// offline only uses sources fetched before
+/// None if the source could not be fetched or does not match its pin
+pub fn resolve_source(spec: &SourceSpec, offline: bool) -> Option<PathBuf> {
+    let gitpin = match &spec.pin {
+        Some(Pin::GitRef(gitref)) => Some(gitref.as_str()),
+        _ => None,
+    };
+    let (path, name) = match &spec.location {
+        SourceLocation::Local(path) => (PathBuf::from(expand_tilde(path)), path.clone()),
+        SourceLocation::Url(url) => (download(url, offline)?, url.clone()),
+        SourceLocation::Git { repo, path } => {
+            let dir = checkout_repo(repo, gitpin, offline)?;
+            // a pinned commit has to be the one checked out
+            if let Some(gitref) = gitpin {
+                let head = git::head_commit(&dir).unwrap_or_default();
+                if gitref.len() >= 7
+                    && gitref.chars().all(|c| c.is_ascii_hexdigit())
+                    && !head.starts_with(&gitref.to_lowercase())
+                {
+                    eprintln!(
+                        "{} {} is at {} instead of {}",
+                        "pin mismatch:".red().bold(),
+                        repo.red(),
+                        head,
+                        gitref
+                    );
+                    return None;
+                }
+            }
+            (dir.join(path), format!("{}//{}", repo, path))
+        }
+    };
+    if !path.is_file() {
+        eprintln!("source {} does not exist", name.red());
+        return None;
+    }
+    if let Some(Pin::Sha256(expected)) = &spec.pin {
+        let actual = sha256::digest(fs::read(&path).ok()?.as_slice());
+        if !actual.eq_ignore_ascii_case(expected) {
+            eprintln!(
+                "{} {} has hash {} instead of {}",
+                "pin mismatch:".red().bold(),
+                name.red(),
+                actual,
+                expected
+            );
+            return None;
+        }
+    }
+    Some(path)
+}
+
+/// commit checked out for a git source, None for other sources
+pub fn source_commit(spec: &SourceSpec) -> Option<String> {
+    match &spec.location {
+        SourceLocation::Git { repo, .. } => git::head_commit(&sources_dir().join(cache_name(repo))),
+        _ => None,
+    }
+}
+
+/// download every remote source used by dotfiles into the cache
+/// and report what was fetched, return false if any source failed
+pub fn fetch_sources(dotfiles: &[DotFile]) -> bool {
+    let mut sources: Vec<&String> = Vec::new();
+    for dotfile in dotfiles {
+        for section in &dotfile.sections {
+            if let Section::Named(_, named_data) = section {
+                if let Some(source) = &named_data.source {
+                    if !sources.contains(&source) {
+                        sources.push(source);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut success = true;
+    for source in sources {
+        let spec = match SourceSpec::parse(source) {
+            Some(spec) if spec.is_remote() => spec,
+            _ => continue,
+        };
+        let path = match resolve_source(&spec, false) {
+            Some(path) => path,
+            None => {
+                success = false;
+                continue;
+            }
+        };
+        let hash = fs::read(&path)
+            .map(|content| sha256::digest(content.as_slice()))
+            .unwrap_or_default();
+        match &spec.location {
+            SourceLocation::Git { repo, .. } => {
+                let dir = sources_dir().join(cache_name(repo));
+                println!(
+                    "{} commit {} sha256 {}",
+                    source.green(),
+                    git::head_commit(&dir).unwrap_or_default().bold(),
+                    hash
+                );
+            }
+            _ => println!("{} sha256 {}", source.green(), hash.bold()),
+        }
+    }
+    success
+}
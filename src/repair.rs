@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use colored::Colorize;
+
+use crate::comment::{CommentType, Specialcomment};
+use crate::files::{get_comment_sign, LineEnding, BOM};
+use crate::hashable::HashAlgorithm;
+use crate::section::hashed_content;
+
+// a change to the lines of a file, line indices start at 0
+pub enum Edit {
+    Insert(usize, String), // insert a line before the line at index, or at the end
+    Remove(usize),
+}
+
+// a fix for a broken section, made of edits which only make sense together
+pub struct Repair {
+    pub line: u32,
+    pub description: String,
+    pub edits: Vec<Edit>,
+}
+
+// a section whose begin comment was seen, but not its end
+struct OpenSection {
+    name: String,
+    indent: String,
+    begin: usize,
+    hash: bool,
+    content: String,
+}
+
+// hash line for content, so the repaired section does not show up as modified
+fn hash_line(indent: &str, commentsign: &str, name: &str, content: &str) -> String {
+    let hash = HashAlgorithm::DEFAULT.digest(hashed_content(name, content).as_bytes());
+    format!("{}{}... {} hash {}", indent, commentsign, name, hash)
+}
+
+fn add_hash(repairs: &mut Vec<Repair>, section: &OpenSection, commentsign: &str) {
+    repairs.push(Repair {
+        line: section.begin as u32 + 1,
+        description: format!("add missing hash to section {}", section.name),
+        edits: vec![Edit::Insert(
+            section.begin + 1,
+            hash_line(
+                &section.indent,
+                commentsign,
+                &section.name,
+                &section.content,
+            ),
+        )],
+    });
+}
+
+// close a section without end comment before the line at index
+fn close_section(repairs: &mut Vec<Repair>, section: OpenSection, at: usize, commentsign: &str) {
+    if !section.hash {
+        add_hash(repairs, &section, commentsign);
+    }
+    let place = if at == usize::MAX {
+        String::from("the end of the file")
+    } else {
+        format!("line {}", at + 1)
+    };
+    repairs.push(Repair {
+        line: section.begin as u32 + 1,
+        description: format!("close section {} at {}", section.name, place),
+        edits: vec![Edit::Insert(
+            at,
+            format!("{}{}... {} end", section.indent, commentsign, section.name),
+        )],
+    });
+}
+
+/// find sections without end or hash comments and markers which do not belong to a section
+/// sections without end are closed before the next begin or at the end of the file
+pub fn find_repairs(content: &str, commentsign: &str) -> Vec<Repair> {
+    let mut repairs = Vec::new();
+    let mut open: Option<OpenSection> = None;
+    // first line after the last marker, stray end comments wrap the content after it
+    let mut boundary = 0;
+    let mut wrapped: String = String::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let comment = match Specialcomment::parse_line(line, commentsign, index as u32 + 1) {
+            // comments for the whole file are not part of any section content
+            Ok(Some(comment)) if comment.section == "all" => continue,
+            Ok(Some(comment)) => comment,
+            _ => {
+                match &mut open {
+                    Some(section) => {
+                        section.content.push_str(line);
+                        section.content.push('\n');
+                    }
+                    None => {
+                        wrapped.push_str(line);
+                        wrapped.push('\n');
+                    }
+                }
+                continue;
+            }
+        };
+        let isopen = open.as_ref().map(|section| section.name == comment.section) == Some(true);
+        match comment.comment_type {
+            CommentType::SectionBegin => {
+                if let Some(section) = open.take() {
+                    close_section(&mut repairs, section, index, commentsign);
+                }
+                seen.insert(comment.section.clone());
+                open = Some(OpenSection {
+                    name: comment.section.clone(),
+                    indent: comment.indent.clone(),
+                    begin: index,
+                    hash: false,
+                    content: String::new(),
+                });
+            }
+            CommentType::SectionEnd if isopen => {
+                let section = open.take().unwrap();
+                if !section.hash {
+                    add_hash(&mut repairs, &section, commentsign);
+                }
+            }
+            CommentType::SectionEnd
+                if open.is_none()
+                    && !seen.contains(&comment.section)
+                    && !wrapped.trim().is_empty() =>
+            {
+                // the begin comment got lost, the section starts after the previous marker
+                let mut edits = vec![Edit::Insert(
+                    boundary,
+                    format!(
+                        "{}{}... {} begin",
+                        comment.indent, commentsign, comment.section
+                    ),
+                )];
+                edits.push(Edit::Insert(
+                    boundary,
+                    hash_line(&comment.indent, commentsign, &comment.section, &wrapped),
+                ));
+                repairs.push(Repair {
+                    line: comment.line,
+                    description: format!(
+                        "wrap lines {}-{} into section {}",
+                        boundary + 1,
+                        index,
+                        comment.section
+                    ),
+                    edits,
+                });
+                seen.insert(comment.section.clone());
+            }
+            CommentType::SectionEnd => {
+                repairs.push(Repair {
+                    line: comment.line,
+                    description: format!("remove stray end of section {}", comment.section),
+                    edits: vec![Edit::Remove(index)],
+                });
+            }
+            CommentType::HashInfo if isopen && !open.as_ref().unwrap().hash => {
+                open.as_mut().unwrap().hash = true;
+            }
+            CommentType::HashInfo => {
+                let reason = if isopen { "duplicate" } else { "stray" };
+                repairs.push(Repair {
+                    line: comment.line,
+                    description: format!("remove {} hash of section {}", reason, comment.section),
+                    edits: vec![Edit::Remove(index)],
+                });
+            }
+            _ => {}
+        }
+        boundary = index + 1;
+        wrapped.clear();
+    }
+    if let Some(section) = open.take() {
+        close_section(&mut repairs, section, usize::MAX, commentsign);
+    }
+    repairs
+}
+
+/// content with the edits of repairs, lines end in \n
+pub fn apply_repairs(content: &str, repairs: &[Repair]) -> String {
+    let mut inserts: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    let mut removed: HashSet<usize> = HashSet::new();
+    for edit in repairs.iter().flat_map(|repair| &repair.edits) {
+        match edit {
+            Edit::Insert(index, line) => inserts.entry(*index).or_default().push(line),
+            Edit::Remove(index) => {
+                removed.insert(*index);
+            }
+        }
+    }
+
+    let mut output = String::new();
+    for (index, line) in content.lines().enumerate() {
+        for inserted in inserts.remove(&index).unwrap_or_default() {
+            output.push_str(inserted);
+            output.push('\n');
+        }
+        if !removed.contains(&index) {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    // closing comments at the end of the file
+    for (_, lines) in inserts {
+        for line in lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+/// ask for each repair if it should be made
+pub fn confirm_repairs(repairs: Vec<Repair>) -> Vec<Repair> {
+    let stdin = io::stdin();
+    let mut confirmed = Vec::new();
+    for repair in repairs {
+        print!(
+            "line {}: {}? [y/N] ",
+            repair.line,
+            repair.description.bold()
+        );
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        // closed stdin declines everything
+        if stdin
+            .lock()
+            .read_line(&mut answer)
+            .map_or(true, |read| read == 0)
+        {
+            break;
+        }
+        if matches!(answer.trim(), "y" | "yes") {
+            confirmed.push(repair);
+        }
+    }
+    confirmed
+}
+
+/// repair the sections of the file at path, asking before every repair unless assume_yes is set
+/// returns false if there was nothing to repair or every repair was declined
+pub fn repair_file(path: &Path, assume_yes: bool) -> io::Result<bool> {
+    let filename = path.to_str().unwrap();
+    let content = fs::read_to_string(path)?;
+    let bom = content.starts_with(BOM);
+    let lineending = LineEnding::detect(&content);
+    let content = content.strip_prefix(BOM).unwrap_or(&content);
+
+    let commentsign = get_comment_sign(filename, content);
+    if commentsign.is_empty() {
+        return Ok(false);
+    }
+    let repairs = find_repairs(content, &commentsign);
+    let repairs = if assume_yes {
+        for repair in &repairs {
+            println!("line {}: {}", repair.line, repair.description);
+        }
+        repairs
+    } else {
+        confirm_repairs(repairs)
+    };
+    if repairs.is_empty() {
+        return Ok(false);
+    }
+
+    let mut repaired = apply_repairs(content, &repairs);
+    if !content.ends_with('\n') {
+        repaired.pop();
+    }
+    if bom {
+        repaired.insert(0, BOM);
+    }
+    fs::write(path, lineending.apply(&repaired))?;
+    Ok(true)
+}
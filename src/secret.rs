@@ -0,0 +1,71 @@
+use std::env;
+use std::fs::read_to_string;
+use std::process::{Command, Stdio};
+
+use toml::Value;
+
+use crate::config::config_dir;
+
+// where the value of a secret comes from
+// configured in secrets.toml in the config directory, for example
+// github_token = { pass = "github/token" }
+// api_key = { env = "API_KEY" }
+// wifi = { command = "secret-tool lookup wifi home" }
+// secrets which are not configured are looked up in pass under their name
+enum SecretSource {
+    Pass(String),
+    Env(String),
+    Command(String),
+}
+
+impl SecretSource {
+    fn from_config(name: &str) -> SecretSource {
+        let config = read_to_string(config_dir().join("secrets.toml"))
+            .ok()
+            .and_then(|content| content.parse::<Value>().ok());
+        let entry = config.as_ref().and_then(|config| config.get(name));
+        let get = |key: &str| {
+            entry
+                .and_then(|entry| entry.get(key))
+                .and_then(|value| value.as_str())
+                .map(String::from)
+        };
+        if let Some(entry) = get("pass") {
+            SecretSource::Pass(entry)
+        } else if let Some(variable) = get("env") {
+            SecretSource::Env(variable)
+        } else if let Some(command) = get("command") {
+            SecretSource::Command(command)
+        } else {
+            SecretSource::Pass(String::from(name))
+        }
+    }
+}
+
+// first line of the output of a command
+fn command_output(command: &mut Command) -> Result<String, String> {
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("exited with {}", output.status));
+    }
+    let stdout = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
+    Ok(String::from(stdout.lines().next().unwrap_or("")))
+}
+
+/// value of secret name, secrets are only ever read at apply time
+pub fn lookup_secret(name: &str) -> Result<String, String> {
+    match SecretSource::from_config(name) {
+        SecretSource::Pass(entry) => command_output(Command::new("pass").arg("show").arg(&entry))
+            .map_err(|e| format!("pass show {} failed: {}", entry, e)),
+        SecretSource::Env(variable) => env::var(&variable)
+            .map_err(|_| format!("environment variable {} is not set", variable)),
+        SecretSource::Command(command) => {
+            command_output(Command::new("sh").arg("-c").arg(&command))
+                .map_err(|e| format!("{} failed: {}", command, e))
+        }
+    }
+}
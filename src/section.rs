@@ -1,12 +1,16 @@
 // use crate::comment;
 use crate::comment::CommentType;
 use crate::commentmap::CommentMap;
+use crate::condition::Condition;
+use crate::encryption::Encryption;
+use crate::expand::expand_variables;
+use crate::facts::Facts;
+use crate::template::render_template;
 use crate::{
     comment::Specialcomment,
-    hashable::{ChangeState, Hashable},
+    hashable::{ChangeState, HashAlgorithm, Hashable},
 };
 use colored::Colorize;
-use sha256::digest;
 
 #[derive(Clone)]
 pub enum Section {
@@ -16,10 +20,43 @@ pub enum Section {
     Anonymous(SectionData),
 }
 
+/// where to insert a section into a target which does not have it yet
+pub enum Placement {
+    Top,
+    Bottom,
+    After(String),
+}
+
+impl Placement {
+    pub fn parse(input: &str) -> Option<Placement> {
+        match input {
+            "top" => Some(Placement::Top),
+            "bottom" => Some(Placement::Bottom),
+            _ => {
+                let section = input.strip_prefix("after:")?;
+                if section.is_empty() {
+                    return None;
+                }
+                Some(Placement::After(String::from(section)))
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct NamedSectionData {
     pub name: String,           // section name, None if anonymous
     pub source: Option<String>, // source to update section from
+    pub target: Option<String>, // target file to apply section to instead of the file target
+    pub condition: Option<String>, // condition the machine has to fulfill to apply the section
+    pub expand: bool,           // expand environment variables when applying
+    pub template: bool,         // render section as template when applying
+    pub profile: Option<String>, // comma separated profiles the section belongs to
+    pub placement: Option<String>, // where to insert the section into targets missing it
+    pub anchor: Option<String>, // regex of the unmanaged target line to insert the section after
+    pub indent: String,         // whitespace markers are indented with
+    pub protected: bool,        // local only section, never overwritten by apply or update
+    pub encrypted: Option<String>, // tool the section content is encrypted with
     pub hash: String,           // current hash of section
     pub targethash: String,     // hash section should have if unmodified
 }
@@ -35,7 +72,6 @@ impl Hashable for Section {
     /// set target hash to current hash
     /// marking the section as unmodified
     /// return false if nothing has changed
-
     fn compile(&mut self) -> ChangeState {
         match self {
             Section::Named(_, named_data) => {
@@ -54,11 +90,52 @@ impl Hashable for Section {
     /// and detect section status
     fn finalize(&mut self) {
         if let Section::Named(data, named_data) = self {
-            named_data.hash = digest(data.content.as_str()).to_uppercase();
+            // keep the algorithm of the existing hash so it stays comparable
+            let algorithm = HashAlgorithm::from_hash(&named_data.targethash);
+            named_data.hash =
+                algorithm.digest(hashed_content(&named_data.name, &data.content).as_bytes());
         }
     }
 }
 
+// section and kind of an ignorebegin or ignoreend marker
+// markers are plain content, e.g. #... name ignorebegin
+fn ignore_marker(line: &str) -> Option<(&str, bool)> {
+    let (_, marker) = line.split_once("...")?;
+    let mut words = marker.split_whitespace();
+    let section = words.next()?;
+    let begin = match words.next()? {
+        "ignorebegin" => true,
+        "ignoreend" => false,
+        _ => return None,
+    };
+    if words.next().is_some() {
+        return None;
+    }
+    Some((section, begin))
+}
+
+/// the part of the content of section name which is hashed
+/// volatile lines ending in ...ignore and lines between
+/// name ignorebegin and name ignoreend markers are left out
+pub fn hashed_content(name: &str, content: &str) -> String {
+    let mut hashed = String::new();
+    let mut ignoring = false;
+    for line in content.split_inclusive('\n') {
+        if let Some((section, begin)) = ignore_marker(line) {
+            if section == name {
+                ignoring = begin;
+                continue;
+            }
+        }
+        if ignoring || line.trim_end().ends_with("...ignore") {
+            continue;
+        }
+        hashed.push_str(line);
+    }
+    hashed
+}
+
 impl Section {
     pub fn new(
         start: u32,
@@ -76,6 +153,16 @@ impl Section {
             NamedSectionData {
                 name,
                 source,
+                target: None,
+                condition: None,
+                expand: false,
+                template: false,
+                profile: None,
+                placement: None,
+                anchor: None,
+                indent: String::new(),
+                protected: false,
+                encrypted: None,
                 hash: String::from(""),
                 targethash,
             },
@@ -83,7 +170,7 @@ impl Section {
     }
 
     pub fn from_comment_map(name: &str, map: &CommentMap) -> Option<Section> {
-        Some(Section::new(
+        let mut section = Section::new(
             map.get_comment(name, CommentType::SectionBegin)?.line,
             map.get_comment(name, CommentType::SectionEnd)?.line,
             name.to_string(),
@@ -92,7 +179,41 @@ impl Section {
             map.get_comment(name, CommentType::HashInfo)?
                 .clone()
                 .argument?,
-        ))
+        );
+        if let Section::Named(_, named_data) = &mut section {
+            named_data.indent = map
+                .get_comment(name, CommentType::SectionBegin)?
+                .indent
+                .clone();
+            named_data.target = map
+                .get_comment(name, CommentType::TargetInfo)
+                .and_then(|target| target.clone().argument);
+            named_data.condition = map
+                .get_comment(name, CommentType::ConditionInfo)
+                .and_then(|condition| condition.clone().argument);
+            named_data.expand = map
+                .get_comment(name, CommentType::ExpandInfo)
+                .is_some();
+            named_data.template = map
+                .get_comment(name, CommentType::TemplateInfo)
+                .is_some();
+            named_data.profile = map
+                .get_comment(name, CommentType::ProfileInfo)
+                .and_then(|profile| profile.clone().argument);
+            named_data.placement = map
+                .get_comment(name, CommentType::PlacementInfo)
+                .and_then(|placement| placement.clone().argument);
+            named_data.anchor = map
+                .get_comment(name, CommentType::AnchorInfo)
+                .and_then(|anchor| anchor.clone().argument);
+            named_data.protected = map
+                .get_comment(name, CommentType::ProtectedInfo)
+                .is_some();
+            named_data.encrypted = map
+                .get_comment(name, CommentType::EncryptedInfo)
+                .and_then(|encrypted| encrypted.clone().argument);
+        }
+        Some(section)
     }
 
     pub fn new_anonymous(start: u32, end: u32) -> Section {
@@ -106,18 +227,21 @@ impl Section {
     /// append string to content
     //maybe make this a trait?
     pub fn push_line(&mut self, line: &str) {
-        match self {
+        let content = &mut match self {
             Section::Named(data, _) => data,
             Section::Anonymous(data) => data,
         }
-        .content
-        .push_str(format!("{}\n", line).as_str());
+        .content;
+        content.push_str(line);
+        content.push('\n');
     }
 
     /// return entire section with formatted marker comments and content
     pub fn output(&self, commentsign: &str) -> String {
         match self {
             Section::Named(data, named_data) => {
+                // markers keep the indentation of the begin marker they were read from
+                let commentsign = &format!("{}{}", named_data.indent, commentsign);
                 let mut outstr = String::new();
                 outstr.push_str(&Specialcomment::new_string(
                     commentsign,
@@ -139,7 +263,78 @@ impl Section {
                         Some(source),
                     ));
                 }
-                //TODO: section target
+                if let Some(target) = named_data.target.as_ref() {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::TargetInfo,
+                        &named_data.name,
+                        Some(target),
+                    ));
+                }
+                if let Some(condition) = named_data.condition.as_ref() {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::ConditionInfo,
+                        &named_data.name,
+                        Some(condition),
+                    ));
+                }
+                if let Some(profile) = named_data.profile.as_ref() {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::ProfileInfo,
+                        &named_data.name,
+                        Some(profile),
+                    ));
+                }
+                if let Some(placement) = named_data.placement.as_ref() {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::PlacementInfo,
+                        &named_data.name,
+                        Some(placement),
+                    ));
+                }
+                if let Some(anchor) = named_data.anchor.as_ref() {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::AnchorInfo,
+                        &named_data.name,
+                        Some(anchor),
+                    ));
+                }
+                if named_data.protected {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::ProtectedInfo,
+                        &named_data.name,
+                        None,
+                    ));
+                }
+                if let Some(encrypted) = &named_data.encrypted {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::EncryptedInfo,
+                        &named_data.name,
+                        Some(encrypted),
+                    ));
+                }
+                if named_data.expand {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::ExpandInfo,
+                        &named_data.name,
+                        None,
+                    ));
+                }
+                if named_data.template {
+                    outstr.push_str(&Specialcomment::new_string(
+                        commentsign,
+                        CommentType::TemplateInfo,
+                        &named_data.name,
+                        None,
+                    ));
+                }
                 outstr.push_str(&data.content);
                 outstr.push_str(&Specialcomment::new_string(
                     commentsign,
@@ -153,6 +348,75 @@ impl Section {
         }
     }
 
+    /// return the section as it should be written to a target
+    /// the source keeps placeholders and its hash, the rendered
+    /// section is hashed after rendering so the target is not modified
+    /// encrypted sections are decrypted, None if that or the template fails
+    pub fn render(&self) -> Option<Section> {
+        let mut rendered = self.clone();
+        if let Section::Named(data, named_data) = &mut rendered {
+            let encryption = named_data.encrypted.as_deref().and_then(Encryption::parse);
+            if encryption.is_none()
+                && (!(named_data.expand || named_data.template)
+                    || named_data.hash != named_data.targethash)
+            {
+                return Some(rendered);
+            }
+            let mut content = data.content.clone();
+            // the target gets the plaintext, the source hash covers the ciphertext
+            if let Some(encryption) = encryption {
+                match encryption.decrypt(&content) {
+                    Some(plaintext) => content = plaintext,
+                    None => {
+                        eprintln!("could not decrypt section {}", named_data.name.red());
+                        return None;
+                    }
+                }
+                named_data.encrypted = None;
+            }
+            if named_data.template {
+                match render_template(&content) {
+                    Ok(output) => content = output,
+                    Err(e) => {
+                        eprintln!(
+                            "could not render template {}: {}",
+                            named_data.name.red(),
+                            e
+                        );
+                        return None;
+                    }
+                }
+            }
+            if named_data.expand {
+                content = expand_variables(&content);
+            }
+            data.content = content;
+            rendered.finalize();
+            rendered.compile();
+        }
+        Some(rendered)
+    }
+
+    /// check if the section condition and profile match this machine
+    /// sections without a condition or profile always apply
+    pub fn applies(&self, facts: &Facts) -> bool {
+        match self {
+            Section::Named(_, named_data) => {
+                if !facts.matches_profile(&named_data.profile) {
+                    return false;
+                }
+                match &named_data.condition {
+                    Some(condition) => match Condition::parse(condition) {
+                        Some(condition) => condition.evaluate(facts),
+                        None => false,
+                    },
+                    None => true,
+                }
+            }
+            Section::Anonymous(_) => true,
+        }
+    }
+
     pub fn get_data(&self) -> &SectionData {
         match self {
             Section::Named(data, _) => data,
@@ -160,11 +424,18 @@ impl Section {
         }
     }
 
+    pub fn get_data_mut(&mut self) -> &mut SectionData {
+        match self {
+            Section::Named(data, _) => data,
+            Section::Anonymous(data) => data,
+        }
+    }
+
     pub fn pretty_info(&self) -> Option<String> {
         match self {
             Section::Anonymous(_) => None,
             Section::Named(data, named_data) => Some(format!(
-                "{}-{}: {} | {}{}",
+                "{}-{}: {} | {}{}{}",
                 &data.startline,
                 &data.endline,
                 &named_data.name,
@@ -177,6 +448,11 @@ impl Section {
                     format!(" | source {}", source)
                 } else {
                     String::new()
+                },
+                if let Some(target) = &named_data.target {
+                    format!(" | target {}", target)
+                } else {
+                    String::new()
                 }
             )),
         }
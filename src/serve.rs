@@ -0,0 +1,189 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use colored::Colorize;
+use tracing::{info, warn};
+
+// requests with a longer head are refused
+const MAX_HEAD: usize = 8192;
+
+pub struct Response {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn error(status: u16) -> Response {
+        Response {
+            status,
+            etag: None,
+            body: format!("{}\n", reason(status)).into_bytes(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+// the etag of a file is the hash of its content, so it survives restarts and copies
+pub fn etag(content: &[u8]) -> String {
+    format!("\"{}\"", sha256::digest(content))
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// file below root a request path points to
+// paths leaving root, also through symlinks, and git internals are not served
+fn resolve_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_decode(path.split(['?', '#']).next()?)?;
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::RootDir => {}
+            Component::Normal(name) if name != ".git" => resolved.push(name),
+            _ => return None,
+        }
+    }
+    let resolved = resolved.canonicalize().ok()?;
+    if !resolved.starts_with(root) || !resolved.is_file() {
+        return None;
+    }
+    Some(resolved)
+}
+
+/// answer the request with head, root has to be canonical
+/// only GET and HEAD are allowed, If-None-Match is answered with 304 if the etag matches
+pub fn respond(root: &Path, head: &str) -> Response {
+    let mut lines = head.lines();
+    let mut requestline = lines.next().unwrap_or_default().split_whitespace();
+    let (method, path) = match (requestline.next(), requestline.next()) {
+        (Some(method), Some(path)) => (method, path),
+        _ => return Response::error(400),
+    };
+    if method != "GET" && method != "HEAD" {
+        return Response::error(405);
+    }
+    let content = match resolve_path(root, path).and_then(|path| fs::read(path).ok()) {
+        Some(content) => content,
+        None => return Response::error(404),
+    };
+    let etag = etag(&content);
+    let cached = lines
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("if-none-match"))
+        .flat_map(|(_, value)| value.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    if cached {
+        return Response {
+            status: 304,
+            etag: Some(etag),
+            body: Vec::new(),
+        };
+    }
+    Response {
+        status: 200,
+        etag: Some(etag),
+        body: content,
+    }
+}
+
+fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 || head.len() + read > MAX_HEAD {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn handle(root: &Path, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let head = read_head(&mut stream)?;
+    let response = respond(root, &head);
+    info!(
+        "{} {}",
+        head.lines().next().unwrap_or_default(),
+        response.status
+    );
+
+    let mut output = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    if let Some(etag) = &response.etag {
+        output.push_str(&format!("ETag: {}\r\nCache-Control: no-cache\r\n", etag));
+    }
+    if response.status == 200 {
+        output.push_str("Content-Type: application/octet-stream\r\n");
+    }
+    output.push_str("\r\n");
+    stream.write_all(output.as_bytes())?;
+    if !head.starts_with("HEAD ") {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+/// serve the files below dir read-only over http until the process is stopped
+pub fn serve(dir: &Path, address: &str, port: u16) -> io::Result<()> {
+    let root = dir.canonicalize()?;
+    let listener = TcpListener::bind((address, port))?;
+    println!(
+        "serving {} on http://{}",
+        root.display().to_string().bold(),
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("could not accept connection: {}", e);
+                continue;
+            }
+        };
+        let root = root.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(&root, stream) {
+                warn!("could not answer request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::PathBuf;
+
+use diffy::{create_patch, PatchFormatter};
+
+use crate::config::state_dir;
+use crate::files::write_atomic;
+
+// section contents as they were applied, addressed by their hash
+// they are the common base when merging local changes with the source
+pub fn snapshot_dir() -> PathBuf {
+    state_dir().join("snapshots")
+}
+
+pub fn snapshot_path(hash: &str) -> PathBuf {
+    snapshot_dir().join(hash.replace(':', "-"))
+}
+
+pub fn store_snapshot(hash: &str, content: &str) {
+    let path = snapshot_path(hash);
+    if hash.is_empty() || path.exists() {
+        return;
+    }
+    // snapshots are a convenience, failing to store one does not stop apply
+    if fs::create_dir_all(snapshot_dir()).is_ok() {
+        let _ = write_atomic(&path, content.as_bytes());
+    }
+}
+
+pub fn load_snapshot(hash: &str) -> Option<String> {
+    fs::read_to_string(snapshot_path(hash)).ok()
+}
+
+/// unified diff from old to new, colored if colors are enabled
+pub fn format_patch(old: &str, new: &str) -> String {
+    let patch = create_patch(old, new);
+    let formatter = if colored::control::SHOULD_COLORIZE.should_colorize() {
+        PatchFormatter::new().with_color()
+    } else {
+        PatchFormatter::new()
+    };
+    let output = formatter.fmt_patch(&patch).to_string();
+    output
+}
+
+/// what changed in content since it was snapshotted with hash
+/// None if there is no snapshot to compare with
+pub fn diff_snapshot(hash: &str, content: &str) -> Option<String> {
+    let snapshot = load_snapshot(hash)?;
+    Some(format_patch(&snapshot, content))
+}
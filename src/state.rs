@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use toml::Value;
+
+use crate::config::state_dir;
+use crate::facts::Facts;
+use crate::files::{expand_tilde, write_atomic, DotFile};
+use crate::section::Section;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Apply,
+    Compile,  // source and target are the compiled file
+    Rollback, // target restored to what source applied earlier
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Apply => "apply",
+            Action::Compile => "compile",
+            Action::Rollback => "rollback",
+        }
+    }
+}
+
+// one source applied to one target
+#[derive(Clone)]
+pub struct ApplyRecord {
+    pub action: Action,
+    pub source: String,
+    pub target: String,
+    pub hashes: Vec<(String, String)>, // section name and hash, "file" for metafiles
+    pub time: i64,                     // seconds since the epoch
+}
+
+impl ApplyRecord {
+    /// sections added, changed and removed since previous, the record before it for the same target
+    pub fn changes(&self, previous: Option<&ApplyRecord>) -> Vec<String> {
+        let previous = match previous {
+            Some(previous) => previous,
+            None => {
+                return self
+                    .hashes
+                    .iter()
+                    .map(|(name, _)| format!("added {}", name))
+                    .collect()
+            }
+        };
+        let mut changes = Vec::new();
+        for (name, hash) in &self.hashes {
+            match previous.hashes.iter().find(|(oldname, _)| oldname == name) {
+                None => changes.push(format!("added {}", name)),
+                Some((_, oldhash)) if oldhash != hash => changes.push(format!("changed {}", name)),
+                Some(_) => {}
+            }
+        }
+        for (name, _) in &previous.hashes {
+            if !self.hashes.iter().any(|(newname, _)| newname == name) {
+                changes.push(format!("removed {}", name));
+            }
+        }
+        changes
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn canonical_name(filename: &str) -> String {
+    fs::canonicalize(filename)
+        .ok()
+        .and_then(|path| path.to_str().map(String::from))
+        .unwrap_or_else(|| filename.to_string())
+}
+
+// history of everything imosid applied on this machine
+pub struct StateDb {
+    path: PathBuf,
+    pub records: Vec<ApplyRecord>,
+}
+
+impl StateDb {
+    pub fn load() -> StateDb {
+        let mut state = StateDb {
+            path: state_dir().join("state.toml"),
+            records: Vec::new(),
+        };
+        let value = match fs::read_to_string(&state.path)
+            .ok()
+            .and_then(|content| content.parse::<Value>().ok())
+        {
+            Some(value) => value,
+            None => return state,
+        };
+        if let Some(records) = value.get("apply").and_then(|records| records.as_array()) {
+            state.records = records.iter().filter_map(parse_record).collect();
+        }
+        state
+    }
+
+    /// add a record for every target applying dotfile changed
+    pub fn record(&mut self, dotfile: &DotFile, changed: &[String], facts: &Facts) {
+        let time = now();
+        let source = canonical_name(&dotfile.filename);
+        for (target, hashes) in get_target_hashes(dotfile, changed, facts) {
+            self.records.push(ApplyRecord {
+                action: Action::Apply,
+                source: source.clone(),
+                target,
+                hashes,
+                time,
+            });
+        }
+    }
+
+    /// add a record for the sections of a freshly compiled dotfile
+    pub fn record_compile(&mut self, dotfile: &DotFile) {
+        let filename = canonical_name(&dotfile.filename);
+        let hashes = match &dotfile.metafile {
+            Some(metafile) => vec![(String::from("file"), metafile.hash.clone())],
+            None => dotfile
+                .sections
+                .iter()
+                .filter_map(|section| match section {
+                    Section::Named(_, named_data) => {
+                        Some((named_data.name.clone(), named_data.hash.clone()))
+                    }
+                    Section::Anonymous(_) => None,
+                })
+                .collect(),
+        };
+        self.records.push(ApplyRecord {
+            action: Action::Compile,
+            source: filename.clone(),
+            target: filename,
+            hashes,
+            time: now(),
+        });
+    }
+
+    /// add a record for target being restored to the state of record
+    pub fn record_rollback(&mut self, target: &str, record: &ApplyRecord) {
+        self.records.push(ApplyRecord {
+            action: Action::Rollback,
+            source: record.source.clone(),
+            target: canonical_name(target),
+            hashes: record.hashes.clone(),
+            time: now(),
+        });
+    }
+
+    /// records in chronological order with their id, only the ones about path if given
+    /// each record comes with the one before it which changed the same file
+    pub fn history(&self, path: Option<&str>) -> Vec<(usize, &ApplyRecord, Option<&ApplyRecord>)> {
+        let path = path.map(|path| canonical_name(&expand_tilde(path)));
+        // ids are positions in the database, which stay the same when records are added
+        let mut records: Vec<(usize, &ApplyRecord)> = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(index, record)| (index + 1, record))
+            .collect();
+        records.sort_by_key(|(_, record)| record.time);
+        let mut history = Vec::new();
+        for (index, (id, record)) in records.iter().enumerate() {
+            if let Some(path) = &path {
+                if &canonical_name(&record.target) != path
+                    && &canonical_name(&record.source) != path
+                {
+                    continue;
+                }
+            }
+            // compiles change sources, applies and rollbacks change targets
+            let compiled = record.action == Action::Compile;
+            let previous = records[..index]
+                .iter()
+                .rev()
+                .map(|(_, other)| *other)
+                .find(|other| {
+                    other.target == record.target && (other.action == Action::Compile) == compiled
+                });
+            history.push((*id, *record, previous));
+        }
+        history
+    }
+
+    /// the apply record target should be rolled back to
+    /// to is an id from the log or a date, the last apply before it is used
+    /// without to, the apply before the most recent one is used
+    pub fn find_rollback(&self, target: &str, to: Option<&str>) -> Result<&ApplyRecord, String> {
+        let applies: Vec<(usize, &ApplyRecord)> = self
+            .history(Some(target))
+            .into_iter()
+            .filter(|(_, record, _)| {
+                record.action == Action::Apply
+                    && canonical_name(&record.target) == canonical_name(target)
+            })
+            .map(|(id, record, _)| (id, record))
+            .collect();
+        if applies.is_empty() {
+            return Err(format!("{} was never applied", target));
+        }
+        let to = match to {
+            Some(to) => to,
+            None => {
+                return match applies.len() {
+                    1 => Err(format!("{} was only applied once", target)),
+                    count => Ok(applies[count - 2].1),
+                }
+            }
+        };
+        if let Ok(id) = to.trim_start_matches('#').parse::<usize>() {
+            return applies
+                .iter()
+                .find(|(recordid, _)| *recordid == id)
+                .map(|(_, record)| *record)
+                .ok_or_else(|| format!("no apply of {} with id {}", target, id));
+        }
+        let time = parse_time(to).ok_or_else(|| format!("invalid id or date {}", to))?;
+        applies
+            .iter()
+            .rev()
+            .find(|(_, record)| record.time <= time)
+            .map(|(_, record)| *record)
+            .ok_or_else(|| format!("{} was not applied before {}", target, to))
+    }
+
+    /// most recent apply record of every target
+    pub fn latest(&self) -> HashMap<&str, &ApplyRecord> {
+        let mut latest = HashMap::new();
+        for record in self.records.iter().filter(|r| r.action == Action::Apply) {
+            latest.insert(record.target.as_str(), record);
+        }
+        latest
+    }
+
+    /// existing targets whose source was deleted or does not target them anymore
+    pub fn find_orphans(&self, facts: &Facts) -> Vec<&ApplyRecord> {
+        let mut orphans = Vec::new();
+        for (target, record) in self.latest() {
+            if !Path::new(target).exists() {
+                continue;
+            }
+            // from_pathbuf cannot be used on deleted sources
+            let orphaned = !Path::new(&record.source).exists()
+                || match DotFile::from_pathbuf(&PathBuf::from(&record.source)) {
+                    Ok(source) => !source.get_target_paths(facts).iter().any(|t| t == target),
+                    Err(_) => false,
+                };
+            if orphaned {
+                orphans.push(record);
+            }
+        }
+        orphans.sort_by(|a, b| a.target.cmp(&b.target));
+        orphans
+    }
+
+    pub fn save(&self) -> bool {
+        if let Some(parent) = self.path.parent() {
+            if fs::create_dir_all(parent).is_err() {
+                eprintln!("could not create state directory");
+                return false;
+            }
+        }
+        let records = self.records.iter().map(record_to_value).collect();
+        let mut map = toml::map::Map::new();
+        map.insert("apply".into(), Value::Array(records));
+        let content = toml::to_string(&Value::Table(map)).expect("records are valid toml");
+        if write_atomic(&self.path, content.as_bytes()).is_err() {
+            eprintln!("could not write state to {}", self.path.to_str().unwrap());
+            return false;
+        }
+        true
+    }
+}
+
+// hashes of the sections written to each changed target
+// a section counts as written if the target now holds it unmodified as the source renders it,
+// sections skipped as modified or protected and sections not selected are left out
+// rendered sections are recorded with the hash of what the target got,
+// which is also what their snapshots are stored under
+fn get_target_hashes(
+    dotfile: &DotFile,
+    changed: &[String],
+    facts: &Facts,
+) -> Vec<(String, Vec<(String, String)>)> {
+    let mut targets: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    if let Some(metafile) = &dotfile.metafile {
+        if let Some(target) = &dotfile.targetfile {
+            let target = expand_tilde(target);
+            if changed.contains(&target) {
+                let hashes = vec![(String::from("file"), metafile.hash.clone())];
+                targets.push((target, hashes));
+            }
+        }
+        return targets;
+    }
+    let mut targetfiles: HashMap<String, Option<DotFile>> = HashMap::new();
+    for section in &dotfile.sections {
+        if !section.applies(facts) {
+            continue;
+        }
+        if let Some(Section::Named(_, named_data)) = section.render() {
+            let target = match named_data.target.as_ref().or(dotfile.targetfile.as_ref()) {
+                Some(target) => expand_tilde(target),
+                None => continue,
+            };
+            if !changed.contains(&target) {
+                continue;
+            }
+            let targetfile = targetfiles
+                .entry(target.clone())
+                .or_insert_with(|| DotFile::from_pathbuf(&PathBuf::from(&target)).ok());
+            let written = targetfile.as_ref().is_some_and(|targetfile| {
+                targetfile.sections.iter().any(|section| match section {
+                    Section::Named(_, target_data) => {
+                        target_data.name == named_data.name
+                            && target_data.hash == named_data.hash
+                            && target_data.hash == target_data.targethash
+                    }
+                    Section::Anonymous(_) => false,
+                })
+            });
+            if !written {
+                continue;
+            }
+            let hash = (named_data.name, named_data.hash);
+            match targets.iter_mut().find(|(name, _)| name == &target) {
+                Some((_, hashes)) => hashes.push(hash),
+                None => targets.push((target, vec![hash])),
+            }
+        }
+    }
+    targets
+}
+
+fn parse_record(value: &Value) -> Option<ApplyRecord> {
+    let mut hashes = Vec::new();
+    for (name, hash) in value.get("hashes")?.as_table()? {
+        hashes.push((name.clone(), hash.as_str()?.to_string()));
+    }
+    // records from before compiles were recorded have no action
+    let action = match value.get("action").and_then(|action| action.as_str()) {
+        Some("compile") => Action::Compile,
+        Some("rollback") => Action::Rollback,
+        _ => Action::Apply,
+    };
+    Some(ApplyRecord {
+        action,
+        source: value.get("source")?.as_str()?.to_string(),
+        target: value.get("target")?.as_str()?.to_string(),
+        hashes,
+        time: value.get("time")?.as_integer()?,
+    })
+}
+
+fn record_to_value(record: &ApplyRecord) -> Value {
+    let mut hashes = toml::map::Map::new();
+    for (name, hash) in &record.hashes {
+        hashes.insert(name.clone(), Value::String(hash.clone()));
+    }
+    let mut map = toml::map::Map::new();
+    map.insert(
+        "action".into(),
+        Value::String(record.action.as_str().into()),
+    );
+    map.insert("source".into(), Value::String(record.source.clone()));
+    map.insert("target".into(), Value::String(record.target.clone()));
+    map.insert("hashes".into(), Value::Table(hashes));
+    map.insert("time".into(), Value::Integer(record.time));
+    Value::Table(map)
+}
+
+/// seconds since the epoch as a utc date and time, 2023-06-01 12:00:00
+pub fn format_time(time: i64) -> String {
+    let days = time.div_euclid(86400);
+    let seconds = time.rem_euclid(86400);
+    // civil date from days since 1970-01-01
+    let shifted = days + 719468;
+    let era = shifted.div_euclid(146097);
+    let dayofera = shifted.rem_euclid(146097);
+    let yearofera = (dayofera - dayofera / 1460 + dayofera / 36524 - dayofera / 146096) / 365;
+    let dayofyear = dayofera - (365 * yearofera + yearofera / 4 - yearofera / 100);
+    let monthindex = (5 * dayofyear + 2) / 153;
+    let day = dayofyear - (153 * monthindex + 2) / 5 + 1;
+    let month = if monthindex < 10 {
+        monthindex + 3
+    } else {
+        monthindex - 9
+    };
+    let year = yearofera + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// utc date like 2023-06-01, optionally followed by a time like 12:00 or 12:00:00
+/// as seconds since the epoch
+pub fn parse_time(text: &str) -> Option<i64> {
+    let (date, time) = match text.trim().split_once([' ', 'T']) {
+        Some((date, time)) => (date, time),
+        None => (text.trim(), "00:00"),
+    };
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+    let hours = time.next()??;
+    let minutes = time.next()??;
+    let seconds = time.next().unwrap_or(Some(0))?;
+    if time.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    // days since 1970-01-01 of the civil date
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yearofera = year.rem_euclid(400);
+    let monthindex = if month > 2 { month - 3 } else { month + 9 };
+    let dayofyear = (153 * monthindex + 2) / 5 + day - 1;
+    let dayofera = yearofera * 365 + yearofera / 4 - yearofera / 100 + dayofyear;
+    let days = era * 146097 + dayofera - 719468;
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
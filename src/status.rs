@@ -0,0 +1,111 @@
+use crate::files::{expand_tilde, ApplyOptions, DotFile};
+use crate::permissions::is_readonly;
+use crate::section::Section;
+use colored::{ColoredString, Colorize};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    UpToDate,
+    NeedsApply,
+    ModifiedLocally,
+    Unmanaged,
+    MissingTarget,
+    ReadonlyTarget, // needs apply, but the target is read-only
+}
+
+impl FileStatus {
+    // right aligned colored label for status output
+    pub fn label(&self) -> ColoredString {
+        let text = match self {
+            FileStatus::UpToDate => "up to date",
+            FileStatus::NeedsApply => "needs apply",
+            FileStatus::ModifiedLocally => "modified locally",
+            FileStatus::Unmanaged => "unmanaged",
+            FileStatus::MissingTarget => "missing target",
+            FileStatus::ReadonlyTarget => "read-only target",
+        };
+        let padded = format!("{:>16}", text);
+        match self {
+            FileStatus::UpToDate => padded.green(),
+            FileStatus::NeedsApply => padded.yellow(),
+            FileStatus::ModifiedLocally => padded.red(),
+            FileStatus::Unmanaged => padded.dimmed(),
+            FileStatus::MissingTarget => padded.blue(),
+            FileStatus::ReadonlyTarget => padded.magenta(),
+        }
+    }
+}
+
+// status of a target apply would change
+fn outdated(target: &str) -> FileStatus {
+    if is_readonly(Path::new(target)) {
+        FileStatus::ReadonlyTarget
+    } else {
+        FileStatus::NeedsApply
+    }
+}
+
+// compare a source file against its targets
+// return None if the file does not declare any target
+pub fn get_status(file: &DotFile, options: &ApplyOptions) -> Option<FileStatus> {
+    if !file.is_managed() {
+        return Some(FileStatus::Unmanaged);
+    }
+    if file.modified {
+        return Some(FileStatus::ModifiedLocally);
+    }
+
+    if let Some(metafile) = &file.metafile {
+        let target = expand_tilde(file.targetfile.as_ref()?);
+        if !Path::new(&target).is_file() {
+            return Some(FileStatus::MissingTarget);
+        }
+        return match DotFile::new(&target) {
+            Ok(targetfile) => match &targetfile.metafile {
+                Some(targetmeta) if targetmeta.hash == metafile.hash => {
+                    Some(FileStatus::UpToDate)
+                }
+                _ => Some(outdated(&target)),
+            },
+            Err(_) => Some(outdated(&target)),
+        };
+    }
+
+    let mut targets: HashMap<String, Option<DotFile>> = HashMap::new();
+    let mut status = None;
+    for section in &file.sections {
+        let named_data = match section {
+            Section::Named(_, named_data) => named_data,
+            Section::Anonymous(_) => continue,
+        };
+        if !options.selects(section) {
+            continue;
+        }
+        let target = match named_data.target.as_ref().or(file.targetfile.as_ref()) {
+            Some(target) => expand_tilde(target),
+            None => continue,
+        };
+        let targetfile = targets
+            .entry(target.clone())
+            .or_insert_with(|| {
+                if Path::new(&target).is_file() {
+                    DotFile::new(&target).ok()
+                } else {
+                    None
+                }
+            });
+        match targetfile {
+            None => return Some(FileStatus::MissingTarget),
+            Some(targetfile) => {
+                if targetfile.has_section_hash(&named_data.name, &named_data.hash) {
+                    status = Some(FileStatus::UpToDate);
+                } else {
+                    return Some(outdated(&target));
+                }
+            }
+        }
+    }
+    status
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use colored::Colorize;
+
+use crate::config::systemd_user_dir;
+use crate::files::write_atomic;
+
+pub const SERVICE_NAME: &str = "imosid-apply.service";
+pub const TIMER_NAME: &str = "imosid-apply.timer";
+
+/// interval like 30s, 15m, 1h or 1d as systemd time span, None if it is invalid
+pub fn parse_interval(interval: &str) -> Option<String> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split);
+    if number.parse::<u32>().ok()? == 0 {
+        return None;
+    }
+    let unit = match unit.trim() {
+        "s" | "sec" => "s",
+        "m" | "min" => "min",
+        "h" => "h",
+        "d" => "d",
+        _ => return None,
+    };
+    Some(format!("{}{}", number, unit))
+}
+
+// quote a word of an ExecStart line, % and $ would be expanded by systemd
+fn systemd_quote(word: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// service running imosid apply --changed-only on dir with apply_args
+/// unchanged files are skipped through the hash cache, so frequent runs are cheap
+pub fn service_unit(imosid: &Path, dir: &Path, apply_args: &[String]) -> String {
+    let mut exec = format!(
+        "{} apply {} --changed-only",
+        systemd_quote(&imosid.display().to_string()),
+        systemd_quote(&dir.display().to_string())
+    );
+    for arg in apply_args {
+        exec.push(' ');
+        exec.push_str(&systemd_quote(arg));
+    }
+    format!(
+        "[Unit]\nDescription=Apply imosid config from {}\n\n[Service]\nType=oneshot\nExecStart={}\n",
+        dir.display(),
+        exec
+    )
+}
+
+/// timer starting the service every interval and shortly after login
+pub fn timer_unit(interval: &str) -> String {
+    format!(
+        "[Unit]\nDescription=Apply imosid config every {}\n\n[Timer]\nOnStartupSec=2min\nOnUnitActiveSec={}\n\n[Install]\nWantedBy=timers.target\n",
+        interval, interval
+    )
+}
+
+fn systemctl(args: &[&str]) -> bool {
+    match Command::new("systemctl").arg("--user").args(args).status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("could not run systemctl: {}", e);
+            false
+        }
+    }
+}
+
+/// write service and timer to the user unit directory and start the timer
+pub fn install(service: &str, timer: &str) -> io::Result<bool> {
+    let dir = systemd_user_dir();
+    fs::create_dir_all(&dir)?;
+    write_atomic(&dir.join(SERVICE_NAME), service.as_bytes())?;
+    write_atomic(&dir.join(TIMER_NAME), timer.as_bytes())?;
+    println!(
+        "wrote {} and {} to {}",
+        SERVICE_NAME.bold(),
+        TIMER_NAME.bold(),
+        dir.display()
+    );
+    Ok(systemctl(&["daemon-reload"]) && systemctl(&["enable", "--now", TIMER_NAME]))
+}
+
+/// stop the timer and remove both units, false if they were not installed
+pub fn uninstall() -> io::Result<bool> {
+    let dir = systemd_user_dir();
+    if !dir.join(TIMER_NAME).exists() && !dir.join(SERVICE_NAME).exists() {
+        return Ok(false);
+    }
+    systemctl(&["disable", "--now", TIMER_NAME]);
+    for name in [TIMER_NAME, SERVICE_NAME] {
+        let path = dir.join(name);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    systemctl(&["daemon-reload"]);
+    Ok(true)
+}
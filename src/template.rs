@@ -0,0 +1,45 @@
+use crate::config::config_dir;
+use crate::facts::Facts;
+use crate::secret::lookup_secret;
+use minijinja::{context, Environment, Error, ErrorKind, Value};
+use std::fs::read_to_string;
+
+// user provided template variables from vars.toml in the config directory
+fn load_vars() -> toml::Value {
+    let path = config_dir().join("vars.toml");
+    match read_to_string(&path) {
+        Ok(content) => match content.parse::<toml::Value>() {
+            Ok(value) => value,
+            Err(_) => {
+                eprintln!("could not parse template variables {}", path.display());
+                toml::Value::Table(toml::map::Map::new())
+            }
+        },
+        Err(_) => toml::Value::Table(toml::map::Map::new()),
+    }
+}
+
+// render section content with host facts and user variables
+pub fn render_template(source: &str) -> Result<String, minijinja::Error> {
+    let mut environment = Environment::new();
+    environment.set_keep_trailing_newline(true);
+    // secrets are pulled in when rendering and never stored in the source
+    environment.add_function("secret", |name: String| {
+        lookup_secret(&name).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("could not get secret {}: {}", name, e),
+            )
+        })
+    });
+    let facts = Facts::gather();
+    environment.render_str(
+        source,
+        context! {
+            hostname => facts.hostname,
+            os => facts.os,
+            arch => facts.arch,
+            vars => Value::from_serialize(load_vars()),
+        },
+    )
+}
@@ -16,13 +16,46 @@ echo \"content of the first section\"
 echo \"content of the second section\"
 #... secondsection end";
 
+    use crate::app::{build_app, pick_section_matcher};
+    use crate::archive::{export_archive, import_archive, EXPORT_MANIFEST_NAME};
     use crate::comment::{CommentType, Specialcomment};
-    use crate::files::DotFile;
-    use crate::hashable::Hashable;
-    use crate::section::Section;
+    use crate::condition::Condition;
+    use crate::config::set_test_home;
+    use crate::deploy::{check_remote_dir, shell_quote, Deployment};
+    use crate::dotwalker::{apply_config_dir, walk_config_dir, WalkOptions};
+    use crate::diagnostic::Severity;
+    use crate::dirmeta::{DirMetaFile, DIR_METAFILE_NAME};
+    use crate::expand::{expand_variables, resolve_os_target};
+    use crate::facts::Facts;
+    use crate::files::{
+        expand_tilde, imosid_version, link_intact, needs_metafile, ApplyOptions, ApplyResult,
+        ConflictStyle, DotFile, LineEnding, ManagementState, ReadonlyPolicy,
+    };
+    use crate::gc::{find_garbage, parse_size, Entry, Retention};
+    use crate::git::{check_clean, commit_file, repo_root};
+    use crate::hashable::{HashAlgorithm, Hashable};
+    use crate::hashcache::HashCache;
+    use crate::importer::{import_chezmoi, import_dotbot, import_stow};
+    use crate::languages::Languages;
+    use crate::lockfile::{LockFile, LockedSource};
+    use crate::matcher::SectionMatcher;
+    use crate::repair::{apply_repairs, find_repairs};
+    use crate::metafile::MetaFile;
+    use crate::notify::Reload;
+    use crate::permissions::{is_readonly, Permissions};
+    use crate::picker::fuzzy_score;
+    use crate::reconcile::sync_target;
+    use crate::privilege::{needs_root, write_file, RootPolicy};
+    use crate::remote::{Pin, SourceLocation, SourceSpec};
+    use crate::section::{hashed_content, Section};
+    use crate::serve::{etag, respond};
+    use crate::snapshot::store_snapshot;
+    use crate::systemd::{parse_interval, service_unit, timer_unit};
+    use crate::state::{format_time, parse_time, Action, ApplyRecord, StateDb};
 
     use std::fs::File;
     use std::io::Write;
+    use std::path::Path;
     use tempdir::TempDir;
 
     #[test]
@@ -47,6 +80,89 @@ echo \"content of the second section\"
         assert_eq!(comment.argument.unwrap().as_str(), "abcdefghijk");
     }
 
+    #[test]
+    fn test_section_target_comment() {
+        let comment =
+            Specialcomment::from_line("#... tester target ~/.config/tester", "#", 3).unwrap();
+        assert_eq!(comment.comment_type, CommentType::TargetInfo);
+        assert_eq!(comment.section.as_str(), "tester");
+        assert_eq!(comment.argument.unwrap().as_str(), "~/.config/tester");
+    }
+
+    #[test]
+    fn test_condition() {
+        let facts = Facts {
+            hostname: String::from("laptop"),
+            os: String::from("linux"),
+            arch: String::from("x86_64"),
+            profile: Some(String::from("work")),
+        };
+        assert!(Condition::parse("hostname=laptop").unwrap().evaluate(&facts));
+        assert!(Condition::parse("hostname=laptop,os!=macos")
+            .unwrap()
+            .evaluate(&facts));
+        assert!(!Condition::parse("hostname=desktop")
+            .unwrap()
+            .evaluate(&facts));
+        assert!(Condition::parse("hostname").is_none());
+        assert!(facts.matches_profile(&Some(String::from("home,work"))));
+        assert!(!facts.matches_profile(&Some(String::from("home"))));
+        assert!(facts.matches_profile(&None));
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        std::env::set_var("IMOSID_TEST_VARIABLE", "value");
+        assert_eq!(
+            expand_variables("path=${IMOSID_TEST_VARIABLE}/foo ${IMOSID_UNSET_VARIABLE}"),
+            "path=value/foo ${IMOSID_UNSET_VARIABLE}"
+        );
+    }
+
+    #[test]
+    fn test_os_target() {
+        assert_eq!(resolve_os_target("~/.config/foo"), Some("~/.config/foo"));
+        let target = format!(
+            "{}=~/.config/foo other=~/Library/Application Support/foo",
+            std::env::consts::OS
+        );
+        assert_eq!(resolve_os_target(&target), Some("~/.config/foo"));
+        assert_eq!(
+            resolve_os_target("other=~/Library/Application Support/foo"),
+            None
+        );
+        let comment = Specialcomment::from_line(
+            "#... all target other=~/Library/Application Support/foo",
+            "#",
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            comment.argument.unwrap(),
+            "other=~/Library/Application Support/foo"
+        );
+    }
+
+    #[test]
+    fn test_permissions() {
+        assert_eq!(Permissions::parse("0644").unwrap().mode, 0o644);
+        assert_eq!(Permissions::parse("755").unwrap().mode, 0o755);
+        assert!(Permissions::parse("0985").is_none());
+        assert!(Permissions::parse("77777").is_none());
+        let owned = Permissions::parse("0600:root:wheel").unwrap();
+        assert_eq!(owned.owner.as_deref(), Some("root"));
+        assert_eq!(owned.group.as_deref(), Some("wheel"));
+        assert_eq!(owned.to_string(), "0600:root:wheel");
+    }
+
+    #[test]
+    fn test_expand_target_path() {
+        std::env::set_var("IMOSID_TEST_TARGET", "/tmp/imosid");
+        assert_eq!(expand_tilde("$IMOSID_TEST_TARGET/foo"), "/tmp/imosid/foo");
+        assert_eq!(expand_tilde("${IMOSID_TEST_TARGET}/foo"), "/tmp/imosid/foo");
+        assert_eq!(expand_tilde("~root/foo"), "/root/foo");
+    }
+
     #[test]
     fn test_section() {
         let sectiontarget = "#... test begin
@@ -86,4 +202,1812 @@ testing123
 
         assert_eq!(sectioncount, 2);
     }
+
+    #[test]
+    fn test_crlf_roundtrip() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let testpath = tmp_dir.path().join("testfile.sh");
+        let mut testfile = File::create(&testpath).unwrap();
+        testfile
+            .write_all(FILE_CONTENT.replace('\n', "\r\n").as_bytes())
+            .unwrap();
+
+        let testfile = DotFile::from_pathbuf(&testpath).unwrap();
+        assert_eq!(testfile.lineending, LineEnding::CrLf);
+        assert!(!testfile.modified);
+        let output = testfile.to_string();
+        assert_eq!(output.matches('\n').count(), output.matches("\r\n").count());
+    }
+
+    #[test]
+    fn test_bom_trailing_newline_roundtrip() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let testpath = tmp_dir.path().join("testfile.sh");
+        let content = format!("\u{feff}{}\necho trailing", FILE_CONTENT);
+        let mut testfile = File::create(&testpath).unwrap();
+        testfile.write_all(content.as_bytes()).unwrap();
+
+        let testfile = DotFile::from_pathbuf(&testpath).unwrap();
+        assert!(testfile.bom);
+        assert!(!testfile.trailingnewline);
+        let output = testfile.to_string();
+        assert!(output.starts_with('\u{feff}'));
+        assert!(output.ends_with("echo trailing"));
+    }
+
+    #[test]
+    fn test_management_state() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let managedpath = tmp_dir.path().join("managed.sh");
+        File::create(&managedpath)
+            .unwrap()
+            .write_all(FILE_CONTENT.as_bytes())
+            .unwrap();
+        let unmanagedpath = tmp_dir.path().join("unmanaged.sh");
+        File::create(&unmanagedpath)
+            .unwrap()
+            .write_all(b"#!/bin/bash\necho hello\n")
+            .unwrap();
+
+        let managed = DotFile::from_pathbuf(&managedpath).unwrap();
+        assert_eq!(managed.management_state(), ManagementState::CommentManaged);
+        assert!(managed.is_managed());
+        let unmanaged = DotFile::from_pathbuf(&unmanagedpath).unwrap();
+        assert_eq!(unmanaged.management_state(), ManagementState::Unmanaged);
+        assert!(!unmanaged.is_managed());
+        assert_eq!(
+            ManagementState::parse(ManagementState::MetafileManaged.as_str()),
+            Some(ManagementState::MetafileManaged)
+        );
+    }
+
+    #[test]
+    fn test_metafile_fields() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let metapath = tmp_dir.path().join("test.json.imosid.toml");
+        std::fs::write(
+            &metapath,
+            "hash = \"abc\"\nparent = \"test.json\"\npermissions = 644\nfuture = \"kept\"\n",
+        )
+        .unwrap();
+        let metafile = MetaFile::new(metapath.clone(), b"{}").unwrap();
+        assert_eq!(metafile.permissions.as_ref().unwrap().mode, 0o644);
+        assert!(metafile.output().contains("future = \"kept\""));
+
+        std::fs::write(&metapath, "parent = \"test.json\"\n").unwrap();
+        let error = MetaFile::new(metapath.clone(), b"{}").err().unwrap();
+        assert!(error.contains("hash"));
+        std::fs::write(&metapath, "hash = 1\nparent = \"test.json\"\n").unwrap();
+        assert!(MetaFile::new(metapath, b"{}").is_err());
+    }
+
+    #[test]
+    fn test_metafile_regions() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let content = "{\n  \"colors\": {\n    \"bg\": \"black\"\n  },\n  \"font\": \"mono\"\n}\n";
+        let metapath = tmp_dir.path().join("test.json.imosid.toml");
+        std::fs::write(
+            &metapath,
+            "hash = \"\"\nparent = \"test.json\"\nsyntaxversion = 2\n\n\
+             [[region]]\nname = \"colors\"\nhash = \"\"\nstart = \"colors\"\nend = \"}\"\n\n\
+             [[region]]\nname = \"font\"\nhash = \"\"\nlines = \"5-5\"\n",
+        )
+        .unwrap();
+        let mut metafile = MetaFile::new(metapath, content.as_bytes()).unwrap();
+        metafile.compile();
+        metafile.finalize();
+        assert!(!metafile.modified);
+        assert_eq!(
+            metafile.region_content("font").unwrap(),
+            "  \"font\": \"mono\"\n"
+        );
+
+        let colors = "  \"colors\": {\n    \"bg\": \"white\",\n    \"fg\": \"black\"\n  },\n";
+        assert!(metafile.replace_region("colors", colors));
+        assert_eq!(metafile.region_content("colors").unwrap(), colors);
+        // the line range moved down with the grown region
+        assert_eq!(
+            metafile.region_content("font").unwrap(),
+            "  \"font\": \"mono\"\n"
+        );
+        metafile.finalize();
+        assert!(!metafile.modified);
+        let output = metafile.output();
+        assert!(output.contains("[[region]]"));
+        assert!(output.contains("lines = \"6-6\""));
+    }
+
+    #[test]
+    fn test_dir_metafile() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let source = tmp_dir.path().join("snippets");
+        let target = tmp_dir.path().join("target");
+        std::fs::create_dir_all(source.join("lua")).unwrap();
+        std::fs::write(source.join("lua/init.lua"), "print(1)\n").unwrap();
+        std::fs::write(source.join("old.txt"), "old\n").unwrap();
+        std::fs::write(source.join("notes.bak"), "skip\n").unwrap();
+        std::fs::write(
+            source.join(DIR_METAFILE_NAME),
+            format!(
+                "hash = \"\"\ntarget = \"{}\"\nexclude = [\"*.bak\"]\n",
+                target.display()
+            ),
+        )
+        .unwrap();
+
+        let mut dirmetafile = DirMetaFile::new(&source).unwrap();
+        assert!(dirmetafile.modified);
+        assert!(dirmetafile.compile().unwrap());
+        dirmetafile.write_to_file();
+        let mut changes = Vec::new();
+        DirMetaFile::new(&source)
+            .unwrap()
+            .apply(&ApplyOptions::default(), &mut changes);
+        assert!(target.join("lua/init.lua").is_file());
+        assert!(!target.join("notes.bak").exists());
+
+        // files removed from the source disappear from the unmodified target
+        std::fs::remove_file(source.join("old.txt")).unwrap();
+        let mut dirmetafile = DirMetaFile::new(&source).unwrap();
+        dirmetafile.compile().unwrap();
+        dirmetafile.write_to_file();
+        DirMetaFile::new(&source)
+            .unwrap()
+            .apply(&ApplyOptions::default(), &mut changes);
+        assert!(!target.join("old.txt").exists());
+        assert!(!DirMetaFile::new(&target).unwrap().modified);
+    }
+
+    #[test]
+    fn test_hash_algorithm() {
+        assert_eq!(HashAlgorithm::from_hash(""), HashAlgorithm::DEFAULT);
+        assert_eq!(
+            HashAlgorithm::from_hash("E0B87AAA2E3C0A3755D20899A6FFE45B"),
+            HashAlgorithm::Sha256
+        );
+        let hash = HashAlgorithm::Blake3.digest(b"test");
+        assert!(hash.starts_with("blake3:"));
+        assert_eq!(HashAlgorithm::from_hash(&hash), HashAlgorithm::Blake3);
+    }
+
+    #[test]
+    fn test_hook_comment() {
+        let comment =
+            Specialcomment::from_line("#... all posthook pkill -USR1 dunst", "#", 1).unwrap();
+        assert_eq!(comment.comment_type, CommentType::PostHookInfo);
+        assert_eq!(comment.argument, Some(String::from("pkill -USR1 dunst")));
+        assert!(Specialcomment::from_line("#... section posthook true", "#", 1).is_none());
+    }
+
+    #[test]
+    fn test_section_matcher() {
+        let matcher = SectionMatcher::new(&["alias*", "path"], &["^key_[0-9]+$"]).unwrap();
+        assert!(matcher.matches("aliases"));
+        assert!(matcher.matches("path"));
+        assert!(matcher.matches("key_12"));
+        assert!(!matcher.matches("pathextra"));
+        assert!(!matcher.matches("key_a"));
+        assert_eq!(matcher.unmatched(&["aliases", "key_1"]), vec!["path"]);
+        assert!(SectionMatcher::new(&[], &[]).unwrap().matches("anything"));
+    }
+
+    #[test]
+    fn test_source_pin() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let spec = SourceSpec::parse(&format!("https://example.com/bashrc@{}", hash)).unwrap();
+        assert_eq!(
+            spec.location,
+            SourceLocation::Url(String::from("https://example.com/bashrc"))
+        );
+        assert_eq!(spec.pin, Some(Pin::Sha256(String::from(hash))));
+
+        let spec = SourceSpec::parse("git@github.com:user/dots.git//bash/bashrc@v1.2.0").unwrap();
+        assert_eq!(
+            spec.location,
+            SourceLocation::Git {
+                repo: String::from("git@github.com:user/dots.git"),
+                path: String::from("bash/bashrc"),
+            }
+        );
+        assert_eq!(spec.pin, Some(Pin::GitRef(String::from("v1.2.0"))));
+
+        let spec = SourceSpec::parse("git@github.com:user/dots.git//bashrc").unwrap();
+        assert_eq!(spec.pin, None);
+        // versions only exist for git sources
+        assert!(SourceSpec::parse("~/dots/bashrc@v1.2.0").is_none());
+    }
+
+    #[test]
+    fn test_reload_paths() {
+        let value: toml::Value = "command = 'swaymsg reload'\npaths = ['/etc/sway', '/tmp/swaylock']"
+            .parse()
+            .unwrap();
+        let reload = Reload::from_value("sway", &value).unwrap();
+        assert_eq!(reload.command, "swaymsg reload");
+        assert!(reload.matches("/etc/sway/config"));
+        assert!(reload.matches("/tmp/swaylock"));
+        assert!(!reload.matches("/etc/swayidle/config"));
+        // a reload without paths would never run
+        let value: toml::Value = "command = 'swaymsg reload'".parse().unwrap();
+        assert!(Reload::from_value("sway", &value).is_none());
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("als", "aliases").is_some());
+        assert!(fuzzy_score("ALI", "aliases").is_some());
+        assert!(fuzzy_score("xyz", "aliases").is_none());
+        assert!(fuzzy_score("sa", "aliases").is_none());
+        // consecutive matches at word starts win
+        assert!(fuzzy_score("path", "path_extra") > fuzzy_score("path", "prompt_that"));
+        assert!(fuzzy_score("env", "set_env") > fuzzy_score("env", "seven"));
+        // names are not patterns for an exact matcher
+        let matcher = SectionMatcher::exact(&[String::from("key*")]);
+        assert!(matcher.matches("key*"));
+        assert!(!matcher.matches("keys"));
+    }
+
+    #[test]
+    fn test_ignored_lines() {
+        let content = "a=1\nstamp=123 #...ignore\n#... first ignorebegin\ngenerated\n#... first ignoreend\nb=2\n";
+        assert_eq!(hashed_content("first", content), "a=1\nb=2\n");
+        // markers of other sections are content
+        assert_eq!(
+            hashed_content("second", "#... first ignorebegin\nx\n"),
+            "#... first ignorebegin\nx\n"
+        );
+
+        let mut section = Section::new(1, 3, String::from("first"), None, String::new());
+        section.push_line("a=1");
+        section.push_line("stamp=123 #...ignore");
+        section.finalize();
+        let mut other = Section::new(1, 3, String::from("first"), None, String::new());
+        other.push_line("a=1");
+        other.push_line("stamp=456 #...ignore");
+        other.finalize();
+        match (section, other) {
+            (Section::Named(_, a), Section::Named(_, b)) => assert_eq!(a.hash, b.hash),
+            _ => panic!("sections are named"),
+        }
+    }
+
+    #[test]
+    fn test_anchor_insertion() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        std::fs::write(&targetpath, "# owned by another tool\n[core]\neditor = vim\n").unwrap();
+        let sourcepath = tmp_dir.path().join("source.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... snippet begin\n#... snippet hash x\n#... snippet anchor ^\\[core\\]$\npager = less\n#... snippet end\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        source.compile();
+        source.write_to_file();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+
+        let mut changes = Vec::new();
+        assert!(matches!(
+            source.apply(&ApplyOptions::new(None), &mut changes),
+            ApplyResult::Changed
+        ));
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        let lines: Vec<&str> = target.lines().collect();
+        assert_eq!(lines[1], "[core]");
+        assert_eq!(lines[2], "#... snippet begin");
+        assert!(target.contains("pager = less\n#... snippet end\neditor = vim\n"));
+        // applying again finds the section in place
+        assert!(matches!(
+            source.apply(&ApplyOptions::new(None), &mut changes),
+            ApplyResult::Unchanged
+        ));
+    }
+
+    #[test]
+    fn test_sync_target() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let targetpath = tmp_dir.path().join("target.sh");
+        std::fs::write(
+            &sourcepath,
+            "#... first begin\n#... first hash x\necho source\n#... first end\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &targetpath,
+            format!(
+                "#... first begin\n#... first hash x\n#... first source {}\necho source\n#... first end\n",
+                sourcepath.display()
+            ),
+        )
+        .unwrap();
+        for path in [&sourcepath, &targetpath] {
+            let mut file = DotFile::from_pathbuf(path).unwrap();
+            file.compile();
+            file.write_to_file();
+        }
+        let edited = std::fs::read_to_string(&targetpath)
+            .unwrap()
+            .replace("echo source", "echo edited");
+        std::fs::write(&targetpath, edited).unwrap();
+
+        let synced = sync_target(&targetpath, false).unwrap();
+        assert_eq!(std::path::PathBuf::from(synced), sourcepath.canonicalize().unwrap());
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        assert!(!source.modified);
+        assert!(source.to_string().contains("echo edited"));
+        assert!(!DotFile::from_pathbuf(&targetpath).unwrap().modified);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let testfile = DotFile::from_str(FILE_CONTENT, "test.sh");
+        assert_eq!(testfile.filename, "test.sh");
+        assert_eq!(testfile.commentsign, "#");
+        assert_eq!(testfile.count_named_sections(), 2);
+        assert!(!testfile.modified);
+        assert_eq!(testfile.to_string(), FILE_CONTENT);
+
+        let content = FILE_CONTENT.replace("#...", "//...");
+        let testfile = DotFile::from_str_with_comment_sign(&content, "stdin", "//");
+        assert_eq!(testfile.count_named_sections(), 2);
+    }
+
+    #[test]
+    fn test_set_info() {
+        let mut testfile = DotFile::from_str(FILE_CONTENT, "test.sh");
+        assert!(testfile.set_section_info("firstsection", Some("~/.bashrc"), None));
+        assert!(!testfile.set_section_info("missing", Some("~/.bashrc"), None));
+        assert!(!testfile.set_file_info(None, None, Some("https://example.com/test.sh")));
+        assert!(testfile.set_file_info(Some("~/test.sh"), Permissions::parse("600"), None));
+
+        let output = testfile.to_string();
+        let parsed = DotFile::from_str(&output, "test.sh");
+        assert_eq!(parsed.targetfile, Some(String::from("~/test.sh")));
+        assert_eq!(parsed.permissions.as_ref().unwrap().to_string(), "0600");
+        assert_eq!(
+            parsed.get_section_target("firstsection"),
+            Some(String::from("~/.bashrc"))
+        );
+        assert!(!parsed.modified);
+    }
+
+    #[test]
+    fn test_all_comments_kept() {
+        let content = FILE_CONTENT.replacen(
+            "#!/bin/bash\n",
+            "#!/bin/bash\n#... all permissions 644\n#... all expand\n",
+            1,
+        );
+        let mut testfile = DotFile::from_str(&content, "test.sh");
+        testfile.compile();
+        let output = testfile.to_string();
+        assert!(output.contains("#... all permissions 0644\n"));
+        assert!(output.contains("#... all expand\n"));
+    }
+
+    #[test]
+    fn test_comment_sign_detection() {
+        // existing markers win over everything else
+        let content = format!("\n{}", FILE_CONTENT.replace("#...", "//..."));
+        assert_eq!(DotFile::from_str(&content, "config.sh").commentsign, "//");
+        // hashbangs after blank lines and comment headers are recognized
+        assert_eq!(
+            DotFile::from_str("\n#!/usr/bin/env node\nfoo();\n", "config").commentsign,
+            "//"
+        );
+        assert_eq!(
+            DotFile::from_str("; license\n; header\nkey=value\n", "config").commentsign,
+            ";"
+        );
+        // modelines name the filetype of files without an extension
+        assert_eq!(
+            DotFile::from_str("key=value\n# vim: set ft=dosini :\n", "config").commentsign,
+            ";"
+        );
+        assert_eq!(
+            DotFile::from_str("-- -*- mode: lua -*-\nx = 1\n", "config").commentsign,
+            "--"
+        );
+    }
+
+    #[test]
+    fn test_languages_extend() {
+        let mut languages = Languages::parse("[extensions]\nconf = \"#\"\n").unwrap();
+        languages.extend(
+            Languages::parse("[extensions]\nconf = \";\"\n[filetypes]\nconf = \"#\"\n").unwrap(),
+        );
+        assert_eq!(languages.extensions.get("conf").unwrap(), ";");
+        assert_eq!(languages.filetype_comment_sign("conf-unix"), Some("#"));
+        assert_eq!(languages.filetype_comment_sign("unknown"), None);
+        assert!(Languages::get().extensions.contains_key("sh"));
+
+        // strict json has no comments, jsonc and known jsonc configs use //
+        assert!(needs_metafile("package.json"));
+        assert!(!needs_metafile("/home/user/.config/Code/User/settings.json"));
+        assert_eq!(DotFile::from_str("{}\n", "config.jsonc").commentsign, "//");
+        assert_eq!(
+            DotFile::from_str("{}\n", "/home/user/.config/waybar/config").commentsign,
+            "//"
+        );
+    }
+
+    #[test]
+    fn test_indented_markers() {
+        let comment = Specialcomment::from_line("  #...\tfoo   target ~/a b  # note", "#", 1).unwrap();
+        assert_eq!(comment.indent, "  ");
+        assert_eq!(comment.section, "foo");
+        assert_eq!(comment.argument, Some(String::from("~/a b")));
+
+        let content = concat!(
+            "[main]\n    #... foo begin  the foo section\n\t#... foo hash x\n",
+            "    key = value\n    #... foo end\n"
+        );
+        let mut testfile = DotFile::from_str(content, "test.conf");
+        assert_eq!(testfile.count_named_sections(), 1);
+        testfile.compile();
+        let output = testfile.to_string();
+        assert!(output.contains("\n    #... foo begin\n    #... foo hash "));
+        assert!(output.contains("\n    #... foo end\n"));
+    }
+
+    #[test]
+    fn test_strict_parse() {
+        let content = "a\n#... foo beginn\n#... bar begin\nb\n#... bar end\n";
+        let problems = match DotFile::from_str_strict(content, "test.sh") {
+            Ok(_) => panic!("malformed comments were accepted"),
+            Err(problems) => problems,
+        };
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].line, 2);
+        assert_eq!(problems[0].message, "unknown keyword beginn");
+        assert_eq!(problems[1].line, 3);
+
+        // lenient parsing keeps the lines as content
+        let testfile = DotFile::from_str(content, "test.sh");
+        assert_eq!(testfile.count_named_sections(), 0);
+        assert_eq!(testfile.diagnostics.len(), 2);
+        assert!(DotFile::from_str_strict(FILE_CONTENT, "test.sh").is_ok());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let content = concat!(
+            "#... foo begin\n#... foo hash x\na\n#... foo end\n",
+            "  #... foo begin\n#... foo hash x\nb\n#... foo end\n#... bar begni\n"
+        );
+        let diagnostics = DotFile::diagnose(content, "test.sh");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "section foo is defined more than once, first on line 1"
+        );
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (5, 3));
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert_eq!((diagnostics[1].line, diagnostics[1].column), (9, 10));
+
+        colored::control::set_override(false);
+        let rendered = diagnostics[1].render("test.sh", content);
+        assert_eq!(
+            rendered,
+            concat!(
+                "warning: unknown keyword begni\n --> test.sh:9:10\n  |\n",
+                "9 | #... bar begni\n  |          ^^^^^\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_duplicate_sections() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = tmp_dir.path().join("source.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n{}{}",
+                targetpath.display(),
+                "#... foo begin\n#... foo hash x\na\n#... foo end\n",
+                "#... foo begin\n#... foo hash x\nb\n#... foo end\n"
+            ),
+        )
+        .unwrap();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        assert_eq!(source.duplicate_sections, vec![String::from("foo")]);
+        assert_eq!(
+            source.diagnostics[0].message,
+            "section foo is defined more than once, first on line 2"
+        );
+        assert_eq!(source.diagnostics[0].line, 6);
+
+        let mut changes = Vec::new();
+        assert!(matches!(
+            source.apply(&ApplyOptions::new(None), &mut changes),
+            ApplyResult::Error
+        ));
+        assert!(!targetpath.exists());
+        let mut options = ApplyOptions::new(None);
+        options.force = true;
+        assert!(!matches!(
+            source.apply(&options, &mut changes),
+            ApplyResult::Error
+        ));
+    }
+
+    #[test]
+    fn test_overlapping_sections() {
+        let overlapping = concat!(
+            "#... a begin\n#... a hash x\n#... b begin\n#... b hash x\n",
+            "#... c begin\n#... c hash x\none\n#... a end\n#... b end\ntwo\n#... c end\n"
+        );
+        let content = format!("{}#... d begin\n#... d hash x\nthree\n#... d end\n", overlapping);
+        let testfile = DotFile::from_str(&content, "test.sh");
+        assert_eq!(testfile.count_named_sections(), 1);
+        assert_eq!(testfile.diagnostics.len(), 1);
+        assert_eq!(
+            testfile.diagnostics[0].message,
+            "sections a, b, c overlap on lines 1-11"
+        );
+        // the overlapping sections are kept as content
+        assert!(testfile.to_string().starts_with(overlapping));
+    }
+
+    #[test]
+    fn test_repair() {
+        let content = concat!(
+            "#... a begin\none\n#... b begin\n#... b hash x\ntwo\n#... b end\n",
+            "#... c hash x\nthree\n#... d end\n#... e begin\nfour\n"
+        );
+        let repairs = find_repairs(content, "#");
+        let descriptions: Vec<&str> = repairs
+            .iter()
+            .map(|repair| repair.description.as_str())
+            .collect();
+        assert_eq!(
+            descriptions,
+            vec![
+                "add missing hash to section a",
+                "close section a at line 3",
+                "remove stray hash of section c",
+                "wrap lines 8-8 into section d",
+                "add missing hash to section e",
+                "close section e at the end of the file",
+            ]
+        );
+
+        let repaired = apply_repairs(content, &repairs);
+        assert!(repaired.starts_with("#... a begin\n#... a hash blake3:"));
+        assert!(repaired.contains("#... b end\n#... d begin\n#... d hash "));
+        assert!(repaired.ends_with("four\n#... e end\n"));
+        let testfile = DotFile::from_str(&repaired, "test.sh");
+        assert!(testfile.diagnostics.is_empty());
+        assert_eq!(testfile.count_named_sections(), 4);
+        // repaired sections keep their content and are not modified, b was modified before
+        for section in &testfile.sections {
+            if let Section::Named(_, named_data) = section {
+                assert_eq!(named_data.name == "b", named_data.hash != named_data.targethash);
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let content = "#... foo start\n#... foo hash x\na\n#... foo stop\n";
+        let mut testfile = DotFile::from_str(content, "test.sh");
+        assert_eq!(testfile.syntaxversion, 1);
+        assert!(testfile.migrate());
+        let output = testfile.to_string();
+        assert!(output.starts_with("#... all syntax 2\n#... foo begin\n"));
+        assert!(output.ends_with("a\n#... foo end\n"));
+        let mut migrated = DotFile::from_str(&output, "test.sh");
+        assert_eq!(migrated.syntaxversion, 2);
+        assert!(!migrated.migrate());
+
+        // files written by newer versions are refused
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let newer = tmp_dir.path().join("newer.sh");
+        std::fs::write(&newer, "#... all syntax 3\n#... foo begin\n").unwrap();
+        assert!(DotFile::from_pathbuf(&newer).is_err());
+        let metapath = tmp_dir.path().join("test.json.imosid.toml");
+        std::fs::write(
+            &metapath,
+            "hash = \"\"\nparent = \"test.json\"\nsyntaxversion = 3\n",
+        )
+        .unwrap();
+        assert!(MetaFile::new(metapath.clone(), b"{}").is_err());
+        std::fs::write(&metapath, "hash = \"\"\nparent = \"test.json\"\n").unwrap();
+        let mut metafile = MetaFile::new(metapath, b"{}").unwrap();
+        assert!(metafile.migrate());
+        assert!(metafile.output().contains("syntaxversion = 2"));
+    }
+
+    #[test]
+    fn test_imosid_version() {
+        let content = concat!(
+            "#... all imosidversion 0.0.1\n",
+            "#... foo begin\n#... foo hash x\na\n#... foo end\n"
+        );
+        let mut testfile = DotFile::from_str(content, "test.sh");
+        assert!(!testfile.written_by_newer());
+        assert!(testfile.compile());
+        let output = testfile.to_string();
+        assert!(output.starts_with(&format!("#... all imosidversion {}\n", imosid_version())));
+
+        let newer = content.replace("0.0.1", "99.0.0");
+        let mut testfile = DotFile::from_str(&newer, "test.sh");
+        assert!(testfile.written_by_newer());
+        assert!(!testfile.migrate());
+        testfile.compile();
+        assert!(testfile.to_string().starts_with("#... all imosidversion 99.0.0\n"));
+        let options = ApplyOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            testfile.apply(&options, &mut Vec::new()),
+            ApplyResult::Error
+        ));
+    }
+
+    #[test]
+    fn test_section_diffs() {
+        let content = "#... foo begin\n#... foo hash x\na\n#... foo end\n";
+        let mut testfile = DotFile::from_str(content, "test.sh");
+        testfile.compile();
+        assert!(testfile.section_diffs().is_empty());
+
+        let changed = testfile.to_string().replace("\na\n", "\nb\n");
+        colored::control::set_override(false);
+        let diffs = DotFile::from_str(&changed, "test.sh").section_diffs();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].0, "foo");
+        assert!(diffs[0].1.as_ref().unwrap().contains("-a\n+b\n"));
+    }
+
+    #[test]
+    fn test_log_changes() {
+        let record = |hashes: &[(&str, &str)], time| ApplyRecord {
+            action: Action::Apply,
+            source: String::from("/dots/bashrc"),
+            target: String::from("/home/user/.bashrc"),
+            hashes: hashes
+                .iter()
+                .map(|(name, hash)| (name.to_string(), hash.to_string()))
+                .collect(),
+            time,
+        };
+        let first = record(&[("aliases", "a"), ("prompt", "p")], 0);
+        let second = record(&[("aliases", "b"), ("path", "x")], 1685620800);
+        assert_eq!(first.changes(None), vec!["added aliases", "added prompt"]);
+        assert_eq!(
+            second.changes(Some(&first)),
+            vec!["changed aliases", "added path", "removed prompt"]
+        );
+        assert!(first.changes(Some(&first)).is_empty());
+
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(second.time), "2023-06-01 12:00:00");
+        assert_eq!(format_time(951782400 + 3661), "2000-02-29 01:01:01");
+    }
+
+    #[test]
+    fn test_rollback() {
+        let content = "#... foo begin\n#... foo hash x\nold\n#... foo end\n";
+        let mut testfile = DotFile::from_str(content, "test.sh");
+        testfile.compile();
+        let oldhashes: Vec<(String, String)> = testfile
+            .sections
+            .iter()
+            .filter_map(|section| match section {
+                Section::Named(_, named_data) => {
+                    Some((named_data.name.clone(), named_data.targethash.clone()))
+                }
+                Section::Anonymous(_) => None,
+            })
+            .collect();
+        let state_home = TempDir::new("imosidtest").unwrap();
+        set_test_home(state_home.path());
+        store_snapshot(&oldhashes[0].1, "old\n");
+
+        let newcontent = testfile.to_string().replace("old", "new");
+        let mut newfile = DotFile::from_str(&newcontent, "test.sh");
+        newfile.compile();
+        let modified = newfile.to_string().replace("new", "local");
+        assert!(newfile.rollback(&oldhashes, false));
+        assert!(!DotFile::from_str(&newfile.to_string(), "test.sh").modified);
+        assert_eq!(newfile.to_string(), testfile.to_string());
+        assert!(!newfile.rollback(&oldhashes, false));
+
+        let mut modifiedfile = DotFile::from_str(&modified, "test.sh");
+        assert!(!modifiedfile.rollback(&oldhashes, false));
+        assert!(modifiedfile.rollback(&oldhashes, true));
+        assert_eq!(modifiedfile.to_string(), testfile.to_string());
+
+        assert_eq!(parse_time("1970-01-01"), Some(0));
+        assert_eq!(parse_time("2000-02-29 01:01:01"), Some(951782400 + 3661));
+        assert_eq!(parse_time("2023-06-01 12:00"), Some(1685620800));
+        assert_eq!(parse_time("2023-13-01"), None);
+    }
+
+    #[test]
+    fn test_gc_retention() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("100M"), Some(100 << 20));
+        assert_eq!(parse_size("big"), None);
+        let value: toml::Value = "max_age = 0\nmax_count = 2\nmax_size = \"1K\"".parse().unwrap();
+        let retention = Retention::from_value(&value).unwrap();
+        assert_eq!(retention.max_age, None);
+        assert_eq!(retention.max_count, Some(2));
+        assert_eq!(retention.max_size, Some(1024));
+
+        let now = std::time::SystemTime::now();
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        let entries = |sizes: &[u64]| {
+            sizes
+                .iter()
+                .enumerate()
+                .map(|(index, size)| Entry {
+                    path: std::path::PathBuf::from(index.to_string()),
+                    modified: now - day * (index as u32 * 50),
+                    size: *size,
+                })
+                .collect::<Vec<Entry>>()
+        };
+        let names = |expired: Vec<Entry>| {
+            expired
+                .iter()
+                .map(|entry| entry.path.to_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(retention.expired(entries(&[1, 1, 1]), now)), vec!["2"]);
+        assert_eq!(names(retention.expired(entries(&[1000, 100, 1]), now)), vec!["1", "2"]);
+        let byage = Retention::default().expired(entries(&[1, 1, 1]), now);
+        assert_eq!(names(byage), vec!["2"]);
+    }
+
+    #[test]
+    fn test_lockfile() {
+        let dir = TempDir::new("imosid-lock").unwrap();
+        let mut lockfile = LockFile::load(dir.path().join("imosid.lock"));
+        assert!(lockfile.sources.is_empty());
+        let locked = LockedSource {
+            source: String::from("git@github.com:user/dots.git//bashrc@main"),
+            url: String::from("git@github.com:user/dots.git"),
+            commit: Some(String::from("0123456789abcdef0123456789abcdef01234567")),
+            sha256: "a".repeat(64),
+        };
+        lockfile.insert(locked.clone());
+        assert!(lockfile.changed);
+        assert!(lockfile.save());
+
+        let mut lockfile = LockFile::load(dir.path().join("imosid.lock"));
+        assert_eq!(lockfile.sources, vec![locked.clone()]);
+        lockfile.insert(locked);
+        assert!(!lockfile.changed);
+        // sources missing from the lock file are not fetched
+        lockfile.locked = true;
+        assert!(lockfile.resolve("https://example.com/bashrc", true).is_none());
+    }
+
+    #[test]
+    fn test_unwritable_target() {
+        let dir = TempDir::new("imosid-root").unwrap();
+        assert!(!needs_root(&dir.path().join("new/test.sh")));
+        // sudo is only used for targets which need it
+        let path = dir.path().join("test.sh");
+        write_file(&path, b"a\n", RootPolicy::Sudo).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\n");
+        // failing writes are reported instead of panicking
+        let mut testfile = DotFile::from_str("#... foo begin\na\n#... foo end\n", "test.sh");
+        testfile.filename = String::from("/proc/imosid/test.sh");
+        assert!(!testfile.write_to_file());
+    }
+
+    #[test]
+    fn test_readonly_target() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let source = |content: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n{}\n#... foo end\n",
+                    targetpath.display(),
+                    content
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+            DotFile::from_pathbuf(&sourcepath).unwrap()
+        };
+        let mut changes = Vec::new();
+        let mut options = ApplyOptions::new(None);
+        assert!(matches!(
+            source("a").apply(&options, &mut changes),
+            ApplyResult::Changed
+        ));
+        Permissions::parse("0444").unwrap().apply(&targetpath).unwrap();
+        assert!(is_readonly(&targetpath));
+
+        let updated = source("b");
+        assert!(matches!(
+            updated.apply(&options, &mut changes),
+            ApplyResult::Error
+        ));
+        options.readonly = ReadonlyPolicy::Skip;
+        assert!(matches!(
+            updated.apply(&options, &mut changes),
+            ApplyResult::Unchanged
+        ));
+        assert!(!std::fs::read_to_string(&targetpath).unwrap().contains("\nb\n"));
+        options.readonly = ReadonlyPolicy::Chmod;
+        assert!(matches!(
+            updated.apply(&options, &mut changes),
+            ApplyResult::Changed
+        ));
+        assert!(std::fs::read_to_string(&targetpath).unwrap().contains("\nb\n"));
+        assert!(is_readonly(&targetpath));
+    }
+
+    #[test]
+    fn test_deploy_script() {
+        assert_eq!(shell_quote("user@host:22"), "user@host:22");
+        assert_eq!(shell_quote("my dots"), "'my dots'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+
+        let deployment = Deployment {
+            remote_dir: String::from("my dots"),
+            apply_args: vec![String::from("--profile"), String::from("work laptop")],
+            ..Default::default()
+        };
+        let script = deployment.remote_script();
+        assert!(script.contains("dir='my dots'\n"));
+        assert!(script.contains("[ ! -e \"$dir/.imosid-deploy\" ]"));
+        assert!(check_remote_dir(".cache/imosid/deploy").is_ok());
+        assert!(check_remote_dir("dots/").is_ok());
+        assert!(check_remote_dir(".config/../.ssh").is_err());
+        assert!(check_remote_dir("/etc").is_err());
+        assert!(check_remote_dir("~/dots").is_err());
+        assert!(check_remote_dir(".").is_err());
+        assert!(check_remote_dir("").is_err());
+        assert!(script.ends_with("\nimosid apply \"$dir\" --profile 'work laptop'"));
+    }
+
+    #[test]
+    fn test_serve_etag() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let root = tmp_dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("sub dir")).unwrap();
+        std::fs::write(root.join("sub dir/bashrc"), "echo hi\n").unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/config"), "").unwrap();
+
+        let response = respond(&root, "GET /sub%20dir/bashrc HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"echo hi\n");
+        let tag = etag(b"echo hi\n");
+        assert_eq!(response.etag, Some(tag.clone()));
+        let head = format!("GET /sub%20dir/bashrc HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", tag);
+        let response = respond(&root, &head);
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+
+        assert_eq!(respond(&root, "GET /../etc/passwd HTTP/1.1\r\n\r\n").status, 404);
+        assert_eq!(respond(&root, "GET /.git/config HTTP/1.1\r\n\r\n").status, 404);
+        assert_eq!(respond(&root, "GET /sub%20dir HTTP/1.1\r\n\r\n").status, 404);
+        assert_eq!(respond(&root, "PUT /sub%20dir/bashrc HTTP/1.1\r\n\r\n").status, 405);
+    }
+
+    #[test]
+    fn test_systemd_units() {
+        assert_eq!(parse_interval("1h"), Some(String::from("1h")));
+        assert_eq!(parse_interval("15m"), Some(String::from("15min")));
+        assert_eq!(parse_interval("30"), None);
+        assert_eq!(parse_interval("0h"), None);
+        assert_eq!(parse_interval("1w"), None);
+
+        let service = service_unit(
+            Path::new("/usr/bin/imosid"),
+            Path::new("/home/user/my dots"),
+            &[String::from("--profile"), String::from("100%")],
+        );
+        assert!(service.contains(
+            "\nExecStart=\"/usr/bin/imosid\" apply \"/home/user/my dots\" --changed-only \
+             \"--profile\" \"100%%\"\n"
+        ));
+        assert!(timer_unit("1h").contains("\nOnUnitActiveSec=1h\n"));
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state_home = TempDir::new("imosidtest").unwrap();
+        set_test_home(state_home.path());
+        let mut state = StateDb::load();
+        assert!(state.records.is_empty());
+        state.records.push(ApplyRecord {
+            action: Action::Rollback,
+            source: String::from("/dots/bashrc"),
+            target: String::from("/home/user/.bashrc"),
+            hashes: vec![(String::from("aliases"), String::from("abc"))],
+            time: 42,
+        });
+        assert!(state.save());
+
+        let state = StateDb::load();
+        assert_eq!(state.records.len(), 1);
+        let record = &state.records[0];
+        assert_eq!(record.action, Action::Rollback);
+        assert_eq!(record.source, "/dots/bashrc");
+        assert_eq!(record.target, "/home/user/.bashrc");
+        assert_eq!(record.hashes, vec![(String::from("aliases"), String::from("abc"))]);
+        assert_eq!(record.time, 42);
+    }
+
+    #[test]
+    fn test_hashcache_roundtrip() {
+        let cache_home = TempDir::new("imosidtest").unwrap();
+        set_test_home(cache_home.path());
+        let targetpath = cache_home.path().join("target.sh");
+        let sourcepath = cache_home.path().join("source.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... foo begin\n#... foo hash x\na\n#... foo end\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        let options = ApplyOptions::new(None);
+        source.apply(&options, &mut Vec::new());
+
+        let mut cache = HashCache::load(true);
+        assert!(cache.get(&sourcepath).is_none());
+        cache.set_applied(&source, &options);
+        cache.save();
+
+        let cache = HashCache::load(true);
+        let entry = cache.get(&sourcepath).unwrap();
+        assert_eq!(entry.modified, source.modified);
+        assert_eq!(entry.state, source.management_state());
+        assert!(cache.is_applied(&sourcepath, &options));
+        // touching the target invalidates the entry
+        std::fs::write(&targetpath, "changed\n").unwrap();
+        assert!(!cache.is_applied(&sourcepath, &options));
+        assert!(!HashCache::load(false).is_applied(&sourcepath, &options));
+        // --changed-only only looks at the source
+        assert!(cache.is_source_applied(&sourcepath, &options));
+        assert!(!cache.is_source_applied(&sourcepath, &ApplyOptions::new(Some("work".into()))));
+        std::fs::write(&sourcepath, "changed\n").unwrap();
+        assert!(!cache.is_source_applied(&sourcepath, &options));
+        assert!(build_app()
+            .try_get_matches_from(["imosid", "apply", "dots", "--changed-only", "--no-cache"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(dots.join("shell")).unwrap();
+        std::fs::write(
+            dots.join("shell/bashrc"),
+            "#... all target ~/.bashrc\n#... foo begin\n#... foo hash x\na\n#... foo end\n",
+        )
+        .unwrap();
+        std::fs::write(dots.join("notes.txt"), "not managed\n").unwrap();
+        let archive = tmp_dir.path().join("dots.tar.gz");
+        assert_eq!(
+            export_archive(&dots, &WalkOptions::default(), &archive).unwrap(),
+            1
+        );
+
+        let mut manifest = String::new();
+        let mut entries = Vec::new();
+        let mut tarball = tar::Archive::new(flate2::read::GzDecoder::new(
+            File::open(&archive).unwrap(),
+        ));
+        for entry in tarball.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().display().to_string();
+            if path == EXPORT_MANIFEST_NAME {
+                std::io::Read::read_to_string(&mut entry, &mut manifest).unwrap();
+            }
+            entries.push(path);
+        }
+        assert!(entries.contains(&String::from("shell/bashrc")));
+        assert!(!entries.contains(&String::from("notes.txt")));
+        let manifest = manifest.parse::<toml::Value>().unwrap();
+        let files = manifest.get("file").unwrap().as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].get("path").unwrap().as_str(), Some("shell/bashrc"));
+        assert_eq!(files[0].get("target").unwrap().as_str(), Some("~/.bashrc"));
+
+        let into = tmp_dir.path().join("imported");
+        import_archive(&archive, &into).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(into.join("shell/bashrc")).unwrap(),
+            std::fs::read_to_string(dots.join("shell/bashrc")).unwrap()
+        );
+        assert!(!into.join(EXPORT_MANIFEST_NAME).exists());
+    }
+
+    #[test]
+    fn test_merge_after_cached_skip() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = dots.join("source.sh");
+        let write_source = |content: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n{}#... foo end\n",
+                    targetpath.display(),
+                    content
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+        };
+        let mut options = ApplyOptions::new(None);
+        write_source("one\ntwo\nthree\n");
+        assert!(apply_config_dir(&dots, &WalkOptions::default(), &options));
+
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        std::fs::write(&targetpath, target.replace("one", "ONE")).unwrap();
+        write_source("one\ntwo\nTHREE\n");
+        // the modified section is skipped, which must not keep a merge from running later
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+        assert!(!std::fs::read_to_string(&targetpath).unwrap().contains("THREE"));
+        options.merge = true;
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        assert!(target.contains("ONE\ntwo\nTHREE\n"));
+    }
+
+    #[test]
+    fn test_rollback_rendered_section() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = dots.join("source.sh");
+        let apply_source = |content: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n#... foo template\n{}#... foo end\n",
+                    targetpath.display(),
+                    content
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+            apply_config_dir(&dots, &WalkOptions::default(), &ApplyOptions::new(None));
+        };
+        apply_source("value {{ 1 + 1 }}\n");
+        apply_source("value {{ 2 + 2 }}\n");
+        assert!(std::fs::read_to_string(&targetpath).unwrap().contains("value 4\n"));
+
+        let state = StateDb::load();
+        let record = state
+            .find_rollback(targetpath.to_str().unwrap(), None)
+            .unwrap();
+        let mut target = DotFile::from_pathbuf(&targetpath).unwrap();
+        assert!(target.rollback(&record.hashes, false));
+        let output = target.to_string();
+        assert!(output.contains("value 2\n"));
+        assert!(!output.contains("{{"));
+    }
+
+    #[test]
+    fn test_record_written_sections() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = dots.join("source.sh");
+        let write_source = |foo: &str, bar: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n{}{}\n#... foo end\n{}{}\n#... bar end\n",
+                    targetpath.display(),
+                    "#... foo begin\n#... foo hash x\n",
+                    foo,
+                    "#... bar begin\n#... bar hash x\n",
+                    bar
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+        };
+        let options = ApplyOptions::new(None);
+        write_source("foo one", "bar one");
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        std::fs::write(&targetpath, target.replace("bar one", "bar local")).unwrap();
+        write_source("foo two", "bar two");
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+        // nothing changes, so nothing is recorded
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+
+        let state = StateDb::load();
+        let names = |record: &ApplyRecord| -> Vec<String> {
+            record.hashes.iter().map(|(name, _)| name.clone()).collect()
+        };
+        assert_eq!(state.records.len(), 2);
+        assert_eq!(names(&state.records[0]), vec!["bar", "foo"]);
+        // bar was skipped as modified in the target
+        assert_eq!(names(&state.records[1]), vec!["foo"]);
+    }
+
+    #[test]
+    fn test_gc_keeps_merge_bases() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = dots.join("source.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... foo begin\n#... foo hash x\nbase\n#... foo end\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        source.compile();
+        source.write_to_file();
+        apply_config_dir(&dots, &WalkOptions::default(), &ApplyOptions::new(None));
+        store_snapshot("unused", "unused\n");
+        std::fs::create_dir_all(tmp_dir.path().join(".config/imosid")).unwrap();
+        std::fs::write(
+            tmp_dir.path().join(".config/imosid/config.toml"),
+            format!("source = \"{}\"\n", dots.display()),
+        )
+        .unwrap();
+        // the history is gone, the target still needs its snapshot as merge base
+        std::fs::remove_file(tmp_dir.path().join(".local/state/imosid/state.toml")).unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(200 * 86400);
+        let snapshot_dir = tmp_dir.path().join(".local/state/imosid/snapshots");
+        for entry in std::fs::read_dir(snapshot_dir).unwrap() {
+            let file = File::options().write(true).open(entry.unwrap().path()).unwrap();
+            file.set_modified(old).unwrap();
+        }
+
+        let garbage = find_garbage(&Retention::default());
+        let snapshots: Vec<String> = garbage
+            .iter()
+            .find(|(kind, _)| *kind == "snapshots")
+            .unwrap()
+            .1
+            .iter()
+            .map(|entry| entry.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(snapshots, vec!["unused"]);
+        assert!(garbage.iter().all(|(kind, _)| *kind != "backups"));
+    }
+
+    #[test]
+    fn test_create_target_error() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        // a directory in place of the target makes writing it fail
+        let targetpath = tmp_dir.path().join("target.sh");
+        std::fs::create_dir_all(targetpath.join("keep")).unwrap();
+        let sourcepath = tmp_dir.path().join("source.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... foo begin\na\n#... foo end\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        source.compile();
+        source.write_to_file();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+
+        let mut changes = Vec::new();
+        assert!(matches!(
+            source.apply(&ApplyOptions::new(None), &mut changes),
+            ApplyResult::Error
+        ));
+        assert!(changes.is_empty());
+        assert!(targetpath.join("keep").is_dir());
+        let leftover = std::fs::read_dir(tmp_dir.path())
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name().to_string_lossy().ends_with(".imosid-tmp"));
+        assert!(!leftover);
+    }
+
+    #[test]
+    fn test_template_error() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let source = |content: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n#... foo template\n{}#... foo end\n",
+                    targetpath.display(),
+                    content
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+            DotFile::from_pathbuf(&sourcepath).unwrap()
+        };
+        let broken = source("value {{ 1 + }}\n");
+        assert!(broken.get_section("foo").unwrap().render().is_none());
+        let mut changes = Vec::new();
+        let options = ApplyOptions::new(None);
+        assert!(matches!(
+            broken.apply(&options, &mut changes),
+            ApplyResult::Error
+        ));
+        assert!(!targetpath.exists());
+
+        assert!(matches!(
+            source("value {{ 1 + 1 }}\n").apply(&options, &mut changes),
+            ApplyResult::Changed
+        ));
+        // the raw template never replaces the rendered section
+        assert!(matches!(
+            source("value {{ 2 + }}\n").apply(&options, &mut changes),
+            ApplyResult::Error
+        ));
+        let output = std::fs::read_to_string(&targetpath).unwrap();
+        assert!(output.contains("value 2\n"));
+        assert!(!output.contains("{{"));
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        for name in ["kept", "deleted", "retargeted"] {
+            let sourcepath = dots.join(format!("{}.sh", name));
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n{}\n#... foo end\n",
+                    tmp_dir.path().join(name).display(),
+                    name
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+        }
+        let options = ApplyOptions::new(None);
+        assert!(apply_config_dir(&dots, &WalkOptions::default(), &options));
+
+        std::fs::remove_file(dots.join("deleted.sh")).unwrap();
+        let retargeted = dots.join("retargeted.sh");
+        let content = std::fs::read_to_string(&retargeted).unwrap();
+        std::fs::write(&retargeted, content.replace("/retargeted\n", "/moved\n")).unwrap();
+
+        let state = StateDb::load();
+        let orphans: Vec<&str> = state
+            .find_orphans(&options.facts)
+            .iter()
+            .map(|record| record.target.as_str())
+            .collect();
+        let expected = [
+            tmp_dir.path().join("deleted").display().to_string(),
+            tmp_dir.path().join("retargeted").display().to_string(),
+        ];
+        assert_eq!(orphans, expected);
+
+        // targets which are already gone are not orphans
+        std::fs::remove_file(tmp_dir.path().join("deleted")).unwrap();
+        assert_eq!(StateDb::load().find_orphans(&options.facts).len(), 1);
+    }
+
+    #[test]
+    fn test_import_stow() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let stowdir = tmp_dir.path().join("stow");
+        let home = tmp_dir.path().join("home");
+        std::fs::create_dir_all(stowdir.join("bash")).unwrap();
+        std::fs::create_dir_all(stowdir.join("nvim/dot-config/nvim")).unwrap();
+        std::fs::write(stowdir.join("bash/dot-bashrc"), "alias ll='ls -l'\n").unwrap();
+        std::fs::write(stowdir.join("nvim/dot-config/nvim/init.lua"), "vim.o.number = true\n")
+            .unwrap();
+
+        assert_eq!(import_stow(&stowdir, Some(&home)).unwrap(), 2);
+        let bashrc = DotFile::new(stowdir.join("bash/dot-bashrc").to_str().unwrap()).unwrap();
+        assert!(bashrc.is_managed());
+        assert!(!bashrc.modified);
+        let expected = home.join(".bashrc").display().to_string();
+        assert_eq!(bashrc.targetfile, Some(expected));
+        let init = DotFile::new(stowdir.join("nvim/dot-config/nvim/init.lua").to_str().unwrap())
+            .unwrap();
+        let expected = home.join(".config/nvim/init.lua").display().to_string();
+        assert_eq!(init.targetfile, Some(expected));
+
+        // managed files are not imported twice
+        assert_eq!(import_stow(&stowdir, Some(&home)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_import_chezmoi() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let sourcedir = tmp_dir.path().join("chezmoi");
+        let home = tmp_dir.path().join("home");
+        std::fs::create_dir_all(sourcedir.join("dot_config/git")).unwrap();
+        std::fs::write(sourcedir.join("dot_config/git/config"), "[user]\n").unwrap();
+        std::fs::write(sourcedir.join("private_executable_dot_profile"), "export A=1\n").unwrap();
+        std::fs::write(sourcedir.join("encrypted_dot_netrc"), "secret\n").unwrap();
+        std::fs::write(sourcedir.join(".chezmoiignore"), "README.md\n").unwrap();
+
+        assert_eq!(import_chezmoi(&sourcedir, Some(&home)).unwrap(), 2);
+        let config = sourcedir.join("dot_config/git/config");
+        let config = DotFile::new(config.to_str().unwrap()).unwrap();
+        let expected = home.join(".config/git/config").display().to_string();
+        assert_eq!(config.targetfile, Some(expected));
+        assert!(config.permissions.is_none());
+
+        let profile = sourcedir.join("private_executable_dot_profile");
+        let profile = DotFile::new(profile.to_str().unwrap()).unwrap();
+        assert_eq!(profile.targetfile, Some(home.join(".profile").display().to_string()));
+        assert_eq!(profile.permissions.unwrap().mode, 0o700);
+
+        // unsupported attributes and chezmoi configuration are left alone
+        let netrc = std::fs::read_to_string(sourcedir.join("encrypted_dot_netrc")).unwrap();
+        assert_eq!(netrc, "secret\n");
+        let ignore = std::fs::read_to_string(sourcedir.join(".chezmoiignore")).unwrap();
+        assert_eq!(ignore, "README.md\n");
+    }
+
+    #[test]
+    fn test_import_dotbot() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let dots = tmp_dir.path().join("dots");
+        let home = tmp_dir.path().join("home");
+        std::fs::create_dir_all(dots.join("zsh")).unwrap();
+        std::fs::create_dir_all(dots.join("kitty")).unwrap();
+        std::fs::write(dots.join("vimrc"), "set number\n").unwrap();
+        std::fs::write(dots.join("zsh/zshrc"), "bindkey -v\n").unwrap();
+        std::fs::write(dots.join("kitty/kitty.conf"), "font_size 12\n").unwrap();
+        std::fs::write(dots.join("gitconfig"), "[user]\n").unwrap();
+        let home = home.display();
+        std::fs::write(
+            dots.join("install.conf.yaml"),
+            format!(
+                "- clean: ['~']\n- link:\n    {h}/.vimrc:\n    {h}/.zshrc: zsh/zshrc\n    \
+                 {h}/.config/kitty:\n      path: kitty\n    {h}/.gitconfig:\n      glob: true\n",
+                h = home
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(import_dotbot(&dots.join("install.conf.yaml"), false).unwrap(), 3);
+        let target = |name: &str| {
+            let path = dots.join(name);
+            DotFile::new(path.to_str().unwrap()).unwrap().targetfile
+        };
+        assert_eq!(target("vimrc"), Some(format!("{}/.vimrc", home)));
+        assert_eq!(target("zsh/zshrc"), Some(format!("{}/.zshrc", home)));
+        assert_eq!(
+            target("kitty/kitty.conf"),
+            Some(format!("{}/.config/kitty/kitty.conf", home))
+        );
+        // globs are skipped
+        assert_eq!(std::fs::read_to_string(dots.join("gitconfig")).unwrap(), "[user]\n");
+    }
+
+    #[test]
+    fn test_git_clean_and_commit() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let repo = tmp_dir.path().canonicalize().unwrap();
+        let filepath = repo.join("bashrc");
+        std::fs::write(&filepath, "alias ll='ls -l'\n").unwrap();
+        // outside of a repository there is nothing to check or commit
+        assert!(check_clean(&filepath, true));
+        assert!(!commit_file(&filepath, "imosid: compile bashrc"));
+
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+        git(&["config", "user.name", "imosid"]);
+        git(&["config", "user.email", "imosid@localhost"]);
+        assert_eq!(repo_root(&filepath), Some(repo.clone()));
+        assert!(check_clean(&filepath, false));
+        assert!(!check_clean(&filepath, true));
+
+        assert!(commit_file(&filepath, "imosid: compile bashrc"));
+        assert!(check_clean(&filepath, true));
+        // unchanged files are not committed again
+        assert!(!commit_file(&filepath, "imosid: compile bashrc"));
+    }
+
+    #[test]
+    fn test_conflict_markers() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let source = |content: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... all target {}\n#... foo begin\n#... foo hash x\n{}#... foo end\n",
+                    targetpath.display(),
+                    content
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+            DotFile::from_pathbuf(&sourcepath).unwrap()
+        };
+        let mut changes = Vec::new();
+        let mut options = ApplyOptions::new(None);
+        source("one\ntwo\n").apply(&options, &mut changes);
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        std::fs::write(&targetpath, target.replace("one", "local")).unwrap();
+
+        // both changed the same line, skipping leaves the target alone
+        let updated = source("upstream\ntwo\n");
+        assert!(matches!(
+            updated.apply(&options, &mut changes),
+            ApplyResult::Unchanged
+        ));
+        options.conflict = ConflictStyle::Markers;
+        assert!(matches!(
+            updated.apply(&options, &mut changes),
+            ApplyResult::Changed
+        ));
+        let target = std::fs::read_to_string(&targetpath).unwrap();
+        assert!(target.contains("<<<<<<< ours\nlocal\n"));
+        assert!(target.contains("=======\nupstream\n>>>>>>> theirs\ntwo\n"));
+    }
+
+    #[test]
+    fn test_apply_filters() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        std::fs::write(dots.join("notes.txt"), "").unwrap();
+        let targetpath = tmp_dir.path().join("target.sh");
+        let sourcepath = dots.join("source.sh");
+        let sections = ["foo", "fizz", "bar"]
+            .iter()
+            .map(|name| format!("#... {n} begin\n#... {n} hash x\n{n}\n#... {n} end\n", n = name))
+            .collect::<String>();
+        std::fs::write(
+            &sourcepath,
+            format!("#... all target {}\n{}", targetpath.display(), sections),
+        )
+        .unwrap();
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        source.compile();
+        source.write_to_file();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+
+        let walkoptions = WalkOptions {
+            only: vec![String::from("*.sh")],
+            ..Default::default()
+        };
+        let walked: Vec<_> = walk_config_dir(&dots, &walkoptions)
+            .map(|entry| entry.path().to_path_buf())
+            .collect();
+        assert_eq!(walked, vec![sourcepath.clone()]);
+
+        let mut changes = Vec::new();
+        let mut options = ApplyOptions::new(None);
+        options.only_sections = SectionMatcher::new(&["f*"], &[]).unwrap();
+        options.skip_sections = SectionMatcher::exact(&[String::from("fizz")]);
+        source.apply(&options, &mut changes);
+        let target = DotFile::from_pathbuf(&targetpath).unwrap();
+        assert_eq!(target.section_names(), vec!["foo"]);
+
+        // files without a selected section are left alone
+        options.only_sections = SectionMatcher::exact(&[String::from("other")]);
+        options.skip_sections = SectionMatcher::default();
+        assert!(matches!(
+            source.apply(&options, &mut changes),
+            ApplyResult::Unchanged
+        ));
+        let target = DotFile::from_pathbuf(&targetpath).unwrap();
+        assert_eq!(target.section_names(), vec!["foo"]);
+    }
+
+    #[test]
+    fn test_walk_options() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let dots = tmp_dir.path().join("dots");
+        let linked = tmp_dir.path().join("linked");
+        std::fs::create_dir_all(dots.join("shell/zsh")).unwrap();
+        std::fs::create_dir_all(dots.join("wm")).unwrap();
+        std::fs::create_dir_all(&linked).unwrap();
+        for file in ["top.sh", "shell/bashrc", "shell/zsh/zshrc", "wm/config"] {
+            std::fs::write(dots.join(file), "").unwrap();
+        }
+        std::fs::write(linked.join("inputrc"), "").unwrap();
+        std::os::unix::fs::symlink(&linked, dots.join("linked")).unwrap();
+
+        let walk = |walkoptions: &WalkOptions| {
+            let mut walked: Vec<String> = walk_config_dir(&dots, walkoptions)
+                .map(|entry| {
+                    let path = entry.path().strip_prefix(&dots).unwrap();
+                    path.display().to_string()
+                })
+                .collect();
+            walked.sort();
+            walked
+        };
+        let mut walkoptions = WalkOptions::default();
+        assert_eq!(
+            walk(&walkoptions),
+            vec!["shell/bashrc", "shell/zsh/zshrc", "top.sh", "wm/config"]
+        );
+        walkoptions.exclude = vec![String::from("wm"), String::from("*.sh")];
+        assert_eq!(walk(&walkoptions), vec!["shell/bashrc", "shell/zsh/zshrc"]);
+        walkoptions.max_depth = Some(2);
+        assert_eq!(walk(&walkoptions), vec!["shell/bashrc"]);
+        walkoptions.follow_symlinks = true;
+        assert_eq!(walk(&walkoptions), vec!["linked/inputrc", "shell/bashrc"]);
+    }
+
+    #[test]
+    fn test_compile_stdin() {
+        // stdin has no file name to guess the comment sign from
+        let content = concat!(
+            "-- settings\n--... options begin\n--... options hash x\n",
+            "vim.o.number = true\n--... options end\n"
+        );
+        let mut compfile = DotFile::from_str_with_comment_sign(content, "stdin", "--");
+        assert!(compfile.compile());
+        let output = compfile.to_string();
+        assert!(output.starts_with("-- settings\n--... options begin\n--... options hash "));
+
+        let compiled = DotFile::from_str_with_comment_sign(&output, "stdin", "--");
+        assert_eq!(compiled.section_names(), vec!["options"]);
+        assert!(compiled.is_managed());
+        assert!(!compiled.modified);
+        assert_eq!(compiled.to_string(), output);
+    }
+
+    #[test]
+    fn test_check_args() {
+        let matches = build_app()
+            .try_get_matches_from(["imosid", "check", "--directory", "dots", "--quiet"])
+            .unwrap();
+        let (name, check_matches) = matches.subcommand().unwrap();
+        assert_eq!(name, "check");
+        let directory = check_matches.get_one::<std::path::PathBuf>("directory");
+        assert_eq!(directory, Some(&std::path::PathBuf::from("dots")));
+        assert!(build_app()
+            .try_get_matches_from(["imosid", "check", "--directory"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_update_filter() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let targetpath = tmp_dir.path().join("target.sh");
+        let write_source = |first: &str, second: &str| {
+            std::fs::write(
+                &sourcepath,
+                format!(
+                    "#... first begin\n#... first hash x\n{}\n#... first end\n\
+                     #... second begin\n#... second hash x\n{}\n#... second end\n",
+                    first, second
+                ),
+            )
+            .unwrap();
+            let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+            source.compile();
+            source.write_to_file();
+        };
+        write_source("echo first", "echo second");
+        let source = sourcepath.display();
+        std::fs::write(
+            &targetpath,
+            format!(
+                "#... first begin\n#... first hash x\n#... first source {}\necho first\n\
+                 #... first end\n#... second begin\n#... second hash x\n\
+                 #... second source {}\necho second\n#... second end\n",
+                source, source
+            ),
+        )
+        .unwrap();
+        let mut target = DotFile::from_pathbuf(&targetpath).unwrap();
+        target.compile();
+        target.write_to_file();
+
+        write_source("echo new first", "echo new second");
+        let mut target = DotFile::from_pathbuf(&targetpath).unwrap();
+        let mut lockfile = LockFile::load(tmp_dir.path().join("imosid.lock"));
+        let filter = SectionMatcher::new(&["fir*"], &[]).unwrap();
+        assert!(target.update(&filter, true, &mut lockfile));
+        let content = target.to_string();
+        assert!(content.contains("echo new first"));
+        assert!(content.contains("echo second"));
+        assert!(!content.contains("echo new second"));
+    }
+
+    #[test]
+    fn test_section_args() {
+        let sections = |args: &[&str]| {
+            let matches = build_app().try_get_matches_from(args).unwrap();
+            let (_, sub_matches) = matches.subcommand().unwrap();
+            sub_matches
+                .get_many::<String>("section")
+                .unwrap_or_default()
+                .cloned()
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            sections(&["imosid", "query", "--file", "test.sh", "--section", "a", "--section", "b"]),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            sections(&["imosid", "update", "-f", "test.sh", "-s", "a", "-s", "b*"]),
+            vec!["a", "b*"]
+        );
+        assert_eq!(sections(&["imosid", "delete", "test.sh", "-s", "a"]), vec!["a"]);
+        assert!(sections(&["imosid", "update", "-f", "test.sh"]).is_empty());
+        assert!(build_app()
+            .try_get_matches_from(["imosid", "query", "--file"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_pick_section_matcher() {
+        let query = |args: &[&str]| {
+            let matches = build_app().try_get_matches_from(args).unwrap();
+            matches.subcommand_matches("query").unwrap().clone()
+        };
+        let names = ["first", "second"];
+        let given = query(&["imosid", "query", "--file", "test.sh", "--section", "fir*"]);
+        let matcher = pick_section_matcher(&given, &names).unwrap();
+        assert!(matcher.matches("first"));
+        assert!(!matcher.matches("second"));
+
+        // nothing can be picked without a terminal
+        let omitted = query(&["imosid", "query", "--file", "test.sh"]);
+        assert!(pick_section_matcher(&omitted, &[]).is_none());
+        if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+            assert!(pick_section_matcher(&omitted, &names).is_none());
+        }
+    }
+
+    #[test]
+    fn test_link_intact() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        let sourcepath = tmp_dir.path().join("source.conf");
+        let targetpath = tmp_dir.path().join("target.conf");
+        std::fs::write(&sourcepath, "content\n").unwrap();
+        assert_eq!(link_intact(&sourcepath), None);
+        std::fs::write(
+            tmp_dir.path().join("source.conf.imosid.toml"),
+            format!(
+                "hash = \"x\"\nparent = \"source.conf\"\ntarget = \"{}\"\nlink = true\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        assert_eq!(link_intact(&sourcepath), Some(false));
+        std::os::unix::fs::symlink(sourcepath.canonicalize().unwrap(), &targetpath).unwrap();
+        assert_eq!(link_intact(&sourcepath), Some(true));
+
+        // a broken link is noticed even though the source did not change
+        std::fs::remove_file(&targetpath).unwrap();
+        std::fs::write(&targetpath, "content\n").unwrap();
+        assert_eq!(link_intact(&sourcepath), Some(false));
+    }
+
+    #[test]
+    fn test_strict_pathbuf() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let dots = tmp_dir.path().join("dots");
+        std::fs::create_dir_all(&dots).unwrap();
+        let sourcepath = dots.join("source.sh");
+        let targetpath = tmp_dir.path().join("target.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... foo beginn\n#... bar begin\n#... bar hash x\nb\n\
+                 #... bar end\n",
+                targetpath.display()
+            ),
+        )
+        .unwrap();
+        let error = DotFile::from_pathbuf_strict(&sourcepath).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        assert_eq!(source.diagnostics.len(), 1);
+        source.compile();
+        source.write_to_file();
+
+        let options = ApplyOptions {
+            strict: true,
+            ..Default::default()
+        };
+        apply_config_dir(&dots, &WalkOptions::default(), &options);
+        assert!(!targetpath.exists());
+        apply_config_dir(&dots, &WalkOptions::default(), &ApplyOptions::default());
+        assert!(targetpath.exists());
+    }
+
+    #[test]
+    fn test_section_targets() {
+        let tmp_dir = TempDir::new("imosidtest").unwrap();
+        set_test_home(tmp_dir.path());
+        let sourcepath = tmp_dir.path().join("source.sh");
+        let filetarget = tmp_dir.path().join("file.sh");
+        let sectiontarget = tmp_dir.path().join("section.sh");
+        std::fs::write(
+            &sourcepath,
+            format!(
+                "#... all target {}\n#... foo begin\n#... foo hash x\necho foo\n#... foo end\n\
+                 #... bar begin\n#... bar hash x\n#... bar target {}\necho bar\n#... bar end\n",
+                filetarget.display(),
+                sectiontarget.display()
+            ),
+        )
+        .unwrap();
+        let mut source = DotFile::from_pathbuf(&sourcepath).unwrap();
+        source.compile();
+        source.write_to_file();
+        let source = DotFile::from_pathbuf(&sourcepath).unwrap();
+
+        let mut changes = Vec::new();
+        assert!(matches!(
+            source.apply(&ApplyOptions::new(None), &mut changes),
+            ApplyResult::Changed
+        ));
+        assert_eq!(changes.len(), 2);
+        let filecontent = std::fs::read_to_string(&filetarget).unwrap();
+        assert!(filecontent.contains("echo foo"));
+        assert!(!filecontent.contains("echo bar"));
+        let sectioncontent = std::fs::read_to_string(&sectiontarget).unwrap();
+        assert!(sectioncontent.contains("echo bar"));
+        assert!(!sectioncontent.contains("echo foo"));
+        assert!(!DotFile::from_pathbuf(&sectiontarget).unwrap().modified);
+    }
 }
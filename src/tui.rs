@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Stdout};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use diffy::{create_patch, PatchFormatter};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+
+use crate::dotwalker::{walk_config_dir, WalkOptions};
+use crate::files::{expand_tilde, ApplyOptions, ApplyResult, DotFile};
+use crate::matcher::SectionMatcher;
+use crate::section::Section;
+use crate::status::{get_status, FileStatus};
+
+// what a row of the tree stands for
+enum RowKind {
+    File,
+    Section(String), // sections of comment files, regions of metafiles
+}
+
+struct Row {
+    file: usize, // index into App::files
+    kind: RowKind,
+    status: Option<FileStatus>, // None if there is nothing to compare against
+}
+
+// what the detail pane shows for the selected row
+enum Details {
+    Info,
+    Diff,
+    ConfirmDelete(String),
+}
+
+struct App {
+    dir: PathBuf,
+    walkoptions: WalkOptions,
+    options: ApplyOptions,
+    files: Vec<DotFile>,
+    rows: Vec<Row>,
+    state: ListState,
+    details: Details,
+    message: String,
+    logged: bool, // an action may have logged over the screen
+}
+
+fn status_style(status: Option<FileStatus>) -> (&'static str, Style) {
+    let (text, color) = match status {
+        Some(FileStatus::UpToDate) => ("up to date", Color::Green),
+        Some(FileStatus::NeedsApply) => ("needs apply", Color::Yellow),
+        Some(FileStatus::ModifiedLocally) => ("modified", Color::Red),
+        Some(FileStatus::Unmanaged) => ("unmanaged", Color::DarkGray),
+        Some(FileStatus::MissingTarget) => ("missing target", Color::Blue),
+        Some(FileStatus::ReadonlyTarget) => ("read-only target", Color::Magenta),
+        None => ("no target", Color::DarkGray),
+    };
+    (text, Style::default().fg(color))
+}
+
+// compare a single section against the target it is applied to
+fn section_status(
+    file: &DotFile,
+    section: &Section,
+    targets: &mut HashMap<String, Option<DotFile>>,
+) -> Option<FileStatus> {
+    let named_data = match section {
+        Section::Named(_, named_data) => named_data,
+        Section::Anonymous(_) => return None,
+    };
+    if named_data.hash != named_data.targethash {
+        return Some(FileStatus::ModifiedLocally);
+    }
+    let target = expand_tilde(named_data.target.as_ref().or(file.targetfile.as_ref())?);
+    let targetfile = targets.entry(target.clone()).or_insert_with(|| {
+        if Path::new(&target).is_file() {
+            DotFile::new(&target).ok()
+        } else {
+            None
+        }
+    });
+    match targetfile {
+        None => Some(FileStatus::MissingTarget),
+        Some(targetfile) if targetfile.has_section_hash(&named_data.name, &named_data.hash) => {
+            Some(FileStatus::UpToDate)
+        }
+        Some(_) => Some(FileStatus::NeedsApply),
+    }
+}
+
+// diff from the target to the source, the lines apply would change
+fn diff_lines(title: &str, target: &str, source: &str) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(Span::styled(
+        title.to_string(),
+        Style::default().add_modifier(Modifier::BOLD),
+    ))];
+    if target == source {
+        lines.push(Line::from("no changes"));
+        return lines;
+    }
+    let patch = create_patch(target, source);
+    for line in PatchFormatter::new().fmt_patch(&patch).to_string().lines() {
+        let style = match line.chars().next() {
+            Some('+') => Style::default().fg(Color::Green),
+            Some('-') => Style::default().fg(Color::Red),
+            Some('@') => Style::default().fg(Color::Cyan),
+            _ => Style::default(),
+        };
+        lines.push(Line::from(Span::styled(line.to_string(), style)));
+    }
+    lines
+}
+
+fn section_content(file: &DotFile, name: &str) -> Option<String> {
+    match file.get_section(name)? {
+        Section::Named(data, _) => Some(data.content),
+        Section::Anonymous(_) => None,
+    }
+}
+
+impl App {
+    fn new(dir: &Path, walkoptions: WalkOptions, options: ApplyOptions) -> App {
+        let mut app = App {
+            dir: dir.to_path_buf(),
+            walkoptions,
+            options,
+            files: Vec::new(),
+            rows: Vec::new(),
+            state: ListState::default(),
+            details: Details::Info,
+            message: String::new(),
+            logged: false,
+        };
+        app.reload();
+        app
+    }
+
+    // read all managed files again, keeping the selection where possible
+    fn reload(&mut self) {
+        let selected = self.state.selected().unwrap_or(0);
+        self.files = walk_config_dir(&self.dir, &self.walkoptions)
+            .filter_map(|entry| DotFile::from_pathbuf(&entry.path().to_path_buf()).ok())
+            .filter(|file| file.is_managed())
+            .collect();
+        self.files.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        let mut targets = HashMap::new();
+        self.rows.clear();
+        for (index, file) in self.files.iter().enumerate() {
+            self.rows.push(Row {
+                file: index,
+                kind: RowKind::File,
+                status: get_status(file, &self.options),
+            });
+            if let Some(metafile) = &file.metafile {
+                for region in &metafile.regions {
+                    self.rows.push(Row {
+                        file: index,
+                        kind: RowKind::Section(region.name.clone()),
+                        status: if region.modified {
+                            Some(FileStatus::ModifiedLocally)
+                        } else {
+                            get_status(file, &self.options)
+                        },
+                    });
+                }
+                continue;
+            }
+            for section in &file.sections {
+                if let Section::Named(_, named_data) = section {
+                    self.rows.push(Row {
+                        file: index,
+                        kind: RowKind::Section(named_data.name.clone()),
+                        status: section_status(file, section, &mut targets),
+                    });
+                }
+            }
+        }
+        if self.rows.is_empty() {
+            self.state.select(None);
+        } else {
+            self.state.select(Some(selected.min(self.rows.len() - 1)));
+        }
+    }
+
+    fn selected(&self) -> Option<&Row> {
+        self.rows.get(self.state.selected()?)
+    }
+
+    fn select(&mut self, offset: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let current = self.state.selected().unwrap_or(0) as isize;
+        let next = (current + offset).clamp(0, self.rows.len() as isize - 1);
+        self.state.select(Some(next as usize));
+        self.details = Details::Info;
+    }
+
+    fn display_name(&self, file: &DotFile) -> String {
+        let path = PathBuf::from(&file.filename);
+        let dir = self.dir.canonicalize().unwrap_or_else(|_| self.dir.clone());
+        path.strip_prefix(&dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string()
+    }
+
+    fn apply(&mut self) {
+        let (index, section) = match self.selected() {
+            Some(row) => match &row.kind {
+                RowKind::File => (row.file, None),
+                RowKind::Section(name) => (row.file, Some(name.clone())),
+            },
+            None => return,
+        };
+        if let Some(name) = &section {
+            self.options.only_sections =
+                SectionMatcher::new(&[name.as_str()], &[]).unwrap_or_default();
+        }
+        let mut changes = Vec::new();
+        let result = self.files[index].apply(&self.options, &mut changes);
+        self.options.only_sections = SectionMatcher::default();
+        self.logged = true;
+        let name = section.unwrap_or_else(|| self.display_name(&self.files[index]));
+        self.message = match result {
+            ApplyResult::Changed => format!("applied {} to {}", name, changes.join(", ")),
+            ApplyResult::Unchanged => format!("{} is already applied", name),
+            ApplyResult::Error => format!("could not apply {}", name),
+        };
+        self.reload();
+    }
+
+    fn compile(&mut self) {
+        let index = match self.selected() {
+            Some(row) => row.file,
+            None => return,
+        };
+        let name = self.display_name(&self.files[index]);
+        let file = &mut self.files[index];
+        self.logged = true;
+        self.message = if file.compile() {
+            file.write_to_file();
+            format!("compiled {}", name)
+        } else {
+            format!("{} already compiled, no change", name)
+        };
+        self.reload();
+    }
+
+    fn delete(&mut self, name: &str) {
+        let index = match self.selected() {
+            Some(row) => row.file,
+            None => return,
+        };
+        let file = &mut self.files[index];
+        self.logged = true;
+        self.message = if file.deletesection(name) {
+            file.write_to_file();
+            format!("deleted section {}", name)
+        } else {
+            format!("could not delete section {}", name)
+        };
+        self.details = Details::Info;
+        self.reload();
+    }
+
+    fn info_lines(&self, row: &Row) -> Vec<Line<'static>> {
+        let file = &self.files[row.file];
+        let (status, style) = status_style(row.status);
+        let mut lines = vec![
+            Line::from(Span::styled(
+                self.display_name(file),
+                Style::default().add_modifier(Modifier::BOLD),
+            )),
+            Line::from(Span::styled(status, style)),
+            Line::from(""),
+        ];
+        let info = match &row.kind {
+            RowKind::File => file.pretty_info(),
+            RowKind::Section(name) => match &file.metafile {
+                Some(metafile) => metafile
+                    .region_content(name)
+                    .unwrap_or_else(|| format!("region {} not found", name)),
+                None => file
+                    .get_section(name)
+                    .map(|section| match &section {
+                        Section::Named(data, _) => format!(
+                            "{}\n\n{}",
+                            section.pretty_info().unwrap_or_default(),
+                            data.content
+                        ),
+                        Section::Anonymous(_) => String::new(),
+                    })
+                    .unwrap_or_default(),
+            },
+        };
+        lines.extend(info.lines().map(|line| Line::from(line.to_string())));
+        lines
+    }
+
+    fn diff(&self, row: &Row) -> Vec<Line<'static>> {
+        let file = &self.files[row.file];
+        if let Some(metafile) = &file.metafile {
+            let target = match &file.targetfile {
+                Some(target) => expand_tilde(target),
+                None => return vec![Line::from("file has no target")],
+            };
+            let source = String::from_utf8_lossy(&metafile.content).to_string();
+            let targetcontent = fs::read_to_string(&target).unwrap_or_default();
+            return diff_lines(&target, &targetcontent, &source);
+        }
+        let names = match &row.kind {
+            RowKind::File => file.section_names().into_iter().map(String::from).collect(),
+            RowKind::Section(name) => vec![name.clone()],
+        };
+        let mut lines = Vec::new();
+        for name in names {
+            let target = match file.get_section_target(&name) {
+                Some(target) => expand_tilde(&target),
+                None => {
+                    lines.push(Line::from(format!("section {} has no target", name)));
+                    continue;
+                }
+            };
+            let targetcontent = DotFile::new(&target)
+                .ok()
+                .and_then(|targetfile| section_content(&targetfile, &name))
+                .unwrap_or_default();
+            let source = section_content(file, &name).unwrap_or_default();
+            lines.extend(diff_lines(
+                &format!("{} -> {}", name, target),
+                &targetcontent,
+                &source,
+            ));
+            lines.push(Line::from(""));
+        }
+        lines
+    }
+
+    fn draw<B: Backend>(&mut self, frame: &mut Frame<B>) {
+        let outer = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(frame.size());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+            .split(outer[0]);
+
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let (status, style) = status_style(row.status);
+                let name = match &row.kind {
+                    RowKind::File => Span::styled(
+                        self.display_name(&self.files[row.file]),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    RowKind::Section(name) => Span::raw(format!("  {}", name)),
+                };
+                ListItem::new(Line::from(vec![
+                    name,
+                    Span::raw(" "),
+                    Span::styled(status, style),
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.dir.display().to_string()),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, panes[0], &mut self.state);
+
+        let (title, lines) = match (self.selected(), &self.details) {
+            (None, _) => ("info", vec![Line::from("no managed files found")]),
+            (Some(row), Details::Info) => ("info", self.info_lines(row)),
+            (Some(row), Details::Diff) => ("diff", self.diff(row)),
+            (Some(_), Details::ConfirmDelete(name)) => (
+                "delete",
+                vec![Line::from(format!("delete section {}? [y/n]", name))],
+            ),
+        };
+        let details = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, panes[1]);
+
+        let status = if self.message.is_empty() {
+            "j/k move  a apply  c compile  d diff  x delete  r reload  q quit"
+        } else {
+            self.message.as_str()
+        };
+        frame.render_widget(Paragraph::new(status.to_string()), outer[1]);
+    }
+
+    // return false once the user wants to quit
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        if let Details::ConfirmDelete(name) = &self.details {
+            let name = name.clone();
+            match code {
+                KeyCode::Char('y') => self.delete(&name),
+                _ => self.details = Details::Info,
+            }
+            return true;
+        }
+        self.message.clear();
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Esc => match self.details {
+                Details::Info => return false,
+                _ => self.details = Details::Info,
+            },
+            KeyCode::Char('j') | KeyCode::Down => self.select(1),
+            KeyCode::Char('k') | KeyCode::Up => self.select(-1),
+            KeyCode::Char('g') | KeyCode::Home => self.select(-(self.rows.len() as isize)),
+            KeyCode::Char('G') | KeyCode::End => self.select(self.rows.len() as isize),
+            KeyCode::Char('a') => self.apply(),
+            KeyCode::Char('c') => self.compile(),
+            KeyCode::Char('d') => self.details = Details::Diff,
+            KeyCode::Enter | KeyCode::Char('i') => self.details = Details::Info,
+            KeyCode::Char('r') => self.reload(),
+            KeyCode::Char('x') | KeyCode::Delete => self.confirm_delete(),
+            _ => {}
+        }
+        true
+    }
+
+    fn confirm_delete(&mut self) {
+        let name = match self.selected() {
+            Some(Row {
+                file,
+                kind: RowKind::Section(name),
+                ..
+            }) if self.files[*file].metafile.is_none() => name.clone(),
+            Some(_) => {
+                self.message = String::from("only sections of files with comments can be deleted");
+                return;
+            }
+            None => return,
+        };
+        self.details = Details::ConfirmDelete(name);
+    }
+
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if !self.handle_key(key.code) {
+                    return Ok(());
+                }
+                if self.logged {
+                    self.logged = false;
+                    terminal.clear()?;
+                }
+            }
+        }
+    }
+}
+
+/// browse the managed files below dir and their sections
+pub fn run_tui(dir: &Path, walkoptions: WalkOptions, options: ApplyOptions) -> io::Result<()> {
+    let mut app = App::new(dir, walkoptions, options);
+    // info is rendered by the tui, escape codes would show up as text
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    colored::control::set_override(false);
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let result = app.run(&mut terminal);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    colored::control::set_override(colorize);
+    result
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use colored::Colorize;
+
+use crate::files::{expand_tilde, ApplyOptions, DotFile};
+use crate::section::Section;
+
+// ways a target can differ from what its source would apply
+pub enum Drift {
+    MissingTarget,
+    MissingSection(String),
+    ChangedSection(String),
+    ChangedContent, // whole file targets of metafiles
+}
+
+impl Drift {
+    pub fn describe(&self) -> String {
+        match self {
+            Drift::MissingTarget => "target is missing".red().to_string(),
+            Drift::MissingSection(name) => format!("section {} is missing", name.bold())
+                .red()
+                .to_string(),
+            Drift::ChangedSection(name) => format!("section {} differs from source", name.bold())
+                .yellow()
+                .to_string(),
+            Drift::ChangedContent => "content differs from source".yellow().to_string(),
+        }
+    }
+}
+
+/// compare every target of file with what applying file would write
+/// return the drift of every target which does not match
+pub fn verify_file(file: &DotFile, options: &ApplyOptions) -> Vec<(String, Drift)> {
+    let mut drift = Vec::new();
+    if !file.is_managed() || !options.facts.matches_profile(&file.profile) {
+        return drift;
+    }
+
+    if let Some(metafile) = &file.metafile {
+        let target = match &file.targetfile {
+            Some(target) => expand_tilde(target),
+            None => return drift,
+        };
+        match fs::read(&target) {
+            Ok(content) if content == metafile.content => {}
+            Ok(_) => drift.push((target, Drift::ChangedContent)),
+            Err(_) => drift.push((target, Drift::MissingTarget)),
+        }
+        return drift;
+    }
+
+    let mut targets: HashMap<String, Option<DotFile>> = HashMap::new();
+    for section in &file.sections {
+        if !options.selects(section) {
+            continue;
+        }
+        // targets get rendered sections, their hash is what ends up there
+        let (target, named_data) = match (section, section.render()) {
+            (Section::Named(_, named_data), Some(Section::Named(_, rendered))) => {
+                match named_data.target.as_ref().or(file.targetfile.as_ref()) {
+                    Some(target) => (expand_tilde(target), rendered),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let targetfile = targets.entry(target.clone()).or_insert_with(|| {
+            if Path::new(&target).is_file() {
+                DotFile::new(&target).ok()
+            } else {
+                None
+            }
+        });
+        let targetfile = match targetfile {
+            Some(targetfile) => targetfile,
+            None => {
+                if !drift
+                    .iter()
+                    .any(|(path, kind)| path == &target && matches!(kind, Drift::MissingTarget))
+                {
+                    drift.push((target, Drift::MissingTarget));
+                }
+                continue;
+            }
+        };
+        if targetfile.get_section(&named_data.name).is_none() {
+            drift.push((target, Drift::MissingSection(named_data.name)));
+        } else if !targetfile.has_section_hash(&named_data.name, &named_data.hash) {
+            drift.push((target, Drift::ChangedSection(named_data.name)));
+        }
+    }
+    drift
+}